@@ -1 +1 @@
-cat test_input.txt | cargo tarpaulin $@
+cargo tarpaulin $@
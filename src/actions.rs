@@ -1,115 +1,342 @@
-use std::io::{Write, stdout};
-use state::{AppState};
+use state::{AppState, Mode, Angle, Settings};
 use command::Command;
 use complex::Complex;
+use complex::dialect::Dialect;
+use history::{History, describe};
+use event::Event;
+use error::{CalcError, CalcResult};
+use registry;
+use repl::eval_cmd;
+use script::Condition;
+use value::Value;
+use format::{self, FormatOption};
 
 macro_rules! num_fst {
-    ($state:expr) => (Err(($state, "You must insert a number first".into())))
-}
-
-pub fn print_help(state: AppState) -> Result<AppState, (AppState, String)> {
-    println!("help - Show all available operations and commands");
-    println!("exit - Quits the program");
-    println!("clear - Calculator is restarted");
-    println!("addition - Performs addition to the current number");
-    println!("subtraction - Performs subtraction to the current number");
-    println!("multiplication - Performs multiplication to the current number");
-    println!("real - Returns the real part of the complex number");
-    println!("power - Calculate the power. Usage: power <arg>");
-    println!("root - Calculate the root. Usage: root <arg>");
-    {
-        let _ = stdout().flush();
+    ($state:expr) => (Err(($state, CalcError::MissingOperand("You must insert a number first".into()))))
+}
+
+macro_rules! single {
+    ($state:expr, $name:expr) => {{
+        let message = format!("{} needs a single number, not a {}", $name, $state.number.as_ref().map_or("number", |v| v.kind()));
+        Err(($state, CalcError::Domain(message)))
+    }}
+}
+
+pub fn print_help(state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    match registry::help_in(None, state.settings.locale) {
+        Ok(help) => {
+            out.push(Event::Message(help));
+            Ok(state)
+        },
+        Err(e) => Err((state, e))
     }
-    Ok(state)
 }
 
-pub fn clear() -> Result<AppState, (AppState, String)> {
+pub fn print_command_help(topic: &str, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    match registry::help_in(Some(topic), state.settings.locale) {
+        Ok(help) => {
+            out.push(Event::Message(help));
+            Ok(state)
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn clear() -> CalcResult {
     Ok(AppState::default())
 }
 
-pub fn print_real(state: AppState) -> Result<AppState, (AppState, String)> {
+pub fn print_real(state: AppState, out: &mut Vec<Event>) -> CalcResult {
     match state {
-        AppState { number: Some(cplx), pending_op: op, log: _ } => {
-            println!("{}", cplx.real());
-            Ok(AppState::new(Some(cplx), op, false))
+        AppState { number: Some(Value::Scalar(cplx)), pending_op: op, settings, .. } => {
+            out.push(Event::Real(cplx.real()));
+            Ok(AppState::new(Some(cplx.into()), op, false).with_settings(settings))
         },
-        s @ AppState { number: None, pending_op: _, log: _ } => num_fst!(s),
+        s @ AppState { number: None, .. } => num_fst!(s),
+        s => single!(s, "real"),
     }
 }
 
-pub fn print_imaginary(state: AppState) -> Result<AppState, (AppState, String)> {
+pub fn print_imaginary(state: AppState, out: &mut Vec<Event>) -> CalcResult {
     match state {
-        AppState { number: Some(cplx), pending_op: op, log: _ } => {
-            println!("{}", cplx.imaginary());
-            Ok(AppState::new(Some(cplx), op, false))
+        AppState { number: Some(Value::Scalar(cplx)), pending_op: op, settings, .. } => {
+            out.push(Event::Imaginary(cplx.imaginary()));
+            Ok(AppState::new(Some(cplx.into()), op, false).with_settings(settings))
         },
-        s @ AppState { number: None, pending_op: _, log: _ } => num_fst!(s),
+        s @ AppState { number: None, .. } => num_fst!(s),
+        s => single!(s, "imaginary"),
     }
 }
 
-pub fn add_action(state: AppState, action: Command) -> Result<AppState, (AppState, String)> {
+pub fn print_argument(state: AppState, out: &mut Vec<Event>) -> CalcResult {
     match state {
-        AppState { number: Some(cplx), pending_op: _, log} => {
-            Ok(AppState::new(Some(cplx), Some(action), log))
+        AppState { number: Some(Value::Scalar(cplx)), pending_op: op, settings, .. } => {
+            out.push(Event::Argument(settings.angle.from_radians(cplx.argument())));
+            Ok(AppState::new(Some(cplx.into()), op, false).with_settings(settings))
         },
-        s @ AppState { number: None, pending_op: _, log: _ } => num_fst!(s),
+        s @ AppState { number: None, .. } => num_fst!(s),
+        s => single!(s, "argument"),
     }
 }
 
-pub fn add_number(num: Complex, state: AppState) -> Result<AppState, (AppState, String)> {
+pub fn print_polar(state: AppState, out: &mut Vec<Event>) -> CalcResult {
     match state {
-        s @ AppState { number: None, pending_op: Some(_), log: _ } => num_fst!(s),
-        AppState { number: Some(number), pending_op: Some(Command::Addition), log: _ } => {
-            let new_num = number + num;
-            println!("{}", new_num);
-            Ok(AppState::new(Some(new_num), None, true))
+        AppState { number: Some(Value::Scalar(cplx)), pending_op: op, settings, .. } => {
+            out.push(Event::Polar(cplx.modulus(), settings.angle.from_radians(cplx.argument()), settings.angle));
+            Ok(AppState::new(Some(cplx.into()), op, false).with_settings(settings))
         },
-        AppState { number: Some(number), pending_op: Some(Command::Subtraction), log: _ } => {
-            let new_num = number + num;
-            println!("{}", new_num);
-            Ok(AppState::new(Some(new_num), None, true))
+        s @ AppState { number: None, .. } => num_fst!(s),
+        s => single!(s, "polar"),
+    }
+}
+
+pub fn phasor(r: f64, theta: f64, unit: Option<Angle>, settings: &Settings) -> Complex {
+    Complex::from_polar(r, unit.unwrap_or(settings.angle).to_radians(theta))
+}
+
+// Reads the number as an angle in the given unit
+pub fn trig(f: fn(&Complex) -> Complex, angle: Angle, cplx: &Complex) -> Complex {
+    f(&Complex::new(angle.to_radians(cplx.real()), angle.to_radians(cplx.imaginary())))
+}
+
+pub fn do_trig(f: fn(&Complex) -> Complex, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let result = match state.number {
+        Some(ref value) => value.map(|cplx| Ok(trig(f, state.settings.angle, cplx))),
+        None => return num_fst!(state)
+    };
+    match result {
+        Ok(new_num) => {
+            out.push(Event::Result(new_num.clone()));
+            Ok(AppState::new(Some(new_num), state.pending_op, true).with_settings(state.settings))
         },
-        AppState { number: Some(number), pending_op: Some(Command::Multiplication), log: _ } => {
-            let new_num = number * num;
-            println!("{}", new_num);
-            Ok(AppState::new(Some(new_num), None, false))
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn set_angle(angle: Angle, mut state: AppState) -> CalcResult {
+    state.settings.angle = angle;
+    state.log = false;
+    Ok(state)
+}
+
+pub fn set_format(options: &[FormatOption], mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    if options.is_empty() {
+        out.push(Event::Message(format!("format {}", format::describe(&state.settings))));
+    }
+    for option in options {
+        option.apply(&mut state.settings);
+    }
+    state.log = false;
+    Ok(state)
+}
+
+pub fn copy_as(dialect: Dialect, mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    match state.result().map(|value| format::in_dialect(value, dialect)) {
+        Some(text) => {
+            out.push(Event::Message(text));
+            state.log = false;
+            Ok(state)
         },
-        AppState { number: _, pending_op: None, log: _ } => Ok(AppState::new(Some(num), None, true)),
-        s => Ok(s)
+        None => num_fst!(state)
     }
 }
 
-pub fn do_power(num: f64, state: AppState) -> Result<AppState, (AppState, String)> {
+pub fn add_action(state: AppState, action: Command) -> CalcResult {
     match state {
-        AppState { number: Some(cplx), pending_op: op, log: _ } => {
-            let new_num = cplx.power(num);
-            println!("{}", new_num);
-            Ok(AppState::new(Some(new_num), op, true))
+        AppState { number: Some(cplx), log, settings, .. } => {
+            Ok(AppState::new(Some(cplx), Some(action), log).with_settings(settings))
         },
-        s @ AppState { number: None, pending_op: _, log: _ } => num_fst!(s)
+        s @ AppState { number: None, .. } => num_fst!(s),
     }
 }
 
-pub fn do_root(num: f64, state: AppState) -> Result<AppState, (AppState, String)> {
-    match state {
-        AppState { number: Some(cplx), pending_op: op, log: _ } => {
-            let new_num = cplx.root(num);
-            println!("{}", new_num);
-            Ok(AppState::new(Some(new_num), op, true))
+pub fn divide(a: &Complex, b: &Complex) -> Result<Complex, CalcError> {
+    if *b == Complex::new(0.0, 0.0) {
+        Err(CalcError::Domain("Division by zero".into()))
+    } else {
+        Ok(a.clone() / b.clone())
+    }
+}
+
+pub fn add_number(num: Value, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let (result, log) = match state {
+        s @ AppState { number: None, pending_op: Some(_), .. } => return num_fst!(s),
+        AppState { number: Some(ref number), pending_op: Some(Command::Addition), .. } => {
+            (number.broadcast(&num, |a, b| Ok(a.clone() + b.clone())), true)
+        },
+        AppState { number: Some(ref number), pending_op: Some(Command::Subtraction), .. } => {
+            (number.broadcast(&num, |a, b| Ok(a.clone() + b.clone())), true)
+        },
+        AppState { number: Some(ref number), pending_op: Some(Command::Multiplication), .. } => {
+            (number.broadcast(&num, |a, b| Ok(a.clone() * b.clone())), false)
+        },
+        AppState { number: Some(ref number), pending_op: Some(Command::Division), .. } => {
+            (number.broadcast(&num, divide), true)
+        },
+        AppState { number: _, pending_op: None, settings, .. } => return Ok(AppState::new(Some(num), None, true).with_settings(settings)),
+        s => return Ok(s)
+    };
+    match result {
+        Ok(new_num) => {
+            out.push(Event::Result(new_num.clone()));
+            Ok(AppState::new(Some(new_num), None, log).with_settings(state.settings))
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn do_power(num: f64, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let result = match state.number {
+        Some(ref value) => value.map(|cplx| Ok(cplx.power(num))),
+        None => return num_fst!(state)
+    };
+    match result {
+        Ok(new_num) => {
+            out.push(Event::Result(new_num.clone()));
+            Ok(AppState::new(Some(new_num), state.pending_op, true).with_settings(state.settings))
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn do_root(num: f64, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let result = match state.number {
+        Some(ref value) => value.map(|cplx| Ok(cplx.root(num))),
+        None => return num_fst!(state)
+    };
+    match result {
+        Ok(new_num) => {
+            out.push(Event::Result(new_num.clone()));
+            Ok(AppState::new(Some(new_num), state.pending_op, true).with_settings(state.settings))
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn print_history(history: &History, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let mut lines = Vec::new();
+    for (i, past) in history.past().iter().enumerate() {
+        lines.push(format!("  {}: {}", i + 1, describe(past)));
+    }
+    lines.push(format!("> {}: {}", history.past().len() + 1, describe(&state)));
+    for (i, next) in history.future().iter().rev().enumerate() {
+        lines.push(format!("  {}: {}", history.past().len() + i + 2, describe(next)));
+    }
+    out.push(Event::Message(lines.join("\n")));
+    Ok(AppState { log: false, ..state })
+}
+
+pub fn set_mode(mode: Mode, state: AppState) -> CalcResult {
+    match (mode, state) {
+        (Mode::Rpn, s @ AppState { mode: Mode::Rpn, .. }) |
+        (Mode::Algebraic, s @ AppState { mode: Mode::Algebraic, .. }) => Ok(s),
+        (Mode::Rpn, AppState { number, settings, .. }) => {
+            Ok(AppState::rpn(number.into_iter().collect(), false).with_settings(settings))
+        },
+        (Mode::Algebraic, AppState { mut stack, settings, .. }) => {
+            Ok(AppState::new(stack.pop(), None, false).with_settings(settings))
+        },
+    }
+}
+
+pub fn print_state(state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    out.push(Event::Message(describe(&state)));
+    Ok(state)
+}
+
+// Runs commands on a scratch algebraic state holding only num, so any mode can reuse them
+fn run_on(num: &Complex, cmds: &[Command]) -> Result<AppState, CalcError> {
+    let mut scratch = AppState::new(Some(num.clone().into()), None, false);
+    for cmd in cmds {
+        scratch = eval_cmd(scratch, cmd.clone(), &mut Vec::new()).map_err(|(_, e)| e)?;
+    }
+    Ok(scratch)
+}
+
+fn elements<'a>(name: &str, value: &'a Value) -> Result<Vec<&'a Complex>, CalcError> {
+    match *value {
+        Value::Scalar(_) | Value::List(_) => Ok(value.numbers()),
+        _ => Err(CalcError::Domain(format!("{} needs a list, not a {}", name, value.kind())))
+    }
+}
+
+fn replace_result(state: AppState, value: Result<Value, CalcError>, out: &mut Vec<Event>) -> CalcResult {
+    match value {
+        Ok(value) => {
+            out.push(Event::Result(value.clone()));
+            let mut state = state;
+            match state.mode {
+                Mode::Algebraic => state.number = Some(value),
+                Mode::Rpn => {
+                    state.stack.pop();
+                    state.stack.push(value);
+                }
+            }
+            state.log = true;
+            Ok(state)
         },
-        s @ AppState { number: None, pending_op: _, log: _ } => num_fst!(s)
+        Err(e) => Err((state, e))
     }
 }
 
+pub fn map_values(cmds: &[Command], state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let mapped = match state.result() {
+        Some(value) => value.map(|num| match run_on(num, cmds)? {
+            AppState { number: Some(Value::Scalar(result)), pending_op: None, .. } => Ok(result),
+            _ => Err(CalcError::Domain("map must turn every element into a single number".into()))
+        }),
+        None => return num_fst!(state)
+    };
+    replace_result(state, mapped, out)
+}
+
+pub fn filter_values(condition: &Condition, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let filtered = match state.result() {
+        Some(value) => elements("filter", value).and_then(|items| {
+            let mut kept = Vec::new();
+            for num in items {
+                if condition.evaluate(&AppState::new(Some(num.clone().into()), None, false))? {
+                    kept.push(num.clone());
+                }
+            }
+            Ok(Value::List(kept))
+        }),
+        None => return num_fst!(state)
+    };
+    replace_result(state, filtered, out)
+}
+
+pub fn reduce_values(op: &Command, state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let reduced = match state.result() {
+        Some(value) => elements("reduce", value).and_then(|items| {
+            let mut items = items.into_iter();
+            let mut acc = match items.next() {
+                Some(first) => first.clone(),
+                None => return Err(CalcError::Domain("Cannot reduce an empty list".into()))
+            };
+            for num in items {
+                acc = match run_on(&acc, &[op.clone(), Command::Number(num.clone())])?.number {
+                    Some(Value::Scalar(result)) => result,
+                    _ => unreachable!()
+                };
+            }
+            Ok(Value::Scalar(acc))
+        }),
+        None => return num_fst!(state)
+    };
+    replace_result(state, reduced, out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use state::{Settings, Angle};
 
     #[test]
     fn test_help_command__029() {
         let ini_state = AppState::default();
 
-        assert_eq!(Ok(AppState::default()), print_help(ini_state));
+        assert_eq!(Ok(AppState::default()), print_help(ini_state, &mut Vec::new()));
     }
 
     #[test]
@@ -120,40 +347,40 @@ mod tests {
     #[test]
     fn test_print_real__031__032() {
         {
-            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0)), None, true);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), None, false));
+            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true);
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false));
 
-            assert_eq!(expected, print_real(ini_state));
+            assert_eq!(expected, print_real(ini_state, &mut Vec::new()));
         }
         {
             let ini_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
-            assert_eq!(expected, print_real(ini_state));
+            assert_eq!(expected, print_real(ini_state, &mut Vec::new()));
         }
     }
 
     #[test]
     fn test_print_imaginary__033__034() {
         {
-            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0)), None, true);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), None, false));
+            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true);
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false));
 
-            assert_eq!(expected, print_imaginary(ini_state));
+            assert_eq!(expected, print_imaginary(ini_state, &mut Vec::new()));
         }
         {
             let ini_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
-            assert_eq!(expected, print_imaginary(ini_state));
+            assert_eq!(expected, print_imaginary(ini_state, &mut Vec::new()));
         }
     }
 
     #[test]
     fn test_add_action__035__036() {
         {
-            let input_state = AppState::new(Some(Complex::new(1.0, 0.0)), None, true);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), Some(Command::Addition), true));
+            let input_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true);
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), Some(Command::Addition), true));
 
             let output = add_action(input_state, Command::Addition);
 
@@ -161,7 +388,7 @@ mod tests {
         }
         {
             let input_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
             let output = add_action(input_state, Command::Addition);
 
@@ -174,44 +401,110 @@ mod tests {
         {
             let input_num = Complex::new(1.0, 1.0);
             let input_state = AppState::new(None, Some(Command::Addition), false);
-            let expected = Err((AppState::new(None, Some(Command::Addition), false), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, Some(Command::Addition), false), CalcError::MissingOperand("You must insert a number first".into())));
 
-            assert_eq!(expected, add_number(input_num, input_state));
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
         }
         {
             let input_num = Complex::new(1.0, 1.0);
-            let input_state = AppState::new(Some(Complex::new(0.0, 0.0)), Some(Command::Addition), false);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 1.0)), None, true));
+            let input_state = AppState::new(Some(Complex::new(0.0, 0.0).into()), Some(Command::Addition), false);
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 1.0).into()), None, true));
 
-            assert_eq!(expected, add_number(input_num, input_state));
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
         }
         {
             let input_num = Complex::new(1.0, 1.0);
-            let input_state = AppState::new(Some(Complex::new(1.0, 1.0)), Some(Command::Subtraction), false);
-            let expected = Ok(AppState::new(Some(Complex::new(0.0, 0.0)), None, true));
+            let input_state = AppState::new(Some(Complex::new(1.0, 1.0).into()), Some(Command::Subtraction), false);
+            let expected = Ok(AppState::new(Some(Complex::new(0.0, 0.0).into()), None, true));
 
-            assert_eq!(expected, add_number(input_num, input_state));
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
         }
         {
             let input_num = Complex::new(1.0, 1.0);
-            let input_state = AppState::new(Some(Complex::new(1.0, 1.0)), Some(Command::Multiplication), false);
-            let expected = Ok(AppState::new(Some(Complex::new(0.0, 0.0)), None, true));
+            let input_state = AppState::new(Some(Complex::new(1.0, 1.0).into()), Some(Command::Multiplication), false);
+            let expected = Ok(AppState::new(Some(Complex::new(0.0, 0.0).into()), None, true));
 
-            assert_eq!(expected, add_number(input_num, input_state));
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
         }
         {
             let input_num = Complex::new(1.0, 0.0);
             let input_state = AppState::new(None, None, false);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), None, true));
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true));
 
-            assert_eq!(expected, add_number(input_num, input_state));
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
         }
         {
             let input_num = Complex::new(1.0, 0.0);
-            let input_state = AppState::new(Some(Complex::new(0.0, 0.0)), Some(Command::Help), false);
-            let expected = Ok(AppState::new(Some(Complex::new(0.0, 0.0)), Some(Command::Help), false));
+            let input_state = AppState::new(Some(Complex::new(0.0, 0.0).into()), Some(Command::Help), false);
+            let expected = Ok(AppState::new(Some(Complex::new(0.0, 0.0).into()), Some(Command::Help), false));
+
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_add_number_division__127__128() {
+        {
+            let input_num = Complex::new(1.0, 1.0);
+            let input_state = AppState::new(Some(Complex::new(1.0, 3.0).into()), Some(Command::Division), false);
+            let expected = Ok(AppState::new(Some(Complex::new(2.0, 1.0).into()), None, true));
+
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
+        }
+        {
+            let input_num = Complex::new(0.0, 0.0);
+            let input_state = AppState::new(Some(Complex::new(1.0, 3.0).into()), Some(Command::Division), false);
+            let expected = Err((AppState::new(Some(Complex::new(1.0, 3.0).into()), Some(Command::Division), false), CalcError::Domain("Division by zero".into())));
+
+            assert_eq!(expected, add_number(input_num.into(), input_state, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_print_argument__156__157() {
+        {
+            let settings = Settings::new(3, Angle::Degrees);
+            let ini_state = AppState::new(Some(Complex::new(0.0, 1.0).into()), None, true).with_settings(settings);
+            let expected = Ok(AppState::new(Some(Complex::new(0.0, 1.0).into()), None, false).with_settings(settings));
+
+            assert_eq!(expected, print_argument(ini_state, &mut Vec::new()));
+        }
+        {
+            let ini_state = AppState::new(None, None, true);
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
+
+            assert_eq!(expected, print_argument(ini_state, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_settings_are_preserved__158() {
+        let settings = Settings::new(6, Angle::Degrees);
+        let input_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), Some(Command::Addition), false).with_settings(settings);
+        let expected = Ok(AppState::new(Some(Complex::new(2.0, 0.0).into()), None, true).with_settings(settings));
+
+        assert_eq!(expected, add_number(Complex::new(1.0, 0.0).into(), input_state, &mut Vec::new()));
+    }
 
-            assert_eq!(expected, add_number(input_num, input_state));
+    #[test]
+    fn test_set_mode__110__111__112() {
+        {
+            let input_state = AppState::new(Some(Complex::new(1.0, 1.0).into()), Some(Command::Addition), true);
+            let expected = Ok(AppState::rpn(vec![Complex::new(1.0, 1.0).into()], false));
+
+            assert_eq!(expected, set_mode(Mode::Rpn, input_state));
+        }
+        {
+            let input_state = AppState::rpn(vec![Complex::new(1.0, 0.0).into(), Complex::new(2.0, 0.0).into()], false);
+            let expected = Ok(AppState::new(Some(Complex::new(2.0, 0.0).into()), None, false));
+
+            assert_eq!(expected, set_mode(Mode::Algebraic, input_state));
+        }
+        {
+            let input_state = AppState::rpn(vec![Complex::new(1.0, 0.0).into()], true);
+            let expected = Ok(AppState::rpn(vec![Complex::new(1.0, 0.0).into()], true));
+
+            assert_eq!(expected, set_mode(Mode::Rpn, input_state));
         }
     }
 
@@ -219,17 +512,17 @@ mod tests {
     fn test_power__043__044() {
         {
             let input_num = 2.0;
-            let input_state = AppState::new(Some(Complex::new(2.0, 2.0)), None, false);
-            let expected = Ok(AppState::new(Some(Complex::new(0.0000000000000004898587196589414, 8.000000000000002)), None, true));
+            let input_state = AppState::new(Some(Complex::new(2.0, 2.0).into()), None, false);
+            let expected = Ok(AppState::new(Some(Complex::new(0.0000000000000004898587196589414, 8.000000000000002).into()), None, true));
 
-            assert_eq!(expected, do_power(input_num, input_state));
+            assert_eq!(expected, do_power(input_num, input_state, &mut Vec::new()));
         }
         {
             let input_num = 2.0;
             let input_state = AppState::new(None, None, false);
-            let expected = Err((AppState::new(None, None, false), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, false), CalcError::MissingOperand("You must insert a number first".into())));
 
-            assert_eq!(expected, do_power(input_num, input_state));
+            assert_eq!(expected, do_power(input_num, input_state, &mut Vec::new()));
         }
     }
 
@@ -237,17 +530,67 @@ mod tests {
     fn test_root__045__046() {
         {
             let input_num = 2.0;
-            let input_state = AppState::new(Some(Complex::new(2.0, 2.0)), None, false);
-            let expected = Ok(AppState::new(Some(Complex::new(1.5537739740300374, 0.6435942529055827)), None, true));
+            let input_state = AppState::new(Some(Complex::new(2.0, 2.0).into()), None, false);
+            let expected = Ok(AppState::new(Some(Complex::new(1.5537739740300374, 0.6435942529055827).into()), None, true));
 
-            assert_eq!(expected, do_root(input_num, input_state));
+            assert_eq!(expected, do_root(input_num, input_state, &mut Vec::new()));
         }
         {
             let input_num = 2.0;
             let input_state = AppState::new(None, None, false);
-            let expected = Err((AppState::new(None, None, false), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, false), CalcError::MissingOperand("You must insert a number first".into())));
 
-            assert_eq!(expected, do_root(input_num, input_state));
+            assert_eq!(expected, do_root(input_num, input_state, &mut Vec::new()));
         }
     }
+
+    fn list(items: &[f64]) -> Value {
+        Value::List(items.iter().map(|&n| Complex::new(n, 0.0)).collect())
+    }
+
+    #[test]
+    fn test_map_values__286() {
+        let state = AppState::new(Some(list(&[1.0, 2.0, 3.0])), None, false);
+        let expected = AppState::new(Some(list(&[2.0, 5.0, 10.0])), None, true);
+        let cmds = vec![Command::Power(2.0), Command::Addition, Command::Number(Complex::new(1.0, 0.0))];
+
+        assert_eq!(Ok(expected), map_values(&cmds, state, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_filter_values__287() {
+        let state = AppState::new(Some(list(&[1.0, -2.0, 3.0])), None, false);
+        let expected = AppState::new(Some(list(&[1.0, 3.0])), None, true);
+        let condition = ::script::parse_condition("real > 0").unwrap();
+
+        assert_eq!(Ok(expected), filter_values(&condition, state, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_reduce_values__288() {
+        {
+            let state = AppState::new(Some(list(&[1.0, 2.0, 3.0])), None, false);
+            let expected = AppState::new(Some(Complex::new(6.0, 0.0).into()), None, true);
+
+            assert_eq!(Ok(expected), reduce_values(&Command::Addition, state, &mut Vec::new()));
+        }
+        {
+            let state = AppState::new(Some(list(&[])), None, false);
+            let expected = Err((state.clone(), CalcError::Domain("Cannot reduce an empty list".into())));
+
+            assert_eq!(expected, reduce_values(&Command::Addition, state, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_print_polar__297() {
+        let settings = Settings::new(3, Angle::Gradians);
+        let ini_state = AppState::new(Some(Complex::new(0.0, -2.0).into()), None, true).with_settings(settings);
+        let mut out = Vec::new();
+
+        let output = print_polar(ini_state, &mut out);
+
+        assert_eq!(Ok(AppState::new(Some(Complex::new(0.0, -2.0).into()), None, false).with_settings(settings)), output);
+        assert_eq!(vec![Event::Polar(2.0, -100.0, Angle::Gradians)], out);
+    }
 }
@@ -0,0 +1,457 @@
+use std::path::PathBuf;
+use complex::state::{Mode, Angle, DEFAULT_PRECISION};
+use complex::complex::dialect::Dialect;
+use complex::locale::Locale;
+use complex::log::LogFormat;
+use complex::limits::Limits;
+use std::time::Duration;
+
+pub const USAGE: &'static str = "Usage: complex [options] [script]
+       complex serve --socket <path> [options]
+       complex client --socket <path>
+
+Runs the calculator interactively, or non-interactively when a script,
+an expression or piped input is given. `serve` shares calculator sessions
+with several clients over a Unix domain socket, and `client` connects to it.
+
+Scripts may contain `# comments`, `source <file>`, and `if`, `while` and
+`for k in <from>..<to>` blocks closed by `end`, with an optional `else`.
+Conditions compare numbers, loop variables, real, imaginary, argument or
+depth with <, <=, >, >=, == or !=.
+
+Options:
+  -e <expr>                Evaluates an expression, can be repeated
+  --keep-going             Keeps running after an error instead of stopping
+  --log <path>             Writes the results log to <path> (default: results.txt)
+  --append                 Appends to the results log instead of truncating it
+  --no-log                 Disables the results log
+  --log-format <format>    Results log format: plain, csv or json (default: plain)
+  --precision <n>          Number of decimals shown in results (default: 3)
+  --mode <rpn|algebraic>   Starts the calculator in the given input mode
+  --angle <unit>           Angle unit: deg, rad, grad or turn (default: rad)
+  --dialect <name>         Reads and shows numbers as native, python, matlab,
+                           mathematica, c99 or json writes them (default: native)
+  --locale <en|es>         Language of messages and number separators (default: from
+                           COMPLEX_LOCALE or LANG, otherwise en)
+  --resume[=<path>]        Restores a saved session and saves it back on exit
+                           (default: ~/.complex_session)
+  --rpc                    Speaks line-delimited JSON-RPC 2.0 over stdin and stdout
+  --socket <path>          Unix domain socket used by serve and client
+  --max-input <bytes>      Longest accepted input line (default: 65536)
+  --max-depth <n>          Deepest nesting of brackets and map commands (default: 128)
+  --max-steps <n>          Most commands run per evaluation (default: 1000000)
+  --timeout <ms>           Wall-clock limit per evaluation (default: none)
+  --max-magnitude <x>      Largest accepted result modulus (default: none)
+  -h, --help               Shows this message";
+
+pub const DEFAULT_LOG: &'static str = "results.txt";
+pub const MAX_PRECISION: usize = 17;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Subcommand {
+    Calculate,
+    Serve,
+    Client
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub subcommand: Subcommand,
+    pub script: Option<PathBuf>,
+    pub expressions: Vec<String>,
+    pub keep_going: bool,
+    pub help: bool,
+    pub log: Option<PathBuf>,
+    pub append: bool,
+    pub log_format: LogFormat,
+    pub precision: usize,
+    pub mode: Mode,
+    pub angle: Angle,
+    pub dialect: Dialect,
+    // None takes it from the environment
+    pub locale: Option<Locale>,
+    pub resume: Option<Option<PathBuf>>,
+    pub rpc: bool,
+    pub socket: Option<PathBuf>,
+    pub limits: Limits
+}
+
+impl Options {
+    pub fn default() -> Options {
+        Options {
+            subcommand: Subcommand::Calculate,
+            script: None,
+            expressions: Vec::new(),
+            keep_going: false,
+            help: false,
+            log: Some(PathBuf::from(DEFAULT_LOG)),
+            append: false,
+            log_format: LogFormat::Plain,
+            precision: DEFAULT_PRECISION,
+            mode: Mode::Algebraic,
+            angle: Angle::Radians,
+            dialect: Dialect::Native,
+            locale: None,
+            resume: None,
+            rpc: false,
+            socket: None,
+            limits: Limits::default()
+        }
+    }
+}
+
+fn positive<I: Iterator<Item = String>>(option: &str, inline: Option<String>, args: &mut I) -> Result<usize, String> {
+    let v = value(option, inline, args)?;
+    match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Invalid value for {}: {} (expecting a positive integer)", option, v))
+    }
+}
+
+fn value<I: Iterator<Item = String>>(option: &str, inline: Option<String>, args: &mut I) -> Result<String, String> {
+    match inline.or_else(|| args.next()) {
+        Some(v) => Ok(v),
+        None => Err(format!("Option {} expects a value", option))
+    }
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut first = true;
+    while let Some(arg) = args.next() {
+        if first {
+            first = false;
+            match arg.as_str() {
+                "serve" => {
+                    options.subcommand = Subcommand::Serve;
+                    continue;
+                },
+                "client" => {
+                    options.subcommand = Subcommand::Client;
+                    continue;
+                },
+                _ => ()
+            }
+        }
+        let (name, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_owned(), Some(arg[i + 1..].to_owned())),
+            _ => (arg.clone(), None)
+        };
+        match name.as_str() {
+            "-h" | "--help" => options.help = true,
+            "--keep-going" => options.keep_going = true,
+            "--append" => options.append = true,
+            "--rpc" => options.rpc = true,
+            "--no-log" => options.log = None,
+            "-e" => match args.next() {
+                Some(expr) => options.expressions.push(expr),
+                None => return Err("Option -e expects an expression".into())
+            },
+            "--log" => options.log = Some(PathBuf::from(value("--log", inline, &mut args)?)),
+            "--log-format" => options.log_format = match value("--log-format", inline, &mut args)?.as_str() {
+                "plain" => LogFormat::Plain,
+                "csv" => LogFormat::Csv,
+                "json" => LogFormat::Json,
+                v => return Err(format!("Invalid log format: {} (expecting plain, csv or json)", v))
+            },
+            "--precision" => {
+                let v = value("--precision", inline, &mut args)?;
+                options.precision = match v.parse::<usize>() {
+                    Ok(n) if n <= MAX_PRECISION => n,
+                    _ => return Err(format!("Invalid precision: {} (expecting 0 to {})", v, MAX_PRECISION))
+                };
+            },
+            "--mode" => options.mode = match value("--mode", inline, &mut args)?.as_str() {
+                "rpn" => Mode::Rpn,
+                "algebraic" => Mode::Algebraic,
+                v => return Err(format!("Invalid mode: {} (expecting rpn or algebraic)", v))
+            },
+            "--angle" => {
+                let v = value("--angle", inline, &mut args)?;
+                options.angle = match Angle::parse(&v) {
+                    Some(angle) => angle,
+                    None => return Err(format!("Invalid angle unit: {} (expecting deg, rad, grad or turn)", v))
+                }
+            },
+            "--dialect" => {
+                let v = value("--dialect", inline, &mut args)?;
+                options.dialect = match Dialect::parse(&v) {
+                    Some(dialect) => dialect,
+                    None => return Err(format!("Invalid dialect: {} (expecting native, python, matlab, mathematica, c99 or json)", v))
+                }
+            },
+            "--locale" => {
+                let v = value("--locale", inline, &mut args)?;
+                options.locale = match Locale::parse(&v) {
+                    Some(locale) => Some(locale),
+                    None => return Err(format!("Invalid locale: {} (expecting en or es)", v))
+                }
+            },
+            "--socket" => options.socket = Some(PathBuf::from(value("--socket", inline, &mut args)?)),
+            "--max-input" => options.limits.max_input = positive("--max-input", inline, &mut args)?,
+            "--max-depth" => options.limits.max_depth = positive("--max-depth", inline, &mut args)?,
+            "--max-steps" => options.limits.max_steps = positive("--max-steps", inline, &mut args)?,
+            "--timeout" => {
+                let ms = positive("--timeout", inline, &mut args)?;
+                options.limits.timeout = Some(Duration::from_millis(ms as u64));
+            },
+            "--max-magnitude" => {
+                let v = value("--max-magnitude", inline, &mut args)?;
+                options.limits.max_magnitude = match v.parse::<f64>() {
+                    Ok(x) if x > 0.0 => x,
+                    _ => return Err(format!("Invalid value for --max-magnitude: {} (expecting a positive number)", v))
+                };
+            },
+            "--resume" => options.resume = Some(inline.map(PathBuf::from)),
+            s if s.starts_with('-') && s != "-" => return Err(format!("Unknown option: {}", s)),
+            _ if options.script.is_some() => return Err(format!("Unexpected argument: {}", arg)),
+            _ => options.script = Some(PathBuf::from(arg))
+        }
+    }
+    if options.script.is_some() && !options.expressions.is_empty() {
+        return Err("A script cannot be combined with -e".into());
+    }
+    if options.rpc && (options.script.is_some() || !options.expressions.is_empty()) {
+        return Err("--rpc cannot be combined with a script or -e".into());
+    }
+    match options.subcommand {
+        Subcommand::Calculate if options.socket.is_some() => {
+            return Err("--socket is only valid with serve or client".into());
+        },
+        Subcommand::Calculate => (),
+        _ if options.socket.is_none() => return Err("Option --socket is required".into()),
+        _ if options.rpc || options.script.is_some() || !options.expressions.is_empty() => {
+            return Err("serve and client cannot be combined with --rpc, a script or -e".into());
+        },
+        _ => ()
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &[&str]) -> Vec<String> {
+        input.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    #[test]
+    fn test_parse_no_args__142() {
+        let expected = Ok(Options::default());
+
+        assert_eq!(expected, parse_args(args(&[]).into_iter()));
+    }
+
+    #[test]
+    fn test_parse_script_and_expressions__143__144() {
+        {
+            let mut expected = Options::default();
+            expected.script = Some(PathBuf::from("script.cx"));
+            expected.keep_going = true;
+
+            assert_eq!(Ok(expected), parse_args(args(&["--keep-going", "script.cx"]).into_iter()));
+        }
+        {
+            let mut expected = Options::default();
+            expected.expressions = vec!["1+1j".to_owned(), "addition 2".to_owned()];
+
+            assert_eq!(Ok(expected), parse_args(args(&["-e", "1+1j", "-e", "addition 2"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_bad_args__145__146__147__148() {
+        {
+            let expected = Err("Option -e expects an expression".into());
+
+            assert_eq!(expected, parse_args(args(&["-e"]).into_iter()));
+        }
+        {
+            let expected = Err("Unknown option: --fast".into());
+
+            assert_eq!(expected, parse_args(args(&["--fast"]).into_iter()));
+        }
+        {
+            let expected = Err("Unexpected argument: b.cx".into());
+
+            assert_eq!(expected, parse_args(args(&["a.cx", "b.cx"]).into_iter()));
+        }
+        {
+            let expected = Err("A script cannot be combined with -e".into());
+
+            assert_eq!(expected, parse_args(args(&["a.cx", "-e", "1"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_log_options__161__162() {
+        {
+            let mut expected = Options::default();
+            expected.log = Some(PathBuf::from("out.log"));
+            expected.append = true;
+
+            assert_eq!(Ok(expected), parse_args(args(&["--log", "out.log", "--append"]).into_iter()));
+        }
+        {
+            let mut expected = Options::default();
+            expected.log = None;
+
+            assert_eq!(Ok(expected), parse_args(args(&["--no-log"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_log_format__180__181() {
+        {
+            let mut expected = Options::default();
+            expected.log_format = LogFormat::Json;
+
+            assert_eq!(Ok(expected), parse_args(args(&["--log-format", "json"]).into_iter()));
+        }
+        {
+            let expected = Err("Invalid log format: xml (expecting plain, csv or json)".into());
+
+            assert_eq!(expected, parse_args(args(&["--log-format=xml"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_settings_options__163() {
+        let mut expected = Options::default();
+        expected.precision = 6;
+        expected.mode = Mode::Rpn;
+        expected.angle = Angle::Degrees;
+
+        assert_eq!(Ok(expected), parse_args(args(&["--precision", "6", "--mode=rpn", "--angle", "deg"]).into_iter()));
+    }
+
+    #[test]
+    fn test_parse_bad_values__164__165__166__167() {
+        {
+            let expected = Err("Invalid precision: 40 (expecting 0 to 17)".into());
+
+            assert_eq!(expected, parse_args(args(&["--precision", "40"]).into_iter()));
+        }
+        {
+            let expected = Err("Invalid mode: hp (expecting rpn or algebraic)".into());
+
+            assert_eq!(expected, parse_args(args(&["--mode", "hp"]).into_iter()));
+        }
+        {
+            let expected = Err("Invalid angle unit: gon (expecting deg, rad, grad or turn)".into());
+
+            assert_eq!(expected, parse_args(args(&["--angle=gon"]).into_iter()));
+        }
+        {
+            let expected = Err("Option --log expects a value".into());
+
+            assert_eq!(expected, parse_args(args(&["--log"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_resume__196__197() {
+        {
+            let mut expected = Options::default();
+            expected.resume = Some(None);
+            expected.script = Some(PathBuf::from("script.cx"));
+
+            assert_eq!(Ok(expected), parse_args(args(&["--resume", "script.cx"]).into_iter()));
+        }
+        {
+            let mut expected = Options::default();
+            expected.resume = Some(Some(PathBuf::from("work.cx")));
+
+            assert_eq!(Ok(expected), parse_args(args(&["--resume=work.cx"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_rpc__221__222() {
+        {
+            let mut expected = Options::default();
+            expected.rpc = true;
+
+            assert_eq!(Ok(expected), parse_args(args(&["--rpc"]).into_iter()));
+        }
+        {
+            let expected = Err("--rpc cannot be combined with a script or -e".into());
+
+            assert_eq!(expected, parse_args(args(&["--rpc", "-e", "1"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_subcommands__229__230__231() {
+        {
+            let mut expected = Options::default();
+            expected.subcommand = Subcommand::Serve;
+            expected.socket = Some(PathBuf::from("/tmp/complex.sock"));
+            expected.mode = Mode::Rpn;
+
+            assert_eq!(Ok(expected), parse_args(args(&["serve", "--socket", "/tmp/complex.sock", "--mode", "rpn"]).into_iter()));
+        }
+        {
+            let expected = Err("Option --socket is required".into());
+
+            assert_eq!(expected, parse_args(args(&["client"]).into_iter()));
+        }
+        {
+            let expected = Err("--socket is only valid with serve or client".into());
+
+            assert_eq!(expected, parse_args(args(&["script.cx", "--socket", "x"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_limits__242__243() {
+        {
+            let mut expected = Options::default();
+            expected.limits.max_input = 80;
+            expected.limits.max_steps = 10;
+            expected.limits.timeout = Some(Duration::from_millis(250));
+            expected.limits.max_magnitude = 1e9;
+
+            let output = parse_args(args(&["--max-input", "80", "--max-steps=10", "--timeout", "250",
+                                           "--max-magnitude", "1e9"]).into_iter());
+
+            assert_eq!(Ok(expected), output);
+        }
+        {
+            let expected = Err("Invalid value for --max-depth: 0 (expecting a positive integer)".into());
+
+            assert_eq!(expected, parse_args(args(&["--max-depth", "0"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_dialect__324__325() {
+        {
+            let mut expected = Options::default();
+            expected.dialect = Dialect::Mathematica;
+
+            assert_eq!(Ok(expected), parse_args(args(&["--dialect", "mathematica"]).into_iter()));
+        }
+        {
+            let expected = Err("Invalid dialect: fortran (expecting native, python, matlab, mathematica, c99 or json)".into());
+
+            assert_eq!(expected, parse_args(args(&["--dialect=fortran"]).into_iter()));
+        }
+    }
+
+    #[test]
+    fn test_parse_locale__333__334() {
+        {
+            let mut expected = Options::default();
+            expected.locale = Some(Locale::Es);
+
+            assert_eq!(Ok(expected), parse_args(args(&["--locale", "es_ES.UTF-8"]).into_iter()));
+        }
+        {
+            let expected = Err("Invalid locale: fr (expecting en or es)".into());
+
+            assert_eq!(expected, parse_args(args(&["--locale=fr"]).into_iter()));
+        }
+    }
+}
@@ -1,9 +1,19 @@
 use complex::Complex;
-use std::str::SplitWhitespace;
-use std::io::{Write, stdout};
-use complex::parser::parse_from_string;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use complex::parser::{self, parse_from_string};
+use regex::{Captures, Regex};
+use complex::dialect::Dialect;
+use state::{Mode, Angle, Settings};
+use error::{CalcError, Span};
+use rewrite::Rewrite;
+use limits::{Limits, Nesting};
+use input::Source;
+use registry::{self, Arg, ArgKind, Spec};
+use script::{self, Condition};
+use value::{self, Value};
+use format::FormatOption;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Command {
     Help,
     Exit,
@@ -11,79 +21,567 @@ pub enum Command {
     Addition,
     Subtraction,
     Multiplication,
+    Division,
     Real,
     Imaginary,
+    Argument,
+    Polar,
+    Sin,
+    Cos,
+    Tan,
     Power(f64),
     Root(f64),
     Number(Complex),
+    // A polar literal like 2@45, in the configured unit unless it names one
+    Phasor(f64, f64, Option<Angle>),
+    Undo,
+    Redo,
+    History,
+    Mode(Mode),
+    Angle(Angle),
+    // No options shows the current format
+    Format(Vec<FormatOption>),
+    CopyAs(Dialect),
+    Swap,
+    Dup,
+    Drop,
+    Roll,
+    Stack,
+    Save(String),
+    Load(String),
+    Source(String),
+    Value(Value),
+    Map(Vec<Command>),
+    Filter(Condition),
+    Reduce(Box<Command>),
+    HelpOn(String),
+    Custom(String, Vec<Arg>),
 }
 
-fn parse_command(head: &str, mut tail: SplitWhitespace) -> Result<Command, String> {
-    match head {
-        "help" => Ok(Command::Help),
-        "clear" => Ok(Command::Clear),
-        "exit" => Ok(Command::Exit),
-        "addition" => Ok(Command::Addition),
-        "subtraction" => Ok(Command::Subtraction),
-        "multiplication" => Ok(Command::Multiplication),
-        "real" => Ok(Command::Real),
-        "imaginary" => Ok(Command::Imaginary),
-        "power" => match tail.next() {
-            Some(s) => {
-                match s.parse::<f64>() {
-                    Ok(n) => Ok(Command::Power(n)),
-                    Err(_) => Err("Expecting a number".into())
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Command::Help => write!(f, "help"),
+            Command::Exit => write!(f, "exit"),
+            Command::Clear => write!(f, "clear"),
+            Command::Addition => write!(f, "addition"),
+            Command::Subtraction => write!(f, "subtraction"),
+            Command::Multiplication => write!(f, "multiplication"),
+            Command::Division => write!(f, "division"),
+            Command::Real => write!(f, "real"),
+            Command::Imaginary => write!(f, "imaginary"),
+            Command::Argument => write!(f, "argument"),
+            Command::Polar => write!(f, "polar"),
+            Command::Sin => write!(f, "sin"),
+            Command::Cos => write!(f, "cos"),
+            Command::Tan => write!(f, "tan"),
+            Command::Power(n) => write!(f, "power {}", n),
+            Command::Root(n) => write!(f, "root {}", n),
+            Command::Number(ref num) => write!(f, "{}", num),
+            Command::Phasor(r, theta, Some(unit)) => write!(f, "{}@{}{}", r, theta, unit),
+            Command::Phasor(r, theta, None) => write!(f, "{}@{}", r, theta),
+            Command::Undo => write!(f, "undo"),
+            Command::Redo => write!(f, "redo"),
+            Command::History => write!(f, "history"),
+            Command::Mode(mode) => write!(f, "mode {}", mode),
+            Command::Angle(angle) => write!(f, "angle {}", angle),
+            Command::Format(ref options) => {
+                write!(f, "format")?;
+                for option in options {
+                    write!(f, " {}", option)?;
                 }
+                Ok(())
             },
-            None => Err("Expecting a number".into())
-        },
-        "root" => match tail.next() {
-            Some(s) => {
-                match s.parse::<f64>() {
-                    Ok(n) => Ok(Command::Root(n)),
-                    Err(_) => Err("Expecting a number".into())
+            Command::CopyAs(dialect) => write!(f, "copyas {}", dialect),
+            Command::Swap => write!(f, "swap"),
+            Command::Dup => write!(f, "dup"),
+            Command::Drop => write!(f, "drop"),
+            Command::Roll => write!(f, "roll"),
+            Command::Stack => write!(f, "stack"),
+            Command::Save(ref path) => write!(f, "save {}", path),
+            Command::Load(ref path) => write!(f, "load {}", path),
+            Command::Source(ref path) => write!(f, "source {}", path),
+            Command::Value(ref value) => write!(f, "{}", value),
+            Command::Map(ref cmds) => {
+                write!(f, "map")?;
+                for cmd in cmds {
+                    write!(f, " {}", cmd)?;
                 }
+                Ok(())
             },
-            None => Err("Expecting a number".into())
-        },
-        s => {
-            let mut v = vec![s];
-            for t in tail {
-                v.push(t);
+            Command::Filter(ref condition) => write!(f, "filter {}", condition),
+            Command::Reduce(ref op) => write!(f, "reduce {}", op),
+            Command::HelpOn(ref topic) => write!(f, "help {}", topic),
+            Command::Custom(ref name, ref args) => {
+                write!(f, "{}", name)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+pub fn command_names() -> Vec<String> {
+    registry::names()
+}
+
+// A piece of the line with the byte offset where it starts, so errors can point back at it
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub start: usize
+}
+
+impl<'a> Token<'a> {
+    pub fn new(text: &'a str, start: usize) -> Token<'a> {
+        Token {
+            text: text,
+            start: start
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+
+    pub fn span(&self) -> Span {
+        Span::new(self.start, self.end())
+    }
+
+    // The span from the start of this token to the end of last
+    pub fn to(&self, last: &Token) -> Span {
+        Span::new(self.start, last.end())
+    }
+
+    pub fn slice(&self, from: usize, to: usize) -> Token<'a> {
+        Token::new(&self.text[from..to], self.start + from)
+    }
+
+    pub fn trim(&self) -> Token<'a> {
+        let from = self.text.len() - self.text.trim_start().len();
+        Token::new(self.text.trim(), self.start + from)
+    }
+
+    pub fn words(&self) -> Vec<Token<'a>> {
+        let mut words = Vec::new();
+        let mut rest = 0;
+        while let Some(offset) = self.text[rest..].find(|c: char| !c.is_whitespace()) {
+            let start = rest + offset;
+            rest = self.text[start..].find(char::is_whitespace).map_or(self.text.len(), |i| start + i);
+            words.push(self.slice(start, rest));
+        }
+        words
+    }
+}
+
+// Parses the rest of the line from the first token left, keeping error spans relative to the line
+fn parse_rest<'a, I, T, F>(line: &str, head: Token, tail: I, missing: &str, parse: F) -> Result<T, CalcError>
+    where I: Iterator<Item = Token<'a>>, F: Fn(&str) -> Result<T, CalcError> {
+    let words: Vec<Token> = tail.collect();
+    let offset = match words.first() {
+        Some(first) => first.start,
+        None => return Err(CalcError::parse(missing, head.span()))
+    };
+    parse(&line[offset..]).map_err(|e| match e {
+        CalcError::Parse { message, span } => CalcError::parse(message, span.shift(offset)),
+        e => e
+    })
+}
+
+fn parse_operation(head: Token, token: Option<Token>) -> Result<Command, CalcError> {
+    let spec = token.and_then(|token| registry::find(token.text)).filter(|spec| spec.args.is_empty());
+    match spec.map(|spec| spec.build(Vec::new())) {
+        Some(op @ Command::Addition) |
+        Some(op @ Command::Subtraction) |
+        Some(op @ Command::Multiplication) |
+        Some(op @ Command::Division) => Ok(op),
+        _ => Err(CalcError::parse("Expecting addition, subtraction, multiplication or division", token.unwrap_or(head).span()))
+    }
+}
+
+fn parse_args<'a, I>(line: &str, head: Token, spec: &Spec, mut tail: I, nesting: Nesting) -> Result<Vec<Arg>, CalcError>
+    where I: Iterator<Item = Token<'a>> {
+    let mut args = Vec::new();
+    for kind in &spec.args {
+        match *kind {
+            ArgKind::Commands => {
+                args.push(Arg::Commands(parse_rest(line, head, tail.by_ref(), "Expecting commands",
+                                                   |text| parse_tokens(text, nesting))?));
+                continue;
+            },
+            ArgKind::Format => {
+                let mut options = Vec::new();
+                for word in tail.by_ref() {
+                    options.push(FormatOption::parse(word.text).map_err(|message| CalcError::parse(message, word.span()))?);
+                }
+                args.push(Arg::Format(options));
+                continue;
+            },
+            ArgKind::Condition => {
+                let condition = parse_rest(line, head, tail.by_ref(), "Expecting a condition such as real > 0",
+                                           script::parse_condition)?;
+                args.push(Arg::Condition(condition));
+                continue;
+            },
+            _ => ()
+        }
+        let token = tail.next();
+        let text = token.map(|token| token.text);
+        let at = token.unwrap_or(head).span();
+        let arg = match (*kind, text) {
+            (ArgKind::Commands, _) | (ArgKind::Condition, _) | (ArgKind::Format, _) => unreachable!(),
+            (ArgKind::Operation, _) => Arg::Operation(parse_operation(head, token)?),
+            (ArgKind::Path, _) => {
+                return Err(CalcError::parse(format!("{} must be the only command on its line", head.text), head.span()));
+            },
+            (ArgKind::Topic, None) => continue,
+            (ArgKind::Topic, Some(s)) => match registry::find(s) {
+                Some(spec) => Arg::Text(spec.name),
+                None => {
+                    let message = format!("Unknown command: {}{}", s, registry::did_you_mean(s));
+                    return Err(CalcError::parse(message, at));
+                }
+            },
+            (ArgKind::Number, Some(s)) => match s.parse::<f64>() {
+                Ok(n) => Arg::Number(n),
+                Err(_) => return Err(CalcError::parse("Expecting a number", at))
+            },
+            (ArgKind::Number, None) => return Err(CalcError::parse("Expecting a number", at)),
+            (ArgKind::Mode, Some(s)) if s.eq_ignore_ascii_case("rpn") => Arg::Mode(Mode::Rpn),
+            (ArgKind::Mode, Some(s)) if s.eq_ignore_ascii_case("algebraic") => Arg::Mode(Mode::Algebraic),
+            (ArgKind::Mode, _) => return Err(CalcError::parse("Expecting a mode: rpn or algebraic", at)),
+            (ArgKind::Angle, text) => match text.and_then(Angle::parse) {
+                Some(angle) => Arg::Angle(angle),
+                None => return Err(CalcError::parse("Expecting an angle unit: deg, rad, grad or turn", at))
+            },
+            (ArgKind::Dialect, text) => match text.and_then(Dialect::parse) {
+                Some(dialect) => Arg::Dialect(dialect),
+                None => {
+                    let message = "Expecting a dialect: native, python, matlab, mathematica, c99 or json";
+                    return Err(CalcError::parse(message, at));
+                }
             }
-            
-            match parse_from_string(v.join(" ")) {
+        };
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn parse_command<'a, I>(line: &str, head: Token<'a>, tail: I, nesting: Nesting) -> Result<Command, CalcError>
+    where I: Iterator<Item = Token<'a>> {
+    match registry::find(head.text) {
+        Some(spec) => parse_args(line, head, &spec, tail, nesting).map(|args| spec.build(args)),
+        None => {
+            let mut v = vec![head];
+            v.extend(tail);
+            let text: Vec<&str> = v.iter().map(|token| token.text).collect();
+            match parse_from_string(text.join(" ")) {
                 Ok(cplx) => Ok(Command::Number(cplx)),
-                Err(unk) => Err(format!("Unknown command: {}", unk))
+                Err(unk) => {
+                    let message = format!("Unknown command: {}{}", unk, registry::did_you_mean(head.text));
+                    Err(CalcError::parse(message, head.to(&v[v.len() - 1])))
+                }
             }
         }
     }
 }
 
-pub fn read_command() -> Result<Command, String> {
-    print!(">>> ");
-    {
-        let _ = stdout().flush();
+// A literal ends at its matching bracket or quote, or at the end of the input if it is unterminated
+fn literal_end(input: &str, start: usize) -> usize {
+    let text = &input[start..];
+    let end = if text.starts_with('"') {
+        text[1..].find('"').map(|i| i + 2)
+    } else {
+        let mut depth = 0;
+        text.char_indices().find(|&(_, c)| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => ()
+            }
+            depth == 0
+        }).map(|(i, _)| i + 1)
+    };
+    end.map_or(input.len(), |end| start + end)
+}
+
+fn tokenize<'a>(input: &'a str) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    let mut rest = 0;
+    while let Some(offset) = input[rest..].find(|c: char| !c.is_whitespace()) {
+        let start = rest + offset;
+        if input[start..].starts_with('[') || input[start..].starts_with('"') {
+            rest = literal_end(input, start);
+            tokens.push(Token::new(&input[start..rest], start));
+            continue;
+        }
+        rest = input[start..].find(|c: char| c.is_whitespace() || c == '[' || c == '"').map_or(input.len(), |i| start + i);
+        let word = Token::new(&input[start..rest], start);
+        let mut from = 0;
+        for (i, c) in word.text.char_indices() {
+            if c == '*' || c == '/' || c == '^' {
+                if from < i {
+                    tokens.push(word.slice(from, i));
+                }
+                tokens.push(word.slice(i, i + 1));
+                from = i + 1;
+            }
+        }
+        if from < word.text.len() {
+            tokens.push(word.slice(from, word.text.len()));
+        }
     }
-    let input_str: String = read!("{}\n");
-    let mut iter = input_str.split_whitespace();
-    match iter.next() {
-        None => Err("Please enter a command".into()),
-        Some(s) => parse_command(s, iter),
+    tokens
+}
+
+fn is_literal(token: &str) -> bool {
+    token.starts_with('[') || token.starts_with('"') || token.eq_ignore_ascii_case("true") ||
+        token.eq_ignore_ascii_case("false")
+}
+
+fn parse_literal(token: Token, nesting: Nesting) -> Result<Command, CalcError> {
+    match value::parse(token.text, nesting.max()) {
+        Ok(Value::Scalar(num)) => Ok(Command::Number(num)),
+        Ok(value) => Ok(Command::Value(value)),
+        Err(message) => Err(CalcError::parse(message, token.span()))
+    }
+}
+
+// r@theta with an optional unit right after the angle, as in 2@45deg or 1 @ 0.25 turn
+fn parse_phasor(text: &str) -> Option<Command> {
+    let at = text.find('@')?;
+    let r = text[..at].trim().parse::<f64>().ok()?;
+    let angle = text[at + 1..].trim();
+    let (theta, unit) = match angle.rfind(|c: char| c.is_ascii_digit() || c == '.') {
+        Some(i) if i + 1 < angle.len() => (&angle[..i + 1], Some(Angle::parse(angle[i + 1..].trim())?)),
+        _ => (angle, None)
+    };
+    theta.trim().parse::<f64>().ok().map(|theta| Command::Phasor(r, theta, unit))
+}
+
+fn parse_numbers(words: &[Token]) -> Result<Vec<Command>, CalcError> {
+    let text: Vec<&str> = words.iter().map(|word| word.text).collect();
+    let joined = text.join(" ");
+    if let Some(phasor) = parse_phasor(&joined) {
+        return Ok(vec![phasor]);
+    }
+    match parse_from_string(joined.clone()) {
+        Ok(cplx) => Ok(vec![Command::Number(cplx)]),
+        Err(_) => {
+            let numbers: Result<Vec<Command>, String> = text.iter()
+                .map(|w| parse_from_string((*w).to_owned()).map(Command::Number))
+                .collect();
+            numbers.map_err(|unknown| {
+                let message = format!("Unknown command: {}{}", joined, registry::did_you_mean(&unknown));
+                CalcError::parse(message, words[0].to(&words[words.len() - 1]))
+            })
+        }
+    }
+}
+
+fn superscript(c: char) -> char {
+    match c {
+        '⁻' => '-',
+        '⁺' => '+',
+        c => "⁰¹²³⁴⁵⁶⁷⁸⁹".chars().position(|s| s == c).map_or(c, |i| (b'0' + i as u8) as char)
+    }
+}
+
+// Applies a replacement outside strings; patterns start with the string alternative, which has no groups
+fn outside_strings<F>(text: Rewrite, pattern: &Regex, replace: F) -> Rewrite where F: Fn(&Captures) -> String {
+    text.replace_all(pattern, |caps: &Captures| {
+        if caps[0].starts_with('"') {
+            caps[0].to_owned()
+        } else {
+            replace(caps)
+        }
+    })
+}
+
+// The literal inside optional parentheses, if it is one
+fn operand(text: &str) -> Option<Complex> {
+    let text = text.trim();
+    let text = if text.starts_with('(') && text.ends_with(')') { &text[1..text.len() - 1] } else { text };
+    parse_from_string(text.to_owned()).ok()
+}
+
+// Rewrites the mathematical symbols of pasted formulas, outside strings, into the ASCII grammar:
+// π and ⅈ as numbers, ·, × and ÷ as operators, ∠ and ° for polar numbers and parentheses around a
+// single number. A number written right before π, ⅈ or a radical multiplies it, and radicals and
+// superscripts of a literal are worked out, so 2√3 and 3² are plain numbers; superscripts after
+// anything else are powers. A sign that starts a term binds looser than its power, so -3² is -9
+// as in maths and (-3)² is 9
+fn symbols(input: Rewrite) -> Rewrite {
+    lazy_static! {
+        static ref MINUS: Regex = Regex::new(r#""[^"]*"?|−"#).unwrap();
+        static ref TERM: Regex = Regex::new(&format!(r#""[^"]*"?|{}"#, parser::TERM)).unwrap();
+        static ref OPERATOR: Regex = Regex::new(r#""[^"]*"?|[·×÷∠°]"#).unwrap();
+        static ref POWER: Regex = Regex::new(
+            r#""[^"]*"?|(?:(^|[\s(\[,])([+-]))?(\([^()]*\)|(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?j?)?([⁻⁺]?[⁰¹²³⁴⁵⁶⁷⁸⁹]+)"#
+        ).unwrap();
+        static ref RADICAL: Regex = Regex::new(
+            r#""[^"]*"?|((?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)?([√∛∜])(\([^()]*\)|[+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?j?)"#
+        ).unwrap();
+        static ref GROUP: Regex = Regex::new(r#""[^"]*"?|\(([^()]*)\)"#).unwrap();
+    }
+
+    let text = outside_strings(input, &MINUS, |_| "-".to_owned());
+    let text = outside_strings(text, &TERM, |caps| parser::term(caps, 1));
+    let text = outside_strings(text, &OPERATOR, |caps| match &caps[0] {
+        "·" | "×" => " * ".to_owned(),
+        "÷" => " / ".to_owned(),
+        "∠" => "@".to_owned(),
+        _ => "deg".to_owned()
+    });
+    let text = outside_strings(text, &POWER, |caps| {
+        let exponent: String = caps[4].chars().map(superscript).collect();
+        let prefix = caps.get(1).map_or("", |m| m.as_str());
+        let sign = caps.get(2).map_or("", |m| m.as_str());
+        match caps.get(3).map(|base| (base.as_str(), operand(base.as_str()))) {
+            Some((_, Some(base))) => {
+                let num = base.power(exponent.parse::<f64>().unwrap());
+                let num = if sign == "-" { Complex::new(0.0 - num.real(), 0.0 - num.imaginary()) } else { num };
+                format!("{} {} ", prefix, num.lossless())
+            },
+            Some((base, None)) => format!("{}{}{} ^ {} ", prefix, sign, base, exponent),
+            None => format!("{}{} ^ {} ", prefix, sign, exponent)
+        }
+    });
+    let text = outside_strings(text, &RADICAL, |caps| {
+        let degree = match &caps[2] {
+            "√" => 2.0,
+            "∛" => 3.0,
+            _ => 4.0
+        };
+        let coefficient = caps.get(1).map_or(1.0, |m| m.as_str().parse::<f64>().unwrap());
+        match operand(&caps[3]) {
+            Some(num) => {
+                let root = num.root(degree);
+                format!(" {} ", Complex::new(coefficient * root.real(), coefficient * root.imaginary()).lossless())
+            },
+            None => caps[0].to_owned()
+        }
+    });
+    outside_strings(text, &GROUP, |caps| {
+        if parse_from_string(caps[1].to_owned()).is_ok() {
+            format!(" {} ", caps[1].trim())
+        } else {
+            caps[0].to_owned()
+        }
+    })
+}
+
+fn parse_file_command(input: &str) -> Option<Result<Command, CalcError>> {
+    let line = Token::new(input, 0).trim();
+    let (head, path) = match line.text.find(char::is_whitespace) {
+        Some(i) => (line.slice(0, i), line.text[i..].trim()),
+        None => (line, "")
+    };
+    let spec = match registry::find(head.text) {
+        Some(ref spec) if spec.args == [ArgKind::Path] => spec.clone(),
+        _ => return None
+    };
+    if path.is_empty() {
+        Some(Err(CalcError::parse("Expecting a file name", head.span())))
+    } else {
+        Some(Ok(spec.build(vec![Arg::Text(path.to_owned())])))
+    }
+}
+
+pub fn parse_line(input: &str) -> Result<Vec<Command>, CalcError> {
+    parse_nested(input, Limits::default().max_depth)
+}
+
+// Brackets and the commands of map nest at most max_depth deep
+fn parse_nested(input: &str, max_depth: usize) -> Result<Vec<Command>, CalcError> {
+    if let Some(cmd) = parse_file_command(input) {
+        return cmd.map(|c| vec![c]);
+    }
+    let text = symbols(Rewrite::new(input));
+    parse_tokens(text.text(), Nesting::new(max_depth)).map_err(|e| text.error(e))
+}
+
+// Commands such as map take the rest of the line as more commands, which may nest in turn
+fn parse_tokens(input: &str, nesting: Nesting) -> Result<Vec<Command>, CalcError> {
+    let nesting = nesting.enter().map_err(CalcError::Limit)?;
+    let tokens = tokenize(input);
+    if tokens.is_empty() {
+        return Err(CalcError::parse("Please enter a command", Span::new(0, input.len())));
+    }
+
+    let mut commands = Vec::new();
+    let mut words = Vec::new();
+    let mut iter = tokens.into_iter();
+    while let Some(token) = iter.next() {
+        if is_literal(token.text) {
+            if !words.is_empty() {
+                commands.extend(parse_numbers(&words)?);
+                words.clear();
+            }
+            commands.push(parse_literal(token, nesting)?);
+        } else if registry::find(token.text).is_some() {
+            if !words.is_empty() {
+                commands.extend(parse_numbers(&words)?);
+                words.clear();
+            }
+            commands.push(parse_command(input, token, iter.by_ref(), nesting)?);
+        } else {
+            words.push(token);
+        }
+    }
+    if !words.is_empty() {
+        commands.extend(parse_numbers(&words)?);
+    }
+    Ok(commands)
+}
+
+// Numbers on the line may be written the way the dialect or, without one, the locale writes them
+pub fn parse_line_with(input: &str, settings: &Settings, max_depth: usize) -> Result<Vec<Command>, CalcError> {
+    if let Some(cmd) = parse_file_command(input) {
+        return cmd.map(|c| vec![c]);
+    }
+    match settings.dialect {
+        Dialect::Native => {
+            let text = settings.locale.delocalize(Rewrite::new(input))?;
+            parse_nested(text.text(), max_depth).map_err(|e| text.error(e))
+        },
+        dialect => {
+            let text = parser::rewrite(Rewrite::new(input), dialect);
+            parse_nested(text.text(), max_depth).map_err(|e| text.error(e))
+        }
+    }
+}
+
+pub fn read_command<S: Source + ?Sized>(source: &mut S) -> Result<Vec<Command>, CalcError> {
+    read_command_with(source, &Settings::default(), Limits::default().max_depth)
+}
+
+pub fn read_command_with<S: Source + ?Sized>(source: &mut S, settings: &Settings, max_depth: usize)
+                                             -> Result<Vec<Command>, CalcError> {
+    match source.next_line()? {
+        Some(line) => parse_line_with(&line, settings, max_depth),
+        None => Ok(vec![Command::Exit])
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use input::LineSource;
+
+    fn command(input: &str) -> Result<Command, CalcError> {
+        let mut iter = tokenize(input).into_iter();
+        parse_command(input, iter.next().unwrap(), iter, Nesting::new(128))
+    }
     
     #[test]
     fn test_parse_help_command__047() {
         let input: String = "help".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Help);
+        let expected: Result<Command, CalcError> = Ok(Command::Help);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -91,10 +589,9 @@ mod tests {
     #[test]
     fn test_parse_clear_command__048() {
         let input: String = "clear".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Clear);
+        let expected: Result<Command, CalcError> = Ok(Command::Clear);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -102,10 +599,9 @@ mod tests {
     #[test]
     fn test_parse_exit_command__049() {
         let input: String = "exit".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Exit);
+        let expected: Result<Command, CalcError> = Ok(Command::Exit);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -113,10 +609,9 @@ mod tests {
     #[test]
     fn test_parse_addition_command__050() {
         let input: String = "addition".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Addition);
+        let expected: Result<Command, CalcError> = Ok(Command::Addition);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -124,10 +619,9 @@ mod tests {
     #[test]
     fn test_parse_subtraction_command__051() {
         let input: String = "subtraction".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Subtraction);
+        let expected: Result<Command, CalcError> = Ok(Command::Subtraction);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -135,10 +629,9 @@ mod tests {
     #[test]
     fn test_parse_multiplication_command__052() {
         let input: String = "multiplication".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Multiplication);
+        let expected: Result<Command, CalcError> = Ok(Command::Multiplication);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -146,10 +639,9 @@ mod tests {
     #[test]
     fn test_parse_real_command__053() {
         let input: String = "real".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Real);
+        let expected: Result<Command, CalcError> = Ok(Command::Real);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -157,10 +649,9 @@ mod tests {
     #[test]
     fn test_parse_imaginary_command__054() {
         let input: String = "imaginary".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Imaginary);
+        let expected: Result<Command, CalcError> = Ok(Command::Imaginary);
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -168,10 +659,9 @@ mod tests {
     #[test]
     fn test_parse_power_command__055() {
         let input: String = "power 3".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Power(3.0));
+        let expected: Result<Command, CalcError> = Ok(Command::Power(3.0));
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -179,10 +669,9 @@ mod tests {
     #[test]
     fn test_parse_root_command__056() {
         let input: String = "root 2".to_owned();
-        let expected: Result<Command, String> = Ok(Command::Root(2.0));
+        let expected: Result<Command, CalcError> = Ok(Command::Root(2.0));
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -191,19 +680,17 @@ mod tests {
     fn test_parse_bad_power_command__057__058() {
         {
             let input: String = "power".to_owned();
-            let expected: Result<Command, String> = Err("Expecting a number".into());
+            let expected: Result<Command, CalcError> = Err(CalcError::parse("Expecting a number", Span::new(0, 5)));
 
-            let mut iter = input.split_whitespace();
-            let output = parse_command(iter.next().unwrap(), iter);
+            let output = command(&input);
 
             assert_eq!(expected, output);
         }
         {
             let input: String = "power bad".to_owned();
-            let expected: Result<Command, String> = Err("Expecting a number".into());
+            let expected: Result<Command, CalcError> = Err(CalcError::parse("Expecting a number", Span::new(6, 9)));
 
-            let mut iter = input.split_whitespace();
-            let output = parse_command(iter.next().unwrap(), iter);
+            let output = command(&input);
 
             assert_eq!(expected, output);
         }
@@ -213,19 +700,17 @@ mod tests {
     fn test_parse_bad_root_command__059__060() {
         {
             let input: String = "root".to_owned();
-            let expected: Result<Command, String> = Err("Expecting a number".into());
+            let expected: Result<Command, CalcError> = Err(CalcError::parse("Expecting a number", Span::new(0, 4)));
 
-            let mut iter = input.split_whitespace();
-            let output = parse_command(iter.next().unwrap(), iter);
+            let output = command(&input);
 
             assert_eq!(expected, output);
         }
         {
             let input: String = "root bad".to_owned();
-            let expected: Result<Command, String> = Err("Expecting a number".into());
+            let expected: Result<Command, CalcError> = Err(CalcError::parse("Expecting a number", Span::new(5, 8)));
 
-            let mut iter = input.split_whitespace();
-            let output = parse_command(iter.next().unwrap(), iter);
+            let output = command(&input);
 
             assert_eq!(expected, output);
         }
@@ -234,10 +719,9 @@ mod tests {
     #[test]
     fn test_parse_an_unknown_command__061() {
         let input: String = "unknown".to_owned();
-        let expected: Result<Command, String> = Err("Unknown command: unknown".into());
+        let expected: Result<Command, CalcError> = Err(CalcError::parse("Unknown command: unknown", Span::new(0, 7)));
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
         
         assert_eq!(expected, output);
     }
@@ -247,18 +731,401 @@ mod tests {
         let input: String = "1 +1j".to_owned();
         let expected = Ok(Command::Number(Complex::new(1.0,1.0)));
 
-        let mut iter = input.split_whitespace();
-        let output = parse_command(iter.next().unwrap(), iter);
+        let output = command(&input);
 
         assert_eq!(expected, output);
     }
 
     #[test]
-    fn test_read_command_from_stdin__063() {
-        let expected = Ok(Command::Help);
+    fn test_parse_history_commands__086() {
+        let inputs = vec![("undo", Command::Undo), ("redo", Command::Redo), ("history", Command::History)];
 
-        let output = read_command();
+        for (input, expected) in inputs {
+            let output = command(input);
 
-        assert_eq!(expected, output);
+            assert_eq!(Ok(expected), output);
+        }
+    }
+
+    #[test]
+    fn test_parse_mode_command__106__107__108() {
+        {
+            let input: String = "mode rpn".to_owned();
+            let expected: Result<Command, CalcError> = Ok(Command::Mode(Mode::Rpn));
+
+            let output = command(&input);
+
+            assert_eq!(expected, output);
+        }
+        {
+            let input: String = "mode algebraic".to_owned();
+            let expected: Result<Command, CalcError> = Ok(Command::Mode(Mode::Algebraic));
+
+            let output = command(&input);
+
+            assert_eq!(expected, output);
+        }
+        {
+            let input: String = "mode hp".to_owned();
+            let expected: Result<Command, CalcError> = Err(CalcError::parse("Expecting a mode: rpn or algebraic", Span::new(5, 7)));
+
+            let output = command(&input);
+
+            assert_eq!(expected, output);
+        }
+    }
+
+    #[test]
+    fn test_parse_stack_commands__109() {
+        let inputs = vec![("swap", Command::Swap), ("dup", Command::Dup), ("drop", Command::Drop),
+                          ("roll", Command::Roll), ("stack", Command::Stack)];
+
+        for (input, expected) in inputs {
+            let output = command(input);
+
+            assert_eq!(Ok(expected), output);
+        }
+    }
+
+    #[test]
+    fn test_parse_symbolic_operators__117() {
+        let inputs = vec![("+", Command::Addition), ("-", Command::Subtraction), ("*", Command::Multiplication),
+                          ("/", Command::Division), ("^ 2", Command::Power(2.0))];
+
+        for (input, expected) in inputs {
+            let output = command(input);
+
+            assert_eq!(Ok(expected), output);
+        }
+    }
+
+    #[test]
+    fn test_parse_line_chain__118__119__120() {
+        {
+            let input = "3+4j * 2 - 1j";
+            let expected = Ok(vec![
+                Command::Number(Complex::new(3.0, 4.0)),
+                Command::Multiplication,
+                Command::Number(Complex::new(2.0, 0.0)),
+                Command::Subtraction,
+                Command::Number(Complex::new(0.0, 1.0)),
+            ]);
+
+            assert_eq!(expected, parse_line(input));
+        }
+        {
+            let input = "1+1j addition 2";
+            let expected = Ok(vec![
+                Command::Number(Complex::new(1.0, 1.0)),
+                Command::Addition,
+                Command::Number(Complex::new(2.0, 0.0)),
+            ]);
+
+            assert_eq!(expected, parse_line(input));
+        }
+        {
+            let input = "2*3^2";
+            let expected = Ok(vec![
+                Command::Number(Complex::new(2.0, 0.0)),
+                Command::Multiplication,
+                Command::Number(Complex::new(3.0, 0.0)),
+                Command::Power(2.0),
+            ]);
+
+            assert_eq!(expected, parse_line(input));
+        }
+    }
+
+    #[test]
+    fn test_parse_line_numbers__121__122() {
+        {
+            let input = "1 +1j";
+            let expected = Ok(vec![Command::Number(Complex::new(1.0, 1.0))]);
+
+            assert_eq!(expected, parse_line(input));
+        }
+        {
+            let input = "3 4 +";
+            let expected = Ok(vec![
+                Command::Number(Complex::new(3.0, 0.0)),
+                Command::Number(Complex::new(4.0, 0.0)),
+                Command::Addition,
+            ]);
+
+            assert_eq!(expected, parse_line(input));
+        }
+    }
+
+    #[test]
+    fn test_parse_line_errors__123__124__125() {
+        {
+            let expected: Result<Vec<Command>, CalcError> = Err(CalcError::parse("Please enter a command", Span::new(0, 3)));
+
+            assert_eq!(expected, parse_line("   "));
+        }
+        {
+            let expected: Result<Vec<Command>, CalcError> = Err(CalcError::parse("Unknown command: bad", Span::new(4, 7)));
+
+            assert_eq!(expected, parse_line("1 + bad"));
+        }
+        {
+            let expected: Result<Vec<Command>, CalcError> = Err(CalcError::parse("Expecting a number", Span::new(2, 3)));
+
+            assert_eq!(expected, parse_line("1 ^"));
+        }
+    }
+
+    #[test]
+    fn test_command_names__141() {
+        let names = command_names();
+
+        assert!(names.contains(&"multiplication".to_owned()));
+        assert!(names.contains(&"imaginary".to_owned()));
+        assert!(!names.contains(&"+".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_save_and_load__192__193__194() {
+        {
+            let expected = Ok(vec![Command::Save("/tmp/my session.cx".into())]);
+
+            assert_eq!(expected, parse_line("save /tmp/my session.cx "));
+        }
+        {
+            let expected = Ok(vec![Command::Load("session.cx".into())]);
+
+            assert_eq!(expected, parse_line("load session.cx"));
+        }
+        {
+            let expected: Result<Vec<Command>, CalcError> = Err(CalcError::parse("Expecting a file name", Span::new(0, 4)));
+
+            assert_eq!(expected, parse_line("save"));
+        }
+    }
+
+    #[test]
+    fn test_read_command__063() {
+        let mut source = LineSource::string("help\r\n1 + 2\n");
+
+        assert_eq!(Ok(vec![Command::Help]), read_command(&mut source));
+        assert_eq!(Ok(vec![Command::Number(Complex::new(1.0, 0.0)), Command::Addition,
+                           Command::Number(Complex::new(2.0, 0.0))]), read_command(&mut source));
+        assert_eq!(Ok(vec![Command::Exit]), read_command(&mut source));
+    }
+
+    #[test]
+    fn test_parse_help_topics__253__254() {
+        {
+            let expected = Ok(vec![Command::HelpOn("power".into())]);
+
+            assert_eq!(expected, parse_line("help ^"));
+        }
+        {
+            let expected: Result<Vec<Command>, CalcError> = Err(CalcError::parse("Unknown command: fly", Span::new(5, 8)));
+
+            assert_eq!(expected, parse_line("help fly"));
+        }
+    }
+
+    #[test]
+    fn test_parse_fuzzy_commands__260__261__262() {
+        {
+            let expected = Ok(vec![Command::Number(Complex::new(2.0, 0.0)), Command::Multiplication,
+                                   Command::Number(Complex::new(3.0, 0.0)), Command::Imaginary]);
+
+            assert_eq!(expected, parse_line("2 MULT 3 imag"));
+        }
+        {
+            let expected: Result<Vec<Command>, CalcError> =
+                Err(CalcError::parse("Unknown command: 2 multiply 3 (did you mean multiplication?)", Span::new(0, 12)));
+
+            assert_eq!(expected, parse_line("2 multiply 3"));
+        }
+        {
+            assert_eq!(Ok(vec![Command::Mode(Mode::Rpn)]), parse_line("Mode RPN"));
+        }
+    }
+
+    #[test]
+    fn test_parse_value_literals__282__283() {
+        {
+            let list = Value::List(vec![Complex::new(1.0, 0.0), Complex::new(2.0, 1.0)]);
+            let expected = Ok(vec![Command::Value(list), Command::Addition, Command::Number(Complex::new(3.0, 0.0)),
+                                   Command::Value(Value::Text("a b".into()))]);
+
+            assert_eq!(expected, parse_line("[1, 2+1j] + 3 \"a b\""));
+        }
+        {
+            let expected: Result<Vec<Command>, CalcError> = Err(CalcError::parse("Missing closing bracket", Span::new(2, 12)));
+
+            assert_eq!(expected, parse_line("1 [[1, 2], 3"));
+        }
+    }
+
+    #[test]
+    fn test_parse_list_commands__284__285() {
+        {
+            let condition = script::parse_condition("real >= 2").unwrap();
+            let expected = Ok(vec![Command::Filter(condition)]);
+
+            assert_eq!(expected, parse_line("filter real >= 2"));
+        }
+        {
+            let expected = Ok(vec![Command::Number(Complex::new(1.0, 0.0)),
+                                   Command::Map(vec![Command::Power(2.0), Command::Multiplication,
+                                                     Command::Number(Complex::new(3.0, 0.0))]),
+                                   ]);
+
+            assert_eq!(expected, parse_line("1 map power 2 * 3"));
+            assert_eq!(Ok(vec![Command::Reduce(Box::new(Command::Division))]), parse_line("reduce div"));
+            assert_eq!(Err(CalcError::parse("Expecting addition, subtraction, multiplication or division", Span::new(7, 11))),
+                       parse_line("reduce real"));
+        }
+    }
+
+    #[test]
+    fn test_parse_angles__295__296() {
+        {
+            assert_eq!(Ok(vec![Command::Phasor(2.0, 45.0, None)]), parse_line("2@45"));
+            assert_eq!(Ok(vec![Command::Phasor(1.0, -0.25, Some(Angle::Turns))]), parse_line("1 @ -0.25 turn"));
+            assert_eq!(Ok(vec![Command::Phasor(3.0, 100.0, Some(Angle::Gradians)), Command::Sin]), parse_line("3@100grad sin"));
+        }
+        {
+            let expected = Err(CalcError::parse("Expecting an angle unit: deg, rad, grad or turn", Span::new(6, 9)));
+
+            assert_eq!(Ok(vec![Command::Angle(Angle::Degrees)]), parse_line("angle DEG"));
+            assert_eq!(expected, parse_line("angle gon"));
+        }
+    }
+
+    #[test]
+    fn test_parse_format__309__310() {
+        {
+            let expected = Ok(vec![Command::Format(vec![FormatOption::Digits(5), FormatOption::Unit('i'),
+                                                         FormatOption::Zeros(true)])]);
+
+            assert_eq!(expected, parse_line("format 5 i zeros"));
+            assert_eq!(Ok(vec![Command::Format(Vec::new())]), parse_line("format"));
+        }
+        {
+            let expected = Err(CalcError::parse("Expecting digits, fixed, sci, eng, exact, rect, polar, exp, i, j, zeros, nozeros, group or nogroup",
+                                                Span::new(11, 15)));
+
+            assert_eq!(expected, parse_line("format eng deca"));
+        }
+    }
+
+    #[test]
+    fn test_parse_unicode_operators__339__340__341__342() {
+        let number = |re: f64, im: f64| Command::Number(Complex::new(re, im));
+
+        assert_eq!(Ok(vec![number(3.0, 0.0), Command::Multiplication, number(::std::f64::consts::PI, 0.0)]), parse_line("3·π"));
+        assert_eq!(Ok(vec![number(2.0, 0.0), Command::Multiplication, number(1.0, 1.0)]), parse_line("2×(1+ⅈ)"));
+        assert_eq!(Ok(vec![number(6.0, 0.0), Command::Division, number(-4.0, 0.0)]), parse_line("6 ÷ −4"));
+        assert_eq!(Ok(vec![Command::Phasor(2.0, 45.0, Some(Angle::Degrees))]), parse_line("2∠45°"));
+    }
+
+    #[test]
+    fn test_parse_unicode_powers_and_roots__343__344__345__346() {
+        let number = |re: f64, im: f64| Command::Number(Complex::new(re, im));
+
+        assert_eq!(Ok(vec![number(25.0, 0.0)]), parse_line("5²"));
+        assert_eq!(Ok(vec![Command::Number(Complex::new(1.0, 1.0).power(-12.0))]), parse_line("(1+ⅈ)⁻¹²"));
+        assert_eq!(Ok(vec![Command::Number(Complex::new(-4.0, 0.0).root(2.0))]), parse_line("√(−4)"));
+        assert_eq!(Ok(vec![number(2.0, 0.0), Command::Addition, number(1.0, 0.0)]), parse_line("∛8 + 1"));
+        assert_eq!(Ok(vec![Command::Map(vec![Command::Power(2.0)])]), parse_line("map ²"));
+    }
+
+    #[test]
+    fn test_unicode_in_strings_is_kept__347() {
+        assert_eq!(Ok(vec![Command::Value(Value::Text("3×π²".into()))]), parse_line("\"3×π²\""));
+    }
+
+    #[test]
+    fn test_spans_are_offsets_into_the_line__348() {
+        let spans: Vec<Option<Span>> = ["power   bad", "  mode\txx", "1  2  foo", "reduce   nothing"].iter()
+            .map(|input| match parse_line(input) {
+                Err(CalcError::Parse { span, .. }) => Some(span),
+                _ => None
+            })
+            .collect();
+        assert_eq!(spans, vec![Some(Span::new(8, 11)), Some(Span::new(7, 9)), Some(Span::new(0, 9)), Some(Span::new(9, 16))]);
+    }
+
+    #[test]
+    fn test_spans_after_repeated_spaces__349() {
+        match parse_line("map  power  x") {
+            Err(CalcError::Parse { span, .. }) => assert_eq!(span, Span::new(12, 13)),
+            other => panic!("unexpected {:?}", other)
+        }
+        match parse_line("filter   real  >  nope") {
+            Err(CalcError::Parse { span, .. }) => assert_eq!(span, Span::new(18, 22)),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_dialect_spans_point_into_the_input__351() {
+        let mut settings = Settings::default();
+        settings.dialect = Dialect::Python;
+        match parse_line_with("complex(1, 2) power x", &settings, 128) {
+            Err(CalcError::Parse { span, .. }) => assert_eq!(span, Span::new(20, 21)),
+            other => panic!("unexpected {:?}", other)
+        }
+        settings.dialect = Dialect::Mathematica;
+        match parse_line_with("{1, 2*I} mode 1.5*^-7", &settings, 128) {
+            Err(CalcError::Parse { span, .. }) => assert_eq!(span, Span::new(14, 21)),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_numbers_before_symbols_multiply__357__358() {
+        let number = |re: f64, im: f64| Command::Number(Complex::new(re, im));
+        let pi = ::std::f64::consts::PI;
+
+        assert_eq!(Ok(vec![number(3.0 * pi, 0.0)]), parse_line("3π"));
+        assert_eq!(Ok(vec![number(0.0, 2.0 * pi)]), parse_line("2πⅈ"));
+        assert_eq!(Ok(vec![number(1.5 * pi, 0.0)]), parse_line("1.5π"));
+        assert_eq!(Ok(vec![number(0.0, pi)]), parse_line("ⅈπ"));
+        assert_eq!(Ok(vec![number(2.0 * 3f64.sqrt(), 0.0)]), parse_line("2√3"));
+        assert_eq!(Ok(vec![number(2.0, 0.0), Command::Multiplication, number(9.0, 0.0)]), parse_line("2 × 3²"));
+        match parse_line("2√x") {
+            Err(CalcError::Parse { span, .. }) => assert_eq!(span, Span::new(0, 5)),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_symbol_spans_point_into_the_input__359() {
+        match parse_line("1 × foo") {
+            Err(CalcError::Parse { span, .. }) => assert_eq!(span, Span::new(5, 8)),
+            other => panic!("unexpected {:?}", other)
+        }
+        match parse_line("2π power x") {
+            Err(CalcError::Parse { span, .. }) => assert_eq!(span, Span::new(10, 11)),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_negative_bases__373() {
+        let number = |re: f64, im: f64| Command::Number(Complex::new(re, im));
+
+        assert_eq!(Ok(vec![number(-9.0, 0.0)]), parse_line("-3²"));
+        assert_eq!(Ok(vec![number(-9.0, 0.0), Command::Addition, number(0.0, 0.0)]), parse_line("−3² + 0"));
+        assert_eq!(Ok(vec![Command::Number(Complex::new(-3.0, 0.0).power(2.0))]), parse_line("(-3)²"));
+        assert_eq!(Ok(vec![number(1.0, 0.0), Command::Subtraction, number(9.0, 0.0)]), parse_line("1 - 3²"));
+        assert_eq!(Ok(vec![Command::Value(Value::List(vec![Complex::new(-4.0, 0.0), Complex::new(8.0, 0.0)]))]),
+                   parse_line("[-2², 2³]"));
+    }
+
+    #[test]
+    fn test_parse_nesting_limit__367() {
+        let expected = Err(CalcError::Limit("The input nests more than 3 levels".into()));
+
+        assert_eq!(expected, parse_nested("map map map 1", 3));
+        assert!(parse_nested("map map 1", 3).is_ok());
+        assert!(parse_nested("[[[[1]]]]", 3).is_err());
     }
 }
@@ -0,0 +1,94 @@
+use std::fmt::{Display, Formatter, Result};
+use complex::{Complex, shortest};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Dialect {
+    Native,
+    Python,
+    Matlab,
+    Mathematica,
+    C99,
+    Json
+}
+
+impl Dialect {
+    pub fn parse(name: &str) -> Option<Dialect> {
+        match name.to_lowercase().as_str() {
+            "native" => Some(Dialect::Native),
+            "python" => Some(Dialect::Python),
+            "matlab" => Some(Dialect::Matlab),
+            "mathematica" => Some(Dialect::Mathematica),
+            "c99" | "c" => Some(Dialect::C99),
+            "json" => Some(Dialect::Json),
+            _ => None
+        }
+    }
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            Dialect::Native => write!(f, "native"),
+            Dialect::Python => write!(f, "python"),
+            Dialect::Matlab => write!(f, "matlab"),
+            Dialect::Mathematica => write!(f, "mathematica"),
+            Dialect::C99 => write!(f, "c99"),
+            Dialect::Json => write!(f, "json"),
+        }
+    }
+}
+
+pub struct InDialect<'a> {
+    num: &'a Complex,
+    dialect: Dialect
+}
+
+impl Complex {
+    // Like Path::display, for use in format strings: format!("{}", num.display(Dialect::Python))
+    pub fn display<'a>(&'a self, dialect: Dialect) -> InDialect<'a> {
+        InDialect { num: self, dialect: dialect }
+    }
+}
+
+// C needs a point or an exponent to make a double literal
+fn c_number(x: f64) -> String {
+    let s = shortest(x);
+    if s.contains('.') || s.contains('e') { s } else { format!("{}.0", s) }
+}
+
+// Both parts are always written, at full precision, so the other side reads back the same number
+impl<'a> Display for InDialect<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let (r, i) = (self.num.real(), self.num.imaginary());
+        let sign = if i.is_sign_negative() { "-" } else { "+" };
+        match self.dialect {
+            Dialect::Native => write!(f, "{}", self.num.lossless()),
+            Dialect::Python => write!(f, "({}{}{}j)", shortest(r), sign, shortest(i.abs())),
+            Dialect::Matlab => write!(f, "{}{}{}i", shortest(r), sign, shortest(i.abs())),
+            Dialect::Mathematica => {
+                let part = |x: f64| shortest(x).replace('e', "*^");
+                write!(f, "{}{}{} I", part(r), sign, part(i.abs()))
+            },
+            Dialect::C99 => write!(f, "{}{}{}*I", c_number(r), sign, c_number(i.abs())),
+            Dialect::Json => write!(f, "{{\"re\":{},\"im\":{}}}", shortest(r), shortest(i)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_in_dialects__315() {
+        let num = Complex::new(1.5, -2.0);
+
+        assert_eq!("(1.5-2j)", num.display(Dialect::Python).to_string());
+        assert_eq!("1.5-2i", num.display(Dialect::Matlab).to_string());
+        assert_eq!("1.5-2 I", num.display(Dialect::Mathematica).to_string());
+        assert_eq!("1.5-2.0*I", num.display(Dialect::C99).to_string());
+        assert_eq!("{\"re\":1.5,\"im\":-2}", num.display(Dialect::Json).to_string());
+        assert_eq!("1*^-7+0 I", Complex::new(1e-7, 0.0).display(Dialect::Mathematica).to_string());
+        assert_eq!(Some(Dialect::C99), Dialect::parse("C"));
+    }
+}
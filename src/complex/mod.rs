@@ -1,9 +1,10 @@
 pub mod parser;
+pub mod dialect;
 
-use std::fmt::{Display, Formatter, Result};
-use std::ops::{Add, Sub, Mul};
+use std::fmt::{Alignment, Display, Formatter, LowerExp, Result, Write};
+use std::ops::{Add, Sub, Mul, Div};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Complex {
     real: f64,
     imaginary: f64,
@@ -30,6 +31,31 @@ impl Complex {
         self.imaginary
     }
 
+    // Angles are always in radians here, units are converted by the caller
+    pub fn from_polar(r: f64, theta: f64) -> Complex {
+        PolarComplex { r: r, theta: theta }.to_cartesian()
+    }
+
+    pub fn modulus(&self) -> f64 {
+        self.real.hypot(self.imaginary)
+    }
+
+    pub fn argument(&self) -> f64 {
+        self.imaginary.atan2(self.real)
+    }
+
+    pub fn sin(&self) -> Complex {
+        Complex::new(self.real.sin() * self.imaginary.cosh(), self.real.cos() * self.imaginary.sinh())
+    }
+
+    pub fn cos(&self) -> Complex {
+        Complex::new(self.real.cos() * self.imaginary.cosh(), -self.real.sin() * self.imaginary.sinh())
+    }
+
+    pub fn tan(&self) -> Complex {
+        self.sin() / self.cos()
+    }
+
     pub fn power(&self, n: f64) -> Complex {
         self.to_polar().power(n).to_cartesian()
     }
@@ -47,7 +73,7 @@ impl PolarComplex {
     pub fn new(real: f64, imaginary: f64) -> PolarComplex {
         PolarComplex {
             r: (real * real + imaginary * imaginary).sqrt(),
-            theta: (imaginary / real).atan()
+            theta: imaginary.atan2(real)
         }
     }
 
@@ -103,22 +129,102 @@ impl Mul for Complex {
     }
 }
 
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let denominator = other.real * other.real + other.imaginary * other.imaginary;
+        Complex::new((self.real * other.real + self.imaginary * other.imaginary) / denominator,
+                     (self.imaginary * other.real - self.real * other.imaginary) / denominator)
+    }
+}
+
+// Both forms print the fewest digits that read back as the same f64
+pub fn shortest(x: f64) -> String {
+    let plain = format!("{}", x);
+    let exponent = format!("{:e}", x);
+    if exponent.len() < plain.len() { exponent } else { plain }
+}
+
+impl Complex {
+    // Both parts are whole numbers, so they print without decimals
+    pub fn is_whole(&self) -> bool {
+        self.real % 1.0 == 0.0 && self.imaginary % 1.0 == 0.0
+    }
+
+    // Joins the formatted parts the way every output does: zero parts are left out unless zeros is set
+    pub fn rectangular<F: Fn(f64) -> String>(&self, part: F, unit: char, zeros: bool) -> String {
+        let (r, i) = (self.real, self.imaginary);
+        let sign = if i.is_sign_negative() { "-" } else { "+" };
+        match (r == 0.0, i == 0.0) {
+            _ if zeros => format!("{}{}{}{}", part(r), sign, part(i.abs()), unit),
+            (_, true) => part(r),
+            (true, false) => format!("{}{}", part(i), unit),
+            (false, false) => format!("{}{}{}{}", part(r), sign, part(i.abs()), unit),
+        }
+    }
+
+    // The shortest text that parser::parse_from_string reads back as exactly this number, signed zeros included
+    pub fn lossless(&self) -> String {
+        let (r, i) = (self.real, self.imaginary);
+        let positive_zero = |x: f64| x == 0.0 && x.is_sign_positive();
+        match (positive_zero(r), positive_zero(i)) {
+            (_, true) => shortest(r),
+            (true, false) => format!("{}j", shortest(i)),
+            (false, false) => {
+                let sign = if i.is_sign_negative() { "-" } else { "+" };
+                format!("{}{}{}j", shortest(r), sign, shortest(i.abs()))
+            }
+        }
+    }
+}
+
+// Honours width, fill, alignment and the + flag like the primitive types do
+fn pad(f: &mut Formatter, s: &str) -> Result {
+    let s = if f.sign_plus() && !s.starts_with('-') { format!("+{}", s) } else { s.to_owned() };
+    let len = s.chars().count();
+    let fill = match f.width() {
+        Some(width) if width > len => width - len,
+        _ => return f.write_str(&s)
+    };
+    let (before, after) = match f.align() {
+        Some(Alignment::Left) => (0, fill),
+        Some(Alignment::Center) => (fill / 2, fill - fill / 2),
+        _ => (fill, 0)
+    };
+    let c = f.fill();
+    for _ in 0..before {
+        f.write_char(c)?;
+    }
+    f.write_str(&s)?;
+    for _ in 0..after {
+        f.write_char(c)?;
+    }
+    Ok(())
+}
+
 impl Display for Complex {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        match *self {
-            Complex { real: r, imaginary: i } if i == 0.0 && r % 1.0 == 0.0 => write!(f, "{}", r),
-            Complex { real: r, imaginary: i } if i == 0.0 => write!(f, "{:.3}", r),
-            Complex { real: r, imaginary: i } if r == 0.0 && i % 1.0 == 0.0 => write!(f, "{}j", i),
-            Complex { real: r, imaginary: i } if r == 0.0 => write!(f, "{:.3}j", i),
-            Complex { real: r, imaginary: i } if i >= 0.0 && i % 1.0 == 0.0 && r % 1.0 == 0.0 => write!(f, "{}+{}j", r, i),
-            Complex { real: r, imaginary: i } if i >= 0.0 && i % 1.0 == 0.0 => write!(f, "{:.3}+{}j", r, i),
-            Complex { real: r, imaginary: i } if i >= 0.0 && r % 1.0 == 0.0 => write!(f, "{}+{:.3}j", r, i),
-            Complex { real: r, imaginary: i } if i >= 0.0 => write!(f, "{:.3}+{:.3}j", r, i),
-            Complex { real: r, imaginary: i } if i % 1.0 == 0.0 && r % 1.0 == 0.0 => write!(f, "{}{}j", r, i),
-            Complex { real: r, imaginary: i } if i % 1.0 == 0.0 => write!(f, "{:.3}{}j", r, i),
-            Complex { real: r, imaginary: i } if r % 1.0 == 0.0 => write!(f, "{}{:.3}j", r, i),
-            Complex { real: r, imaginary: i } => write!(f, "{:.3}{:.3}j", r, i),
-        }
+        // A whole number given a precision shows its decimals, as f64 does; otherwise whole parts stay bare
+        let s = match f.precision() {
+            Some(p) if self.is_whole() => self.rectangular(|x| format!("{:.*}", p, x), 'j', false),
+            p => {
+                let p = p.unwrap_or(3);
+                self.rectangular(|x| if x % 1.0 == 0.0 { format!("{}", x) } else { format!("{:.*}", p, x) }, 'j', false)
+            }
+        };
+        pad(f, &s)
+    }
+}
+
+impl LowerExp for Complex {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let p = f.precision();
+        let s = self.rectangular(|x| match p {
+            Some(p) => format!("{:.*e}", p, x),
+            None => format!("{:e}", x)
+        }, 'j', false);
+        pad(f, &s)
     }
 }
 
@@ -180,6 +286,16 @@ mod tests {
         assert_eq!(expected, input1 * input2);
     }
 
+    #[test]
+    fn test_div__126() {
+        let input1 = Complex::new(1.0, 3.0);
+        let input2 = Complex::new(1.0, 1.0);
+
+        let expected = Complex::new(2.0, 1.0);
+
+        assert_eq!(expected, input1 / input2);
+    }
+
     #[test]
     fn test_display_imaginary_0__006__007() {
         {
@@ -266,6 +382,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_display_precision__153__154() {
+        {
+            let input = Complex::new(1.5537739740300374, -0.6435942529055827);
+            let expected = "1.55-0.64j".to_owned();
+            assert_eq!(expected, format!("{:.2}", input));
+        }
+        {
+            let input = Complex::new(2.0, 0.6435942529055827);
+            let expected = "2+0.64359j".to_owned();
+            assert_eq!(expected, format!("{:.5}", input));
+        }
+    }
+
+    #[test]
+    fn test_argument__155() {
+        let input = Complex::new(-1.0, 1.0);
+        let expected = 3.0 * ::std::f64::consts::PI / 4.0;
+
+        assert_eq!(expected, input.argument());
+    }
+
     #[test]
     fn test_power__018() {
         let input = Complex::new(2.0, 2.0);
@@ -281,4 +419,78 @@ mod tests {
 
         assert_eq!(expected, input.root(2.0));
     }
+
+    #[test]
+    fn test_trig__293() {
+        let input = Complex::new(1.0, 1.0);
+
+        assert_eq!(Complex::new(1.2984575814159773, 0.6349639147847361), input.sin());
+        assert_eq!(Complex::new(0.8337300251311491, -0.9888977057628651), input.cos());
+        assert_eq!(Complex::new(0.0, 0.0), Complex::new(0.0, 0.0).tan());
+    }
+
+    #[test]
+    fn test_from_polar__294() {
+        let input = Complex::from_polar(2.0, ::std::f64::consts::FRAC_PI_2);
+
+        assert_eq!(2.0, input.modulus());
+        assert_eq!(::std::f64::consts::FRAC_PI_2, input.argument());
+    }
+
+    #[test]
+    fn test_display_flags__308() {
+        let input = Complex::new(1.25, -0.5);
+
+        assert_eq!("  1.2-0.5j", format!("{:>10.1}", input));
+        assert_eq!("1.250-0.500j**", format!("{:*<14}", input));
+        assert_eq!("+2j", format!("{:+}", Complex::new(0.0, 2.0)));
+        assert_eq!("1.25e0-5e-1j", format!("{:e}", input));
+    }
+
+    #[test]
+    fn test_lossless__312() {
+        assert_eq!("1.5537739740300374-0.1j", Complex::new(1.5537739740300374, -0.1).lossless());
+        assert_eq!("1e-300+1e21j", Complex::new(1e-300, 1e21).lossless());
+        assert_eq!("-0-0j", Complex::new(-0.0, -0.0).lossless());
+        assert_eq!("2.5j", Complex::new(0.0, 2.5).lossless());
+    }
+
+    // parse(format(z)) == z, bit for bit, over random bit patterns and short decimals
+    #[test]
+    fn test_lossless_round_trip_property__313() {
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut part = || loop {
+            let bits = next();
+            let x = match bits % 3 {
+                0 => f64::from_bits(next()),
+                1 => (next() % 2_000_001) as f64 / 10f64.powi((bits >> 8) as i32 % 8) - 100.0,
+                _ => [0.0, -0.0, ::std::f64::MIN_POSITIVE, 5e-324, ::std::f64::MAX, -1.0][(bits >> 8) as usize % 6]
+            };
+            if x.is_finite() {
+                return x;
+            }
+        };
+        for _ in 0..20000 {
+            let input = Complex::new(part(), part());
+            let text = input.lossless();
+
+            let output = parser::parse_from_string(text.clone()).unwrap();
+
+            assert_eq!((input.real().to_bits(), input.imaginary().to_bits()),
+                       (output.real().to_bits(), output.imaginary().to_bits()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_display_precision_of_whole_numbers__368() {
+        assert_eq!("1.00", format!("{:.2}", Complex::new(1.0, 0.0)));
+        assert_eq!("-3.00+4.00j", format!("{:.2}", Complex::new(-3.0, 4.0)));
+        assert_eq!("-3+4j", format!("{}", Complex::new(-3.0, 4.0)));
+    }
 }
@@ -1,5 +1,8 @@
 use complex::Complex;
-use regex::{Regex};
+use complex::dialect::Dialect;
+use regex::{Captures, Regex};
+use rewrite::Rewrite;
+use std::f64::consts::PI;
 
 // A one-liner horror story: ^\s*(?:(-?\d+(?:\.\d+)?)\s*(?:([\+-])\s*(\d+(?:\.\d+)?)j)?|(-?\d+(?:\.\d+)?)j\s*(?:([\+-])\s*(\d+(?:\.\d+)?))?)\s*$
 
@@ -31,14 +34,36 @@ fn build_from_strings(real_sign: &str, real: &str, imaginary_sign: &str, imagina
     )
 }
 
+// A run of π, ⅈ and ⅉ with an optional number in front, which multiplies them
+pub const TERM: &'static str = r"((?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)?([πⅈⅉ]+)";
+
+// The literal for one match of TERM, as in 2πⅈ for 6.283185307179586j
+pub fn term(caps: &Captures, first: usize) -> String {
+    let coefficient = caps.get(first).map_or(1.0, |m| m.as_str().parse::<f64>().unwrap());
+    let product = caps[first + 1].chars().fold(Complex::new(coefficient, 0.0), |num, symbol| match symbol {
+        'π' => Complex::new(num.real() * PI, num.imaginary() * PI),
+        _ => Complex::new(0.0 - num.imaginary(), num.real())
+    });
+    product.lossless()
+}
+
+// Unicode minus, ⅈ or ⅉ for the imaginary unit and π, as pasted from documents
+pub fn plain_symbols(text: &str) -> String {
+    lazy_static! {
+        static ref SYMBOLS: Regex = Regex::new(TERM).unwrap();
+    }
+
+    SYMBOLS.replace_all(&text.replace('−', "-"), |caps: &Captures| term(caps, 1)).into_owned()
+}
+
 pub fn parse_from_string(s: String) -> Result<Complex, String> {
     lazy_static! {
         static ref RE: Regex = Regex::new(
-            r"^\s*(?:(-?)(\d+(?:\.\d+)?)\s*(?:([\+-])\s*(\d+(?:\.\d+)?)j)?|(-?)(\d+(?:\.\d+)?)j\s*(?:([\+-])\s*(\d+(?:\.\d+)?))?)\s*$"
+            r"^\s*(?:(-?)(\d+(?:\.\d+)?(?:[eE][\+-]?\d+)?)\s*(?:([\+-])\s*(\d+(?:\.\d+)?(?:[eE][\+-]?\d+)?)j)?|(-?)(\d+(?:\.\d+)?(?:[eE][\+-]?\d+)?)j\s*(?:([\+-])\s*(\d+(?:\.\d+)?(?:[eE][\+-]?\d+)?))?)\s*$"
         ).unwrap();
     }
 
-    match RE.captures_iter(&s.to_owned()).next() {
+    match RE.captures_iter(&plain_symbols(&s)).next() {
         Some(cap) => {
             let groups = (
                 cap.get(1),
@@ -70,6 +95,73 @@ pub fn parse_from_string(s: String) -> Result<Complex, String> {
     }
 }
 
+fn number(caps: &Captures, i: usize) -> f64 {
+    caps.get(i).map_or(0.0, |m| m.as_str().parse::<f64>().unwrap())
+}
+
+// Rewrites one match of a "re sign im" or "im" pattern as a native literal; groups 1 to 3 are the
+// first alternative and group 4 the second
+fn literal(caps: &Captures) -> String {
+    match caps.get(4) {
+        Some(_) => Complex::new(0.0, number(caps, 4)).lossless(),
+        None => {
+            let im = number(caps, 3);
+            let im = if &caps[2] == "-" { -im } else { im };
+            Complex::new(number(caps, 1), im).lossless()
+        }
+    }
+}
+
+// Turns the numbers another tool writes into the native syntax, leaving everything else alone
+pub fn normalize(text: &str, dialect: Dialect) -> String {
+    rewrite(Rewrite::new(text), dialect).into_text()
+}
+
+pub fn rewrite(text: Rewrite, dialect: Dialect) -> Rewrite {
+    lazy_static! {
+        static ref PYTHON: Regex = Regex::new(
+            r"complex\(\s*([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*(?:,\s*([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*)?\)|\(\s*([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*([+-])\s*((?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)[jJ]\s*\)"
+        ).unwrap();
+        static ref MATLAB: Regex = Regex::new(
+            r"([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)([+-])((?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)[ij]\b|([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)[ij]\b"
+        ).unwrap();
+        static ref MATHEMATICA_EXPONENT: Regex = Regex::new(r"(\d)\*\^([+-]?\d)").unwrap();
+        static ref MATHEMATICA: Regex = Regex::new(
+            r"([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*([+-])\s*((?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*\*?\s*I\b|([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*\*?\s*I\b"
+        ).unwrap();
+        static ref C99: Regex = Regex::new(
+            r"([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*([+-])\s*((?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*\*\s*I\b|([+-]?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*\*\s*I\b"
+        ).unwrap();
+        static ref BRACE: Regex = Regex::new(r"[{}]").unwrap();
+        static ref JSON: Regex = Regex::new(
+            r#"\{\s*"re"\s*:\s*(-?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*,\s*"im"\s*:\s*(-?(?:\d+\.?\d*|\.\d+)(?:[eE][+-]?\d+)?)\s*\}"#
+        ).unwrap();
+    }
+
+    let brackets = |caps: &Captures| if &caps[0] == "{" { "[".to_owned() } else { "]".to_owned() };
+    match dialect {
+        Dialect::Native => text,
+        Dialect::Python => text.replace_all(&PYTHON, |caps: &Captures| match caps.get(1) {
+            Some(_) => Complex::new(number(caps, 1), number(caps, 2)).lossless(),
+            None => {
+                let im = number(caps, 5);
+                let im = if &caps[4] == "-" { -im } else { im };
+                Complex::new(number(caps, 3), im).lossless()
+            }
+        }),
+        Dialect::Matlab => text.replace_all(&MATLAB, literal),
+        Dialect::Mathematica => text.replace_all(&MATHEMATICA_EXPONENT, |caps: &Captures| format!("{}e{}", &caps[1], &caps[2]))
+            .replace_all(&MATHEMATICA, literal)
+            .replace_all(&BRACE, brackets),
+        Dialect::C99 => text.replace_all(&C99, literal).replace_all(&BRACE, brackets),
+        Dialect::Json => text.replace_all(&JSON, |caps: &Captures| Complex::new(number(caps, 1), number(caps, 2)).lossless()),
+    }
+}
+
+pub fn parse_in(s: &str, dialect: Dialect) -> Result<Complex, String> {
+    parse_from_string(normalize(s, dialect)).map_err(|_| s.to_owned())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -164,6 +256,60 @@ mod tests {
         let output = create_num(input_num, input_sign);
 
         assert_eq!(expected, output);
-    }        
+    }
+
+    #[test]
+    fn test_complex_number_parser_exponents__314() {
+        assert_eq!(Ok(Complex::new(1.5e-300, -2e21)), parse_from_string("1.5e-300-2E+21j".into()));
+        assert_eq!(Ok(Complex::new(0.0, 1e3)), parse_from_string("1e3j".into()));
+        assert!(parse_from_string("1e".into()).is_err());
+    }
+
+    #[test]
+    fn test_normalize_dialects__316__317__318() {
+        {
+            assert_eq!("1+2j * -0.5j", normalize("complex(1, 2) * complex(0,-0.5)", Dialect::Python));
+            assert_eq!("3 + 1-2.5j", normalize("3 + (1 - 2.5j)", Dialect::Python));
+            assert_eq!("1e-7+2j", normalize("1e-7+2i", Dialect::Matlab));
+            assert_eq!("sin 4j", normalize("sin 4i", Dialect::Matlab));
+        }
+        {
+            assert_eq!("1+2j", normalize("1 + 2 I", Dialect::Mathematica));
+            assert_eq!("1.5e-7j", normalize("1.5*^-7 I", Dialect::Mathematica));
+            assert_eq!("[1, 2j]", normalize("{1, 2*I}", Dialect::Mathematica));
+            assert_eq!("1-2j", normalize("1.0 - 2.0*I", Dialect::C99));
+        }
+        {
+            assert_eq!("-1+0.5j", normalize("{\"re\": -1, \"im\": 0.5}", Dialect::Json));
+            assert_eq!("1+2i", normalize("1+2i", Dialect::Native));
+        }
+    }
+
+    #[test]
+    fn test_dialects_read_back__319() {
+        let nums = [Complex::new(1.5, -2.0), Complex::new(-0.1, 1e-300), Complex::new(1e21, 3.0)];
+        let dialects = [Dialect::Python, Dialect::Matlab, Dialect::Mathematica, Dialect::C99, Dialect::Json];
+        for num in &nums {
+            for &dialect in &dialects {
+                assert_eq!(Ok(num.clone()), parse_in(&num.display(dialect).to_string(), dialect));
+            }
+        }
+    }
+
+    #[test]
+    fn test_unicode_literals__335__336__337__338() {
+        assert_eq!(Ok(Complex::new(-1.0, -2.5)), parse_from_string("−1−2.5j".into()));
+        assert_eq!(Ok(Complex::new(1.0, 2.0)), parse_from_string("1+2ⅈ".into()));
+        assert_eq!(Ok(Complex::new(0.0, 1.0)), parse_from_string("ⅉ".into()));
+        assert_eq!(Ok(Complex::new(::std::f64::consts::PI, 0.0)), parse_from_string("π".into()));
+    }
+
+    #[test]
+    fn test_symbols_next_to_numbers_multiply__355() {
+        assert_eq!(Ok(Complex::new(3.0 * PI, 0.0)), parse_from_string("3π".into()));
+        assert_eq!(Ok(Complex::new(1.5 * PI, 0.0)), parse_from_string("1.5π".into()));
+        assert_eq!(Ok(Complex::new(0.0, 2.0 * PI)), parse_from_string("2πⅈ".into()));
+        assert_eq!(Ok(Complex::new(0.0, PI)), parse_from_string("ⅈπ".into()));
+        assert_eq!(Ok(Complex::new(1.0, 2.0 * PI)), parse_from_string("1+2πⅈ".into()));
+    }
 }
-        
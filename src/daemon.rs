@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use state::{AppState, Settings};
+use session::Session;
+use event::{Event, render};
+use json::{Json, parse};
+use limits::Limits;
+use input::{Source, LineSource};
+use rpc::{Handler, INVALID_PARAMS, response, rpc_error, state_json, string_param};
+
+pub struct Registry {
+    initial: AppState,
+    limits: Limits,
+    sessions: Mutex<HashMap<String, Arc<Mutex<Session>>>>
+}
+
+impl Registry {
+    pub fn new(initial: AppState) -> Registry {
+        Registry {
+            initial: initial,
+            limits: Limits::default(),
+            sessions: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Registry {
+        self.limits = limits;
+        self
+    }
+
+    fn session(&self) -> Session {
+        Session::new(self.initial.clone()).with_limits(self.limits)
+    }
+
+    pub fn initial(&self) -> &AppState {
+        &self.initial
+    }
+
+    pub fn attach(&self, name: &str) -> Arc<Mutex<Session>> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.entry(name.to_owned())
+            .or_insert_with(|| Arc::new(Mutex::new(self.session())))
+            .clone()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let mut names: Vec<String> = sessions.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+fn with_session<T, F>(own: &mut Session, shared: &Option<(String, Arc<Mutex<Session>>)>, f: F) -> T
+    where F: FnOnce(&mut Session) -> T {
+    match *shared {
+        Some((_, ref session)) => f(&mut session.lock().unwrap_or_else(|e| e.into_inner())),
+        None => f(own)
+    }
+}
+
+struct Connection<'a> {
+    registry: &'a Registry,
+    own: Session,
+    shared: Option<(String, Arc<Mutex<Session>>)>,
+    handler: Handler,
+    finished: bool
+}
+
+impl<'a> Connection<'a> {
+    fn new(registry: &'a Registry) -> Connection<'a> {
+        Connection {
+            registry: registry,
+            own: registry.session(),
+            shared: None,
+            handler: Handler::new(registry.initial().clone()),
+            finished: false
+        }
+    }
+
+    fn attach(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() || name.chars().any(char::is_whitespace) {
+            return Err("Expecting a session name".into());
+        }
+        self.shared = Some((name.to_owned(), self.registry.attach(name)));
+        Ok(())
+    }
+
+    fn plain(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("attach"), name, None) => return match self.attach(name.unwrap_or("")) {
+                Ok(()) => format!("Attached to session {}\nOK", name.unwrap()),
+                Err(msg) => format!("ERR E002 {}", msg)
+            },
+            (Some("detach"), None, _) => {
+                self.shared = None;
+                return "Detached\nOK".into();
+            },
+            (Some("sessions"), None, _) => {
+                let mut lines = self.registry.names();
+                lines.push("OK".into());
+                return lines.join("\n");
+            },
+            _ => ()
+        }
+        let (events, settings): (Vec<Event>, Settings) = with_session(&mut self.own, &self.shared, |session| {
+            let events = session.eval_line(line);
+            (events, session.state().settings)
+        });
+        let mut lines = Vec::new();
+        let mut status = "OK".to_owned();
+        for event in &events {
+            match *event {
+                Event::Exit => self.finished = true,
+                Event::Error(ref e) => status = format!("ERR {} {}", e.code(), e),
+                ref event => lines.push(render(event, &settings))
+            }
+        }
+        lines.push(status);
+        lines.join("\n")
+    }
+
+    fn control(&mut self, request: &Json) -> Option<Result<Json, Json>> {
+        let params = request.get("params");
+        let result = match request.get("method").and_then(Json::as_str) {
+            Some("attach") => match string_param(params, "name", 0).and_then(|name| {
+                self.attach(&name).map_err(|msg| rpc_error(INVALID_PARAMS, &msg, None))
+            }) {
+                Ok(()) => Ok(()),
+                Err(error) => return Some(Err(error))
+            },
+            Some("detach") => {
+                self.shared = None;
+                Ok(())
+            },
+            Some("sessions") => {
+                let names = self.registry.names().into_iter().map(Json::String).collect();
+                return Some(Ok(Json::object(vec![("sessions", Json::Array(names))])));
+            },
+            _ => return None
+        };
+        let name = self.shared.as_ref().map_or(Json::Null, |&(ref name, _)| Json::string(name.as_str()));
+        let state = with_session(&mut self.own, &self.shared, |session| state_json(session.state()));
+        Some(result.map(|_| Json::object(vec![("session", name), ("state", state)])))
+    }
+
+    fn json(&mut self, request: Json) -> Option<String> {
+        let id = request.get("id").cloned();
+        let output = match self.control(&request) {
+            Some(_) if id.is_none() => None,
+            Some(result) => Some(response(id, result)),
+            None => {
+                let handler = &mut self.handler;
+                with_session(&mut self.own, &self.shared, |session| handler.handle(session, &request))
+            }
+        };
+        if self.handler.is_finished() {
+            self.finished = true;
+        }
+        output.map(|r| format!("{}", r))
+    }
+
+    // List literals also start with '[', so only a request object or a batch of them is JSON-RPC
+    fn handle_line(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        if !line.starts_with('{') && !line.starts_with('[') {
+            return Some(self.plain(line));
+        }
+        match parse(line, self.registry.limits.max_depth) {
+            Ok(request) if is_request(&request) => self.json(request),
+            _ => Some(self.plain(line))
+        }
+    }
+}
+
+fn is_request(json: &Json) -> bool {
+    match *json {
+        Json::Object(_) => true,
+        Json::Array(ref requests) => !requests.is_empty() && requests.iter().all(|r| match *r {
+            Json::Object(_) => true,
+            _ => false
+        }),
+        _ => false
+    }
+}
+
+pub fn handle_connection<R: BufRead, W: Write>(registry: &Registry, input: R, mut output: W) -> io::Result<()> {
+    let mut connection = Connection::new(registry);
+    let mut input = LineSource::new(input).with_limits(registry.limits);
+    while !connection.finished {
+        let response = match input.next_line() {
+            Ok(None) => break,
+            Ok(Some(ref line)) if line.trim().is_empty() => continue,
+            Ok(Some(line)) => connection.handle_line(&line),
+            Err(e) => Some(format!("ERR {} {}", e.code(), e))
+        };
+        if let Some(response) = response {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    // Only a stale socket is replaced; anything else at the path is left alone
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the path exists and is not a socket"));
+        },
+        Ok(_) => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "another server is listening on this socket"));
+            }
+            fs::remove_file(path)?;
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e)
+    }
+    UnixListener::bind(path)
+}
+
+pub fn serve(listener: UnixListener, registry: Arc<Registry>) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        let registry = registry.clone();
+        thread::spawn(move || {
+            let _ = stream.try_clone()
+                .and_then(|reader| handle_connection(&registry, BufReader::new(reader), stream));
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::io::Read;
+
+    fn converse(registry: &Registry, input: &str) -> String {
+        let mut output = Vec::new();
+        handle_connection(registry, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_plain_protocol__223__224() {
+        let registry = Registry::new(AppState::default());
+        {
+            let expected = "3+1j\nOK\nERR E002 Unknown command: bad\n";
+
+            assert_eq!(expected, converse(&registry, "1+1j + 2\n\nbad\n"));
+        }
+        {
+            let expected = "OK\n";
+
+            assert_eq!(expected, converse(&registry, "exit\n1\n"));
+        }
+    }
+
+    #[test]
+    fn test_connections_are_isolated_unless_attached__225__226() {
+        let registry = Registry::new(AppState::default());
+        {
+            converse(&registry, "5\n");
+
+            assert_eq!("ERR E001 You must insert a number first\n", converse(&registry, "real\n"));
+        }
+        {
+            converse(&registry, "attach work\n5\n");
+
+            assert_eq!("Attached to session work\nOK\n5\nOK\n", converse(&registry, "attach work\nreal\n"));
+            assert_eq!(vec!["work".to_owned()], registry.names());
+        }
+    }
+
+    #[test]
+    fn test_json_protocol__227() {
+        let registry = Registry::new(AppState::default());
+        let input = "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"attach\", \"params\": [\"x\"]}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"eval\", \"params\": [\"2\"]}\n";
+
+        let output = converse(&registry, input);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"session\":\"x\",\"state\":"));
+        assert!(lines[1].starts_with("{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{\"events\":[]"));
+        assert_eq!(Some(&::complex::Complex::new(2.0, 0.0).into()), registry.attach("x").lock().unwrap().state().result());
+    }
+
+    #[test]
+    fn test_list_literals_use_the_plain_protocol__372() {
+        let registry = Registry::new(AppState::default());
+        let input = "[1, 2, 3] + 1\n[{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"eval\", \"params\": [\"2\"]}]\n";
+
+        let output = converse(&registry, input);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(&["[2, 3, 4]", "OK"], &lines[..2]);
+        assert!(lines[2].starts_with("[{\"jsonrpc\":\"2.0\",\"id\":1,\"result\""), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_serve_over_socket__228() {
+        let path = env::temp_dir().join(format!("complex_daemon_test_{}.sock", process::id()));
+        let listener = bind(&path).unwrap();
+        let registry = Arc::new(Registry::new(AppState::default()));
+        thread::spawn(move || serve(listener, registry));
+
+        let mut first = UnixStream::connect(&path).unwrap();
+        let mut second = BufReader::new(UnixStream::connect(&path).unwrap());
+        first.write_all(b"attach shared\n7\nexit\n").unwrap();
+        let mut first = BufReader::new(first);
+        let mut output = String::new();
+        while !output.ends_with("OK\nOK\n") {
+            assert!(first.read_line(&mut output).unwrap() > 0);
+        }
+        second.get_mut().write_all(b"attach shared\nreal\nexit\n").unwrap();
+        let mut output = String::new();
+        while !output.ends_with("OK\n7\nOK\n") {
+            assert!(second.read_line(&mut output).unwrap() > 0);
+        }
+
+        assert!(bind(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_bind_keeps_other_files__371() {
+        let path = env::temp_dir().join(format!("complex_daemon_file_{}.sock", process::id()));
+        fs::File::create(&path).unwrap().write_all(b"keep").unwrap();
+
+        assert_eq!(io::ErrorKind::AlreadyExists, bind(&path).unwrap_err().kind());
+        let mut content = String::new();
+        fs::File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!("keep", content);
+    }
+
+    #[test]
+    fn test_hostile_input_over_socket__364() {
+        let path = env::temp_dir().join(format!("complex_daemon_hostile_{}.sock", process::id()));
+        let listener = bind(&path).unwrap();
+        let registry = Arc::new(Registry::new(AppState::default()));
+        thread::spawn(move || serve(listener, registry));
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        let hostile = format!("{}\n{}\n{}real\n2 {}\n", "[".repeat(5000), "{\"a\":".repeat(5000), "map ".repeat(5000),
+                              "[".repeat(5000));
+        stream.write_all(hostile.as_bytes()).unwrap();
+        stream.write_all(b"7\nreal\nexit\n").unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert!(lines[0].starts_with("ERR E002"), "{}", lines[0]);
+        assert!(lines[1].starts_with("ERR E002"), "{}", lines[1]);
+        assert!(lines[2].starts_with("ERR E007"), "{}", lines[2]);
+        assert!(lines[3].starts_with("ERR E002"), "{}", lines[3]);
+        assert_eq!(&["OK", "7", "OK", "OK"], &lines[4..]);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,673 @@
+use std::io::{self, Read, Write, BufRead, BufReader, Stdin, stdout};
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::str;
+use complex::error::CalcError;
+use complex::input::{Source, LineSource};
+
+pub const HISTORY_FILE: &'static str = ".complex_history";
+pub const HISTORY_SIZE: usize = 1000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    CtrlD,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    KillToEnd,
+    KillToStart,
+    KillWordBack,
+    KillWordForward,
+    Yank,
+    Transpose,
+    ClearScreen,
+    Interrupt,
+    Unknown
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+fn read_escape<R: Read>(input: &mut R) -> io::Result<Key> {
+    Ok(match read_byte(input)? {
+        Some(b'[') | Some(b'O') => match read_byte(input)? {
+            Some(b'A') => Key::Up,
+            Some(b'B') => Key::Down,
+            Some(b'C') => Key::Right,
+            Some(b'D') => Key::Left,
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            Some(d) if (d as char).is_digit(10) => {
+                let mut code = vec![d];
+                loop {
+                    match read_byte(input)? {
+                        Some(b'~') => break,
+                        Some(c) if (c as char).is_digit(10) || c == b';' => code.push(c),
+                        _ => return Ok(Key::Unknown)
+                    }
+                }
+                match &code[..] {
+                    b"1" | b"7" => Key::Home,
+                    b"4" | b"8" => Key::End,
+                    b"3" => Key::Delete,
+                    _ => Key::Unknown
+                }
+            },
+            _ => Key::Unknown
+        },
+        Some(b'b') => Key::WordLeft,
+        Some(b'f') => Key::WordRight,
+        Some(b'd') => Key::KillWordForward,
+        _ => Key::Unknown
+    })
+}
+
+fn read_char<R: Read>(first: u8, input: &mut R) -> io::Result<Key> {
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(Key::Unknown)
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(b) => bytes.push(b),
+            None => break
+        }
+    }
+    Ok(match str::from_utf8(&bytes) {
+        Ok(s) => s.chars().next().map(Key::Char).unwrap_or(Key::Unknown),
+        Err(_) => Key::Unknown
+    })
+}
+
+pub fn read_key<R: Read>(input: &mut R) -> io::Result<Option<Key>> {
+    let key = match read_byte(input)? {
+        None => return Ok(None),
+        Some(1) => Key::Home,
+        Some(2) => Key::Left,
+        Some(3) => Key::Interrupt,
+        Some(4) => Key::CtrlD,
+        Some(5) => Key::End,
+        Some(6) => Key::Right,
+        Some(8) | Some(127) => Key::Backspace,
+        Some(9) => Key::Tab,
+        Some(10) | Some(13) => Key::Enter,
+        Some(11) => Key::KillToEnd,
+        Some(12) => Key::ClearScreen,
+        Some(14) => Key::Down,
+        Some(16) => Key::Up,
+        Some(20) => Key::Transpose,
+        Some(21) => Key::KillToStart,
+        Some(23) => Key::KillWordBack,
+        Some(25) => Key::Yank,
+        Some(27) => read_escape(input)?,
+        Some(b) if b < 32 => Key::Unknown,
+        Some(b) => read_char(b, input)?,
+    };
+    Ok(Some(key))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+    killed: Vec<char>
+}
+
+impl LineBuffer {
+    pub fn new() -> LineBuffer {
+        LineBuffer::from("")
+    }
+
+    pub fn from(text: &str) -> LineBuffer {
+        let chars: Vec<char> = text.chars().collect();
+        LineBuffer {
+            cursor: chars.len(),
+            chars: chars,
+            killed: Vec::new()
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.insert(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+        }
+    }
+
+    pub fn right(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.cursor += 1;
+        }
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    fn word_start(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos > 0 && self.chars[pos - 1].is_whitespace() {
+            pos -= 1;
+        }
+        while pos > 0 && !self.chars[pos - 1].is_whitespace() {
+            pos -= 1;
+        }
+        pos
+    }
+
+    fn word_end(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos < self.chars.len() && self.chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        while pos < self.chars.len() && !self.chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        pos
+    }
+
+    pub fn word_left(&mut self) {
+        self.cursor = self.word_start();
+    }
+
+    pub fn word_right(&mut self) {
+        self.cursor = self.word_end();
+    }
+
+    pub fn kill_to_end(&mut self) {
+        self.killed = self.chars.split_off(self.cursor);
+    }
+
+    pub fn kill_to_start(&mut self) {
+        self.killed = self.chars.drain(..self.cursor).collect();
+        self.cursor = 0;
+    }
+
+    pub fn kill_word_back(&mut self) {
+        let start = self.word_start();
+        self.killed = self.chars.drain(start..self.cursor).collect();
+        self.cursor = start;
+    }
+
+    pub fn kill_word_forward(&mut self) {
+        let end = self.word_end();
+        self.killed = self.chars.drain(self.cursor..end).collect();
+    }
+
+    pub fn yank(&mut self) {
+        let killed = self.killed.clone();
+        for c in killed {
+            self.insert(c);
+        }
+    }
+
+    pub fn transpose(&mut self) {
+        if self.cursor == 0 || self.chars.len() < 2 {
+            return;
+        }
+        if self.cursor == self.chars.len() {
+            self.cursor -= 1;
+        }
+        self.chars.swap(self.cursor - 1, self.cursor);
+        self.cursor += 1;
+    }
+
+    pub fn current_word(&self) -> String {
+        let mut start = self.cursor;
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars[start..self.cursor].iter().collect()
+    }
+}
+
+pub fn complete(prefix: &str, candidates: &[String]) -> Vec<String> {
+    let mut matches: Vec<String> = candidates.iter()
+        .filter(|c| c.starts_with(prefix))
+        .cloned()
+        .collect();
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+fn common_prefix(words: &[String]) -> String {
+    match words.first() {
+        None => String::new(),
+        Some(first) => {
+            let mut prefix: String = first.clone();
+            for word in &words[1..] {
+                while !word.starts_with(&prefix[..]) {
+                    prefix.pop();
+                }
+            }
+            prefix
+        }
+    }
+}
+
+#[cfg(unix)]
+mod tty {
+    use std::io;
+    use std::mem;
+    use libc;
+
+    pub fn is_tty() -> bool {
+        unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+    }
+
+    pub fn is_stdin_tty() -> bool {
+        unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+    }
+
+    pub struct RawMode {
+        original: libc::termios
+    }
+
+    impl RawMode {
+        pub fn enable() -> io::Result<RawMode> {
+            unsafe {
+                let mut term: libc::termios = mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let original = term;
+                term.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+                term.c_cflag |= libc::CS8;
+                term.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+                term.c_cc[libc::VMIN] = 1;
+                term.c_cc[libc::VTIME] = 0;
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &term) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(RawMode { original: original })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod tty {
+    use std::io;
+
+    pub fn is_tty() -> bool {
+        false
+    }
+
+    pub fn is_stdin_tty() -> bool {
+        true
+    }
+
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> io::Result<RawMode> {
+            Err(io::Error::new(io::ErrorKind::Other, "raw mode is not supported"))
+        }
+    }
+}
+
+pub fn is_stdin_tty() -> bool {
+    tty::is_stdin_tty()
+}
+
+fn refresh<W: Write>(out: &mut W, prompt: &str, buffer: &LineBuffer) -> io::Result<()> {
+    write!(out, "\r{}{}\x1b[K\r", prompt, buffer.text())?;
+    let column = prompt.chars().count() + buffer.cursor();
+    if column > 0 {
+        write!(out, "\x1b[{}C", column)?;
+    }
+    out.flush()
+}
+
+pub struct Editor {
+    interactive: bool,
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+    completer: Box<dyn Fn(&str) -> Vec<String>>,
+    prompt: String,
+    fallback: LineSource<BufReader<Stdin>>
+}
+
+impl Editor {
+    pub fn new(history_path: Option<PathBuf>, completer: Box<dyn Fn(&str) -> Vec<String>>) -> Editor {
+        let mut editor = Editor {
+            interactive: tty::is_tty(),
+            history: Vec::new(),
+            history_path: history_path,
+            completer: completer,
+            prompt: ">>> ".to_owned(),
+            fallback: LineSource::stdin()
+        };
+        editor.load_history();
+        editor
+    }
+
+    fn load_history(&mut self) {
+        let path = match self.history_path {
+            Some(ref path) => path.clone(),
+            None => return
+        };
+        if let Ok(file) = File::open(&path) {
+            self.history = BufReader::new(file).lines()
+                .filter_map(|line| line.ok())
+                .filter(|line| !line.trim().is_empty())
+                .collect();
+        }
+        if self.history.len() > HISTORY_SIZE {
+            let excess = self.history.len() - HISTORY_SIZE;
+            self.history.drain(..excess);
+            if let Ok(mut file) = File::create(&path) {
+                for line in &self.history {
+                    let _ = writeln!(file, "{}", line);
+                }
+            }
+        }
+    }
+
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = prompt.to_owned();
+    }
+
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(|l| l == line).unwrap_or(false) {
+            return;
+        }
+        self.history.push(line.to_owned());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        if let Some(ref path) = self.history_path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let _raw = tty::RawMode::enable()?;
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+
+        let mut buffer = LineBuffer::new();
+        let mut index = self.history.len();
+        let mut draft = String::new();
+        refresh(&mut out, prompt, &buffer)?;
+        loop {
+            let key = match read_key(&mut input)? {
+                Some(key) => key,
+                None => {
+                    writeln!(out)?;
+                    return Ok(None);
+                }
+            };
+            match key {
+                Key::Enter => {
+                    writeln!(out)?;
+                    out.flush()?;
+                    let line = buffer.text();
+                    self.add_history(&line);
+                    return Ok(Some(line));
+                },
+                Key::CtrlD if buffer.is_empty() => {
+                    writeln!(out)?;
+                    out.flush()?;
+                    return Ok(None);
+                },
+                Key::Interrupt => {
+                    writeln!(out, "^C")?;
+                    buffer = LineBuffer::new();
+                    index = self.history.len();
+                },
+                Key::Up => if index > 0 {
+                    if index == self.history.len() {
+                        draft = buffer.text();
+                    }
+                    index -= 1;
+                    buffer = LineBuffer::from(&self.history[index]);
+                },
+                Key::Down => if index < self.history.len() {
+                    index += 1;
+                    buffer = if index == self.history.len() {
+                        LineBuffer::from(&draft)
+                    } else {
+                        LineBuffer::from(&self.history[index])
+                    };
+                },
+                Key::Tab => {
+                    let word = buffer.current_word();
+                    let matches = complete(&word, &(self.completer)(&word));
+                    if matches.len() == 1 {
+                        buffer.insert_str(&matches[0][word.len()..]);
+                        buffer.insert(' ');
+                    } else if matches.len() > 1 {
+                        let prefix = common_prefix(&matches);
+                        if prefix.len() > word.len() {
+                            buffer.insert_str(&prefix[word.len()..]);
+                        } else {
+                            write!(out, "\n{}\n", matches.join("  "))?;
+                        }
+                    }
+                },
+                Key::ClearScreen => write!(out, "\x1b[H\x1b[2J")?,
+                Key::Char(c) => buffer.insert(c),
+                Key::Backspace => buffer.backspace(),
+                Key::Delete | Key::CtrlD => buffer.delete(),
+                Key::Left => buffer.left(),
+                Key::Right => buffer.right(),
+                Key::Home => buffer.home(),
+                Key::End => buffer.end(),
+                Key::WordLeft => buffer.word_left(),
+                Key::WordRight => buffer.word_right(),
+                Key::KillToEnd => buffer.kill_to_end(),
+                Key::KillToStart => buffer.kill_to_start(),
+                Key::KillWordBack => buffer.kill_word_back(),
+                Key::KillWordForward => buffer.kill_word_forward(),
+                Key::Yank => buffer.yank(),
+                Key::Transpose => buffer.transpose(),
+                Key::Unknown => {}
+            }
+            refresh(&mut out, prompt, &buffer)?;
+        }
+    }
+}
+
+impl Source for Editor {
+    fn next_line(&mut self) -> Result<Option<String>, CalcError> {
+        let prompt = self.prompt.clone();
+        if self.interactive {
+            return self.read_line(&prompt).map_err(|e| CalcError::Io(format!("Input error: {}", e)));
+        }
+        print!("{}", prompt);
+        {
+            let _ = stdout().flush();
+        }
+        self.fallback.next_line()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn keys(input: &[u8]) -> Vec<Key> {
+        let mut reader = input;
+        let mut output = Vec::new();
+        while let Some(key) = read_key(&mut reader).unwrap() {
+            output.push(key);
+        }
+        output
+    }
+
+    #[test]
+    fn test_read_control_keys__131() {
+        let input = b"\x01\x05\x02\x06\x0b\x15\x17\x19\x14\x7f\t\r";
+        let expected = vec![Key::Home, Key::End, Key::Left, Key::Right, Key::KillToEnd, Key::KillToStart,
+                            Key::KillWordBack, Key::Yank, Key::Transpose, Key::Backspace, Key::Tab, Key::Enter];
+
+        assert_eq!(expected, keys(input));
+    }
+
+    #[test]
+    fn test_read_escape_sequences__132() {
+        let input = b"\x1b[A\x1b[B\x1b[C\x1b[D\x1b[3~\x1b[1~\x1b[4~\x1bb\x1bf";
+        let expected = vec![Key::Up, Key::Down, Key::Right, Key::Left, Key::Delete, Key::Home, Key::End,
+                            Key::WordLeft, Key::WordRight];
+
+        assert_eq!(expected, keys(input));
+    }
+
+    #[test]
+    fn test_read_utf8_chars__133() {
+        let input = "a\u{3c0}".as_bytes();
+        let expected = vec![Key::Char('a'), Key::Char('\u{3c0}')];
+
+        assert_eq!(expected, keys(input));
+    }
+
+    #[test]
+    fn test_line_buffer_editing__134__135__136() {
+        {
+            let mut buffer = LineBuffer::from("1+1j addition");
+            buffer.home();
+            buffer.right();
+            buffer.insert('0');
+            buffer.end();
+            buffer.backspace();
+
+            assert_eq!("10+1j additio", buffer.text());
+        }
+        {
+            let mut buffer = LineBuffer::from("1+1j addition");
+            buffer.kill_word_back();
+            buffer.home();
+            buffer.yank();
+
+            assert_eq!("addition1+1j ", buffer.text());
+        }
+        {
+            let mut buffer = LineBuffer::from("power 2");
+            buffer.word_left();
+            buffer.kill_to_start();
+            buffer.end();
+            buffer.transpose();
+
+            assert_eq!("2", buffer.text());
+        }
+    }
+
+    #[test]
+    fn test_current_word__137() {
+        let mut buffer = LineBuffer::from("1 mul");
+
+        assert_eq!("mul", buffer.current_word());
+
+        buffer.left();
+        assert_eq!("mu", buffer.current_word());
+    }
+
+    #[test]
+    fn test_complete__138__139() {
+        let candidates: Vec<String> = vec!["redo", "real", "root", "roll"].into_iter().map(String::from).collect();
+        {
+            let expected: Vec<String> = vec!["real".to_owned(), "redo".to_owned()];
+
+            assert_eq!(expected, complete("re", &candidates));
+        }
+        {
+            assert_eq!("re", common_prefix(&complete("re", &candidates)));
+            assert_eq!("roll", common_prefix(&complete("rol", &candidates)));
+        }
+    }
+
+    #[test]
+    fn test_history_is_persisted__140() {
+        let path = env::temp_dir().join(format!("complex_history_test_{}", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        {
+            let mut editor = Editor::new(Some(path.clone()), Box::new(|_| Vec::new()));
+            editor.add_history("1+1j");
+            editor.add_history("1+1j");
+            editor.add_history("  ");
+            editor.add_history("addition");
+        }
+        let editor = Editor::new(Some(path.clone()), Box::new(|_| Vec::new()));
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(vec!["1+1j".to_owned(), "addition".to_owned()], editor.history);
+    }
+}
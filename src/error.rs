@@ -0,0 +1,160 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
+use state::AppState;
+use complex::Complex;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span {
+            start: start,
+            end: end
+        }
+    }
+
+    pub fn shift(self, offset: usize) -> Span {
+        Span::new(self.start + offset, self.end + offset)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CalcError {
+    MissingOperand(String),
+    Parse { message: String, span: Span },
+    Domain(String),
+    Overflow(String),
+    Unavailable(String),
+    Io(String),
+    Limit(String),
+    Cancelled(String),
+    At { file: String, line: usize, error: Box<CalcError> }
+}
+
+pub type CalcResult = Result<AppState, (AppState, CalcError)>;
+
+impl CalcError {
+    pub fn parse<S: Into<String>>(message: S, span: Span) -> CalcError {
+        CalcError::Parse {
+            message: message.into(),
+            span: span
+        }
+    }
+
+    // Errors keep the innermost location, so a failure inside a sourced file points there
+    pub fn at<S: Into<String>>(file: S, line: usize, error: CalcError) -> CalcError {
+        match error {
+            at @ CalcError::At { .. } => at,
+            error => CalcError::At {
+                file: file.into(),
+                line: line,
+                error: Box::new(error)
+            }
+        }
+    }
+
+    pub fn location(&self) -> Option<(&str, usize)> {
+        match *self {
+            CalcError::At { ref file, line, .. } => Some((file, line)),
+            _ => None
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match *self {
+            CalcError::MissingOperand(_) => "E001",
+            CalcError::Parse { .. } => "E002",
+            CalcError::Domain(_) => "E003",
+            CalcError::Overflow(_) => "E004",
+            CalcError::Unavailable(_) => "E005",
+            CalcError::Io(_) => "E006",
+            CalcError::Limit(_) => "E007",
+            CalcError::Cancelled(_) => "E008",
+            CalcError::At { ref error, .. } => error.code(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match *self {
+            CalcError::MissingOperand(ref msg) |
+            CalcError::Parse { message: ref msg, .. } |
+            CalcError::Domain(ref msg) |
+            CalcError::Overflow(ref msg) |
+            CalcError::Unavailable(ref msg) |
+            CalcError::Io(ref msg) |
+            CalcError::Limit(ref msg) |
+            CalcError::Cancelled(ref msg) => msg,
+            CalcError::At { ref error, .. } => error.message()
+        }
+    }
+}
+
+impl Display for CalcError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.location() {
+            Some((file, line)) => write!(f, "{}:{}: {}", file, line, self.message()),
+            None => write!(f, "{}", self.message())
+        }
+    }
+}
+
+impl Error for CalcError {}
+
+impl From<io::Error> for CalcError {
+    fn from(e: io::Error) -> CalcError {
+        CalcError::Io(e.to_string())
+    }
+}
+
+pub fn check_finite(num: Complex) -> Result<Complex, CalcError> {
+    if num.real().is_infinite() || num.imaginary().is_infinite() {
+        Err(CalcError::Overflow("The result is too large".into()))
+    } else if num.real().is_nan() || num.imaginary().is_nan() {
+        Err(CalcError::Domain("The result is undefined".into()))
+    } else {
+        Ok(num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_and_messages__205() {
+        let error = CalcError::parse("Expecting a number", Span::new(6, 7));
+
+        assert_eq!("E002", error.code());
+        assert_eq!("Expecting a number", format!("{}", error));
+        assert_eq!(Span::new(10, 11), Span::new(6, 7).shift(4));
+    }
+
+    #[test]
+    fn test_error_locations__263() {
+        let inner = CalcError::at("inner.cx", 2, CalcError::Domain("The result is undefined".into()));
+        let error = CalcError::at("outer.cx", 7, inner);
+
+        assert_eq!("E003", error.code());
+        assert_eq!(Some(("inner.cx", 2)), error.location());
+        assert_eq!("inner.cx:2: The result is undefined", format!("{}", error));
+    }
+
+    #[test]
+    fn test_check_finite__206__207() {
+        {
+            let expected = Err(CalcError::Overflow("The result is too large".into()));
+
+            assert_eq!(expected, check_finite(Complex::new(1e308 * 10.0, 0.0)));
+        }
+        {
+            let expected = Err(CalcError::Domain("The result is undefined".into()));
+
+            assert_eq!(expected, check_finite(Complex::new(0.0, 0.0 / 0.0)));
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use value::Value;
+use error::CalcError;
+use state::{Settings, Angle};
+use format;
+use locale::translate;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    Result(Value),
+    Real(f64),
+    Imaginary(f64),
+    Argument(f64),
+    // Modulus and argument, in the unit they were computed with
+    Polar(f64, f64, Angle),
+    Stack(Vec<Value>),
+    Message(String),
+    Error(CalcError),
+    Exit
+}
+
+pub fn render(event: &Event, settings: &Settings) -> String {
+    match *event {
+        Event::Result(ref value) => format::value(value, settings),
+        Event::Real(n) | Event::Imaginary(n) | Event::Argument(n) => format::number(n, settings),
+        Event::Polar(r, theta, unit) => format!("{}@{}{}", format::number(r, settings), format::number(theta, settings), unit),
+        Event::Stack(ref stack) if stack.is_empty() => translate(settings.locale, "<empty>"),
+        Event::Stack(ref stack) => {
+            let lines: Vec<String> = stack.iter().enumerate()
+                .map(|(i, value)| format!("{}: {}", stack.len() - i, format::value(value, settings)))
+                .collect();
+            lines.join("\n")
+        },
+        Event::Message(ref msg) => translate(settings.locale, msg),
+        Event::Error(ref e) => match e.location() {
+            Some((file, line)) => format!("{}:{}: {}", file, line, translate(settings.locale, e.message())),
+            None => translate(settings.locale, e.message())
+        },
+        Event::Exit => String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use format::Notation;
+
+    #[test]
+    fn test_parts_follow_the_format__376() {
+        let mut settings = Settings::new(2, Angle::Radians);
+
+        assert_eq!("0.50", render(&Event::Imaginary(0.5), &settings));
+        assert_eq!("3", render(&Event::Real(3.0), &settings));
+        settings.format.notation = Notation::Scientific;
+        assert_eq!("1.23e3", render(&Event::Real(1234.5), &settings));
+    }
+}
@@ -0,0 +1,381 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use complex::{self, Complex};
+use complex::dialect::Dialect;
+use state::Settings;
+use value::Value;
+use regex::{Captures, Regex};
+
+pub const MAX_DIGITS: usize = 17;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Notation {
+    Fixed,
+    Scientific,
+    // Exponents that are multiples of 3, written as SI prefixes when there is one
+    Engineering,
+    // As many digits as it takes to read the number back unchanged
+    Exact
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Style {
+    Rectangular,
+    Polar,
+    Exponential
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Format {
+    pub notation: Notation,
+    pub style: Style,
+    pub unit: char,
+    pub zeros: bool,
+    // Separates thousands the way the locale does
+    pub grouping: bool
+}
+
+impl Format {
+    pub fn default() -> Format {
+        Format {
+            notation: Notation::Fixed,
+            style: Style::Rectangular,
+            unit: 'j',
+            zeros: false,
+            grouping: false
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FormatOption {
+    Digits(usize),
+    Notation(Notation),
+    Style(Style),
+    Unit(char),
+    Zeros(bool),
+    Grouping(bool)
+}
+
+impl FormatOption {
+    pub fn parse(word: &str) -> Result<FormatOption, String> {
+        if let Ok(digits) = word.parse::<usize>() {
+            return if digits <= MAX_DIGITS {
+                Ok(FormatOption::Digits(digits))
+            } else {
+                Err(format!("Expecting at most {} digits", MAX_DIGITS))
+            };
+        }
+        match word.to_lowercase().as_str() {
+            "fixed" => Ok(FormatOption::Notation(Notation::Fixed)),
+            "sci" => Ok(FormatOption::Notation(Notation::Scientific)),
+            "eng" => Ok(FormatOption::Notation(Notation::Engineering)),
+            "exact" => Ok(FormatOption::Notation(Notation::Exact)),
+            "rect" => Ok(FormatOption::Style(Style::Rectangular)),
+            "polar" => Ok(FormatOption::Style(Style::Polar)),
+            "exp" => Ok(FormatOption::Style(Style::Exponential)),
+            "i" => Ok(FormatOption::Unit('i')),
+            "j" => Ok(FormatOption::Unit('j')),
+            "zeros" => Ok(FormatOption::Zeros(true)),
+            "nozeros" => Ok(FormatOption::Zeros(false)),
+            "group" => Ok(FormatOption::Grouping(true)),
+            "nogroup" => Ok(FormatOption::Grouping(false)),
+            _ => Err("Expecting digits, fixed, sci, eng, exact, rect, polar, exp, i, j, zeros, nozeros, group or nogroup".into())
+        }
+    }
+
+    pub fn apply(self, settings: &mut Settings) {
+        match self {
+            FormatOption::Digits(digits) => settings.precision = digits,
+            FormatOption::Notation(notation) => settings.format.notation = notation,
+            FormatOption::Style(style) => settings.format.style = style,
+            FormatOption::Unit(unit) => settings.format.unit = unit,
+            FormatOption::Zeros(zeros) => settings.format.zeros = zeros,
+            FormatOption::Grouping(grouping) => settings.format.grouping = grouping,
+        }
+    }
+}
+
+impl Display for FormatOption {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            FormatOption::Digits(digits) => write!(f, "{}", digits),
+            FormatOption::Notation(Notation::Fixed) => write!(f, "fixed"),
+            FormatOption::Notation(Notation::Scientific) => write!(f, "sci"),
+            FormatOption::Notation(Notation::Engineering) => write!(f, "eng"),
+            FormatOption::Notation(Notation::Exact) => write!(f, "exact"),
+            FormatOption::Style(Style::Rectangular) => write!(f, "rect"),
+            FormatOption::Style(Style::Polar) => write!(f, "polar"),
+            FormatOption::Style(Style::Exponential) => write!(f, "exp"),
+            FormatOption::Unit(unit) => write!(f, "{}", unit),
+            FormatOption::Zeros(true) => write!(f, "zeros"),
+            FormatOption::Zeros(false) => write!(f, "nozeros"),
+            FormatOption::Grouping(true) => write!(f, "group"),
+            FormatOption::Grouping(false) => write!(f, "nogroup"),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} {} {} {} {}", FormatOption::Notation(self.notation), FormatOption::Style(self.style),
+               FormatOption::Unit(self.unit), FormatOption::Zeros(self.zeros), FormatOption::Grouping(self.grouping))
+    }
+}
+
+// The options that reproduce the given settings, as shown by a bare format command
+pub fn describe(settings: &Settings) -> String {
+    format!("{} {}", settings.precision, settings.format)
+}
+
+const PREFIXES: [&str; 17] = ["y", "z", "a", "f", "p", "n", "µ", "m", "", "k", "M", "G", "T", "P", "E", "Z", "Y"];
+
+fn engineering(x: f64, precision: usize) -> String {
+    if x == 0.0 || !x.is_finite() {
+        return format!("{:.*}", precision, x);
+    }
+    let mut exponent = (x.abs().log10() / 3.0).floor() as i32 * 3;
+    let mut mantissa = x / 10f64.powi(exponent);
+    // 999.9996 rounds up to 1000.000, which belongs to the next prefix
+    if format!("{:.*}", precision, mantissa.abs()).parse::<f64>().map_or(false, |m| m >= 1000.0) {
+        exponent += 3;
+        mantissa /= 1000.0;
+    }
+    let index = exponent / 3 + 8;
+    if index >= 0 && (index as usize) < PREFIXES.len() {
+        format!("{:.*}{}", precision, mantissa, PREFIXES[index as usize])
+    } else {
+        format!("{:.*}e{}", precision, mantissa, exponent)
+    }
+}
+
+fn group(digits: &str, separator: char) -> String {
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(separator);
+        }
+        out.push(digit);
+    }
+    out
+}
+
+// Writes the numbers in the text with the locale's decimal separator, and groups their thousands if asked to
+pub fn localize(text: &str, settings: &Settings) -> String {
+    lazy_static! {
+        static ref NUMBER: Regex = Regex::new(r"(\d+)(?:\.(\d+))?").unwrap();
+    }
+
+    let locale = settings.locale;
+    if locale.decimal() == '.' && !settings.format.grouping {
+        return text.to_owned();
+    }
+    NUMBER.replace_all(text, |caps: &Captures| {
+        let integer = if settings.format.grouping { group(&caps[1], locale.separator()) } else { caps[1].to_owned() };
+        match caps.get(2) {
+            Some(fraction) => format!("{}{}{}", integer, locale.decimal(), fraction.as_str()),
+            None => integer
+        }
+    }).into_owned()
+}
+
+pub fn number(x: f64, settings: &Settings) -> String {
+    localize(&plain_number(x, x % 1.0 == 0.0, settings), settings)
+}
+
+// Fixed notation leaves out the decimals only when the whole number they belong to is whole
+fn plain_number(x: f64, whole: bool, settings: &Settings) -> String {
+    let p = settings.precision;
+    match settings.format.notation {
+        Notation::Fixed if whole => format!("{}", x),
+        Notation::Fixed => format!("{:.*}", p, x),
+        Notation::Scientific => format!("{:.*e}", p, x),
+        Notation::Engineering => engineering(x, p),
+        Notation::Exact => complex::shortest(x),
+    }
+}
+
+pub fn complex(num: &Complex, settings: &Settings) -> String {
+    let format = settings.format;
+    match format.style {
+        Style::Rectangular if format.notation == Notation::Exact => {
+            localize(&num.lossless().replace('j', &format.unit.to_string()), settings)
+        },
+        Style::Rectangular => {
+            let whole = num.is_whole();
+            num.rectangular(|x| localize(&plain_number(x, whole, settings), settings), format.unit, format.zeros)
+        },
+        Style::Polar => {
+            let angle = settings.angle;
+            format!("{}@{}{}", number(num.modulus(), settings), number(angle.from_radians(num.argument()), settings), angle)
+        },
+        // The exponent is always in radians, whatever the angle unit
+        Style::Exponential => {
+            let theta = num.argument();
+            let sign = if theta.is_sign_negative() { "-" } else { "" };
+            format!("{}·e^({}{}{})", number(num.modulus(), settings), sign, format.unit, number(theta.abs(), settings))
+        },
+    }
+}
+
+fn items(items: &[Complex], settings: &Settings) -> String {
+    let items: Vec<String> = items.iter().map(|num| complex(num, settings)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+pub fn value(value: &Value, settings: &Settings) -> String {
+    if settings.dialect != Dialect::Native {
+        return in_dialect(value, settings.dialect);
+    }
+    match *value {
+        Value::Scalar(ref num) => complex(num, settings),
+        Value::List(ref list) => items(list, settings),
+        Value::Matrix(ref rows) => {
+            let rows: Vec<String> = rows.iter().map(|row| items(row, settings)).collect();
+            format!("[{}]", rows.join(", "))
+        },
+        ref value => format!("{}", value)
+    }
+}
+
+fn lossless_items(items: &[Complex]) -> String {
+    let items: Vec<String> = items.iter().map(|num| num.lossless()).collect();
+    format!("[{}]", items.join(", "))
+}
+
+// Literals that value::parse reads back unchanged, for logs and saved sessions
+pub fn lossless(value: &Value) -> String {
+    match *value {
+        Value::Scalar(ref num) => num.lossless(),
+        Value::List(ref list) => lossless_items(list),
+        Value::Matrix(ref rows) => {
+            let rows: Vec<String> = rows.iter().map(|row| lossless_items(row)).collect();
+            format!("[{}]", rows.join(", "))
+        },
+        ref value => format!("{}", value)
+    }
+}
+
+fn dialect_items(items: &[Complex], dialect: Dialect) -> String {
+    let items: Vec<String> = items.iter().map(|num| num.display(dialect).to_string()).collect();
+    match dialect {
+        Dialect::Mathematica | Dialect::C99 => format!("{{{}}}", items.join(", ")),
+        _ => format!("[{}]", items.join(", "))
+    }
+}
+
+// What the other tool would accept pasted into it
+pub fn in_dialect(value: &Value, dialect: Dialect) -> String {
+    match (value, dialect) {
+        (_, Dialect::Native) => lossless(value),
+        (&Value::Scalar(ref num), _) => num.display(dialect).to_string(),
+        (&Value::List(ref list), _) => dialect_items(list, dialect),
+        (&Value::Matrix(ref rows), Dialect::Matlab) => {
+            let rows: Vec<String> = rows.iter()
+                .map(|row| row.iter().map(|num| num.display(dialect).to_string()).collect::<Vec<_>>().join(", "))
+                .collect();
+            format!("[{}]", rows.join("; "))
+        },
+        (&Value::Matrix(ref rows), Dialect::Mathematica) | (&Value::Matrix(ref rows), Dialect::C99) => {
+            let rows: Vec<String> = rows.iter().map(|row| dialect_items(row, dialect)).collect();
+            format!("{{{}}}", rows.join(", "))
+        },
+        (&Value::Matrix(ref rows), _) => {
+            let rows: Vec<String> = rows.iter().map(|row| dialect_items(row, dialect)).collect();
+            format!("[{}]", rows.join(", "))
+        },
+        (&Value::Bool(true), Dialect::Python) | (&Value::Bool(true), Dialect::Mathematica) => "True".into(),
+        (&Value::Bool(false), Dialect::Python) | (&Value::Bool(false), Dialect::Mathematica) => "False".into(),
+        (&Value::Bool(b), _) => format!("{}", b),
+        (&Value::Text(ref text), _) => format!("{:?}", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::Angle;
+    use locale::Locale;
+
+    fn settings(precision: usize, options: &[FormatOption]) -> Settings {
+        let mut settings = Settings::new(precision, Angle::Degrees);
+        for option in options {
+            option.apply(&mut settings);
+        }
+        settings
+    }
+
+    #[test]
+    fn test_notations__301__302__303() {
+        {
+            let settings = settings(2, &[]);
+
+            assert_eq!("1.50-2.00j", complex(&Complex::new(1.5, -2.0), &settings));
+            assert_eq!("-3-4j", complex(&Complex::new(-3.0, -4.0), &settings));
+        }
+        {
+            let settings = settings(3, &[FormatOption::Notation(Notation::Scientific), FormatOption::Unit('i')]);
+
+            assert_eq!("1.500e3+2.500e-1i", complex(&Complex::new(1500.0, 0.25), &settings));
+        }
+        {
+            let settings = settings(1, &[FormatOption::Notation(Notation::Engineering)]);
+
+            assert_eq!("4.7k", number(4700.0, &settings));
+            assert_eq!("-22.0µ", number(-0.000022, &settings));
+            assert_eq!("1.0k", number(999.96, &settings));
+            assert_eq!("1.0e27", number(1e27, &settings));
+        }
+    }
+
+    #[test]
+    fn test_styles__304__305() {
+        {
+            let settings = settings(3, &[FormatOption::Style(Style::Polar)]);
+
+            assert_eq!("2@90deg", complex(&Complex::new(0.0, 2.0), &settings));
+        }
+        {
+            let settings = settings(2, &[FormatOption::Style(Style::Exponential), FormatOption::Zeros(true)]);
+
+            assert_eq!("2·e^(-j1.57)", complex(&Complex::new(0.0, -2.0), &settings));
+            assert_eq!("[2·e^(j0), 1·e^(j3.14)]", value(&Value::List(vec![Complex::new(2.0, 0.0), Complex::new(-1.0, 0.0)]), &settings));
+        }
+    }
+
+    #[test]
+    fn test_zeros_and_options__306__307() {
+        {
+            let settings = settings(3, &[FormatOption::Zeros(true)]);
+
+            assert_eq!("1+0j", complex(&Complex::new(1.0, 0.0), &settings));
+            assert_eq!("0-2j", complex(&Complex::new(0.0, -2.0), &settings));
+            assert_eq!("3 fixed rect j zeros nogroup", describe(&settings));
+        }
+        {
+            assert_eq!(Ok(FormatOption::Notation(Notation::Engineering)), FormatOption::parse("ENG"));
+            assert_eq!(Err("Expecting at most 17 digits".into()), FormatOption::parse("18"));
+        }
+    }
+
+    #[test]
+    fn test_values_in_dialects__320() {
+        let row = |a: f64, b: f64| vec![Complex::new(a, 0.0), Complex::new(b, 1.0)];
+        let matrix = Value::Matrix(vec![row(1.0, 2.0), row(3.0, 4.0)]);
+
+        assert_eq!("[1+0i, 2+1i; 3+0i, 4+1i]", in_dialect(&matrix, Dialect::Matlab));
+        assert_eq!("{{1+0 I, 2+1 I}, {3+0 I, 4+1 I}}", in_dialect(&matrix, Dialect::Mathematica));
+        assert_eq!("True", in_dialect(&Value::Bool(true), Dialect::Python));
+        assert_eq!("[[1, 2+1j], [3, 4+1j]]", in_dialect(&matrix, Dialect::Native));
+    }
+
+    #[test]
+    fn test_localize__330() {
+        let mut settings = settings(2, &[FormatOption::Grouping(true)]);
+
+        assert_eq!("1,234,567.50+2.00j", complex(&Complex::new(1234567.5, 2.0), &settings));
+        settings.locale = Locale::Es;
+        assert_eq!("1.234.567,50+2,00j", complex(&Complex::new(1234567.5, 2.0), &settings));
+        settings.format.grouping = false;
+        assert_eq!("[1,50, 1234]", value(&Value::List(vec![Complex::new(1.5, 0.0), Complex::new(1234.0, 0.0)]), &settings));
+    }
+}
@@ -0,0 +1,180 @@
+use state::{AppState, Mode};
+use error::CalcError;
+use format;
+use locale::translate;
+
+pub const HISTORY_LIMIT: usize = 100;
+
+#[derive(Debug, PartialEq)]
+pub struct History {
+    past: Vec<AppState>,
+    future: Vec<AppState>,
+    limit: usize
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History {
+            past: Vec::new(),
+            future: Vec::new(),
+            limit: limit
+        }
+    }
+
+    pub fn default() -> History {
+        History::new(HISTORY_LIMIT)
+    }
+
+    pub fn from_parts(past: Vec<AppState>, future: Vec<AppState>, limit: usize) -> History {
+        let mut history = History {
+            past: past.into_iter().map(quiet).collect(),
+            future: future.into_iter().map(quiet).collect(),
+            limit: limit
+        };
+        if history.past.len() > limit {
+            let excess = history.past.len() - limit;
+            history.past.drain(..excess);
+        }
+        history
+    }
+
+    pub fn past(&self) -> &[AppState] {
+        &self.past
+    }
+
+    pub fn future(&self) -> &[AppState] {
+        &self.future
+    }
+
+    pub fn record(mut self, state: AppState) -> History {
+        self.past.push(quiet(state));
+        if self.past.len() > self.limit {
+            let excess = self.past.len() - self.limit;
+            self.past.drain(..excess);
+        }
+        self.future.clear();
+        self
+    }
+
+    pub fn undo(mut self, current: AppState) -> Result<(History, AppState), (History, AppState, CalcError)> {
+        match self.past.pop() {
+            Some(prev) => {
+                self.future.push(quiet(current));
+                Ok((self, prev))
+            },
+            None => Err((self, current, CalcError::Unavailable("Nothing to undo".into())))
+        }
+    }
+
+    pub fn redo(mut self, current: AppState) -> Result<(History, AppState), (History, AppState, CalcError)> {
+        match self.future.pop() {
+            Some(next) => {
+                self.past.push(quiet(current));
+                Ok((self, next))
+            },
+            None => Err((self, current, CalcError::Unavailable("Nothing to redo".into())))
+        }
+    }
+}
+
+fn quiet(mut state: AppState) -> AppState {
+    state.log = false;
+    state
+}
+
+pub fn describe(state: &AppState) -> String {
+    let settings = &state.settings;
+    match *state {
+        AppState { mode: Mode::Rpn, ref stack, .. } => {
+            let items: Vec<String> = stack.iter().map(|value| format::value(value, settings)).collect();
+            format!("[{}]", items.join(", "))
+        },
+        AppState { number: Some(ref value), pending_op: Some(ref op), .. } => format!("{} {}", format::value(value, settings), op),
+        AppState { number: Some(ref value), pending_op: None, .. } => format::value(value, settings),
+        AppState { number: None, pending_op: Some(ref op), .. } => format!("{} {}", translate(settings.locale, "<empty>"), op),
+        AppState { number: None, pending_op: None, .. } => translate(settings.locale, "<empty>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use complex::Complex;
+    use command::Command;
+
+    #[test]
+    fn test_undo_restores_previous_state__079() {
+        let history = History::default().record(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true));
+        let current = AppState::new(Some(Complex::new(2.0, 0.0).into()), None, true);
+        let expected = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false);
+
+        let (history, output) = history.undo(current).unwrap();
+
+        assert_eq!(expected, output);
+        assert_eq!(0, history.past().len());
+        assert_eq!(1, history.future().len());
+    }
+
+    #[test]
+    fn test_undo_redo_with_empty_history__080__081() {
+        {
+            let expected = Err((History::default(), AppState::default(), CalcError::Unavailable("Nothing to undo".into())));
+
+            assert_eq!(expected, History::default().undo(AppState::default()));
+        }
+        {
+            let expected = Err((History::default(), AppState::default(), CalcError::Unavailable("Nothing to redo".into())));
+
+            assert_eq!(expected, History::default().redo(AppState::default()));
+        }
+    }
+
+    #[test]
+    fn test_redo_after_undo__082() {
+        let history = History::default().record(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false));
+        let current = AppState::new(Some(Complex::new(1.0, 0.0).into()), Some(Command::Addition), false);
+
+        let (history, undone) = history.undo(current).unwrap();
+        let (history, redone) = history.redo(undone).unwrap();
+
+        assert_eq!(AppState::new(Some(Complex::new(1.0, 0.0).into()), Some(Command::Addition), false), redone);
+        assert_eq!(1, history.past().len());
+        assert_eq!(0, history.future().len());
+    }
+
+    #[test]
+    fn test_record_clears_future_and_is_bounded__083__084() {
+        {
+            let history = History::default().record(AppState::default());
+            let (history, current) = history.undo(AppState::default()).unwrap();
+            let history = history.record(current);
+
+            assert_eq!(0, history.future().len());
+        }
+        {
+            let mut history = History::new(3);
+            for i in 0..5 {
+                history = history.record(AppState::new(Some(Complex::new(i as f64, 0.0).into()), None, false));
+            }
+
+            assert_eq!(3, history.past().len());
+            assert_eq!(AppState::new(Some(Complex::new(2.0, 0.0).into()), None, false), history.past()[0]);
+        }
+    }
+
+    #[test]
+    fn test_describe_state__085() {
+        let input = AppState::new(Some(Complex::new(1.0, 1.0).into()), Some(Command::Addition), false);
+        let expected = "1+1j addition".to_owned();
+
+        assert_eq!(expected, describe(&input));
+    }
+
+    #[test]
+    fn test_describe_rpn_state__092() {
+        let input = AppState::rpn(vec![Complex::new(1.0, 0.0).into(), Complex::new(0.0, 2.0).into()], false);
+        let expected = "[1, 2j]".to_owned();
+
+        assert_eq!(expected, describe(&input));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Stdin};
+use std::path::Path;
+use error::CalcError;
+use limits::{self, Limits};
+
+pub trait Source {
+    // Ok(None) means the input is exhausted; errors only affect the line they were found on.
+    fn next_line(&mut self) -> Result<Option<String>, CalcError>;
+}
+
+pub struct LineSource<R: BufRead> {
+    reader: R,
+    limits: Limits,
+    buffer: String,
+    failed: bool
+}
+
+impl<R: BufRead> LineSource<R> {
+    pub fn new(reader: R) -> LineSource<R> {
+        LineSource {
+            reader: reader,
+            limits: Limits::default(),
+            buffer: String::new(),
+            failed: false
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> LineSource<R> {
+        self.limits = limits;
+        self
+    }
+}
+
+impl LineSource<BufReader<Stdin>> {
+    pub fn stdin() -> LineSource<BufReader<Stdin>> {
+        LineSource::new(BufReader::new(io::stdin()))
+    }
+}
+
+impl LineSource<BufReader<File>> {
+    pub fn file(path: &Path) -> io::Result<LineSource<BufReader<File>>> {
+        File::open(path).map(|f| LineSource::new(BufReader::new(f)))
+    }
+}
+
+impl LineSource<Cursor<String>> {
+    pub fn string<S: Into<String>>(input: S) -> LineSource<Cursor<String>> {
+        LineSource::new(Cursor::new(input.into()))
+    }
+}
+
+impl<R: BufRead> Source for LineSource<R> {
+    fn next_line(&mut self) -> Result<Option<String>, CalcError> {
+        if self.failed {
+            return Ok(None);
+        }
+        self.buffer.clear();
+        match limits::read_line(&mut self.reader, &mut self.buffer, self.limits.max_input) {
+            Ok(0) => return Ok(None),
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                return Err(CalcError::Io("The input is not valid UTF-8".into()));
+            },
+            Err(e) => {
+                self.failed = true;
+                return Err(CalcError::Io(format!("Input error: {}", e)));
+            }
+        }
+        let mut end = self.buffer.len();
+        if self.buffer[..end].ends_with('\n') {
+            end -= 1;
+        }
+        if self.buffer[..end].ends_with('\r') {
+            end -= 1;
+        }
+        let line = &self.buffer[..end];
+        self.limits.check_input(line)?;
+        Ok(Some(line.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines<S: Source>(source: &mut S) -> Vec<Result<Option<String>, CalcError>> {
+        let mut output = Vec::new();
+        loop {
+            let line = source.next_line();
+            let done = line == Ok(None);
+            output.push(line);
+            if done {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_line_source__245__246() {
+        {
+            let expected = vec![Ok(Some("1+1j".into())), Ok(Some("".into())), Ok(Some("help".into())), Ok(None)];
+
+            assert_eq!(expected, lines(&mut LineSource::string("1+1j\r\n\nhelp")));
+        }
+        {
+            let input: &[u8] = b"1\n\xff\xfe\n2\n";
+            let expected = vec![Ok(Some("1".into())), Err(CalcError::Io("The input is not valid UTF-8".into())),
+                                Ok(Some("2".into())), Ok(None)];
+
+            assert_eq!(expected, lines(&mut LineSource::new(input)));
+        }
+    }
+
+    #[test]
+    fn test_line_source_limits__247() {
+        let mut limits = Limits::default();
+        limits.max_input = 3;
+        let expected = vec![Err(CalcError::Limit("The input is longer than 3 bytes".into())), Ok(Some("4".into())), Ok(None)];
+
+        assert_eq!(expected, lines(&mut LineSource::string("1 + 2 + 3\n4\n").with_limits(limits)));
+    }
+}
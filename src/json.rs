@@ -0,0 +1,294 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::Chars;
+use std::iter::Peekable;
+use log::json_string;
+use limits::Nesting;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    pub fn string<S: Into<String>>(s: S) -> Json {
+        Json::String(s.into())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref fields) => fields.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None
+        }
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 9007199254740992.0 && (n != 0.0 || n.is_sign_positive()) => {
+                write!(f, "{}", n as i64)
+            },
+            Json::Number(n) => write!(f, "{:?}", n),
+            Json::String(ref s) => write!(f, "{}", json_string(s)),
+            Json::Array(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, &(ref key, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", json_string(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    nesting: Nesting
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == ' ' || c == '\t' || c == '\n' || c == '\r' {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for expected in word.chars() {
+            match self.chars.next() {
+                Some(c) if c == expected => (),
+                _ => return Err(format!("Expecting '{}'", word))
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().cloned() {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => self.nested(Parser::array),
+            Some('{') => self.nested(Parser::object),
+            Some(c) if c == '-' || c.is_digit(10) => self.number(),
+            Some(c) => Err(format!("Unexpected character '{}'", c)),
+            None => Err("Unexpected end of input".into())
+        }
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Json, String> where F: FnOnce(&mut Parser<'a>) -> Result<Json, String> {
+        let outer = self.nesting;
+        self.nesting = outer.enter()?;
+        let value = parse(self);
+        self.nesting = outer;
+        value
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_digit(10) || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' {
+                text.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        text.parse::<f64>().map(Json::Number).map_err(|_| format!("Invalid number '{}'", text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.chars.next();
+        let mut out = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(out),
+                Some('\\') => match self.chars.next() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('u') => out.push(self.escaped_char()?),
+                    _ => return Err("Invalid escape sequence".into())
+                },
+                Some(c) => out.push(c),
+                None => return Err("Unterminated string".into())
+            }
+        }
+    }
+
+    fn code_unit(&mut self) -> Result<u32, String> {
+        let code: String = self.chars.by_ref().take(4).collect();
+        match u32::from_str_radix(&code, 16) {
+            Ok(unit) if code.len() == 4 => Ok(unit),
+            _ => Err(format!("Invalid escape '\\u{}'", code))
+        }
+    }
+
+    // Characters outside the basic plane come as a pair of surrogates, as in \ud83d\ude00
+    fn escaped_char(&mut self) -> Result<char, String> {
+        let high = self.code_unit()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                return Err(format!("Unpaired surrogate '\\u{:04x}'", high));
+            }
+            match self.code_unit()? {
+                low if (0xDC00..0xE000).contains(&low) => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                _ => return Err(format!("Unpaired surrogate '\\u{:04x}'", high))
+            }
+        } else {
+            high
+        };
+        ::std::char::from_u32(code).ok_or_else(|| format!("Invalid escape '\\u{:04x}'", code))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.chars.next();
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err("Expecting ',' or ']'".into())
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.chars.next();
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err("Expecting a key".into());
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.chars.next() != Some(':') {
+                return Err("Expecting ':'".into());
+            }
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(fields)),
+                _ => return Err("Expecting ',' or '}'".into())
+            }
+        }
+    }
+}
+
+// Arrays and objects nest at most max_depth deep
+pub fn parse(input: &str, max_depth: usize) -> Result<Json, String> {
+    let mut parser = Parser { chars: input.chars().peekable(), nesting: Nesting::new(max_depth) };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("Unexpected character '{}'", c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json__209() {
+        let input = r#" {"a": [1, -2.5e1, true, null], "b": "x\"é\n", "c": {}} "#;
+        let expected = Json::object(vec![
+            ("a", Json::Array(vec![Json::Number(1.0), Json::Number(-25.0), Json::Bool(true), Json::Null])),
+            ("b", Json::string("x\"\u{e9}\n")),
+            ("c", Json::Object(vec![]))
+        ]);
+
+        assert_eq!(Ok(expected), parse(input, 128));
+    }
+
+    #[test]
+    fn test_format_json__210() {
+        let input = Json::object(vec![("id", Json::Number(7.0)), ("x", Json::Number(0.5)),
+                                      ("inf", Json::Number(1.0 / 0.0)), ("s", Json::string("a\"b"))]);
+
+        assert_eq!("{\"id\":7,\"x\":0.5,\"inf\":null,\"s\":\"a\\\"b\"}", format!("{}", input));
+    }
+
+    #[test]
+    fn test_parse_bad_json__211__212() {
+        {
+            assert_eq!(Err("Expecting ',' or ']'".into()), parse("[1 2]", 128));
+        }
+        {
+            assert_eq!(Err("Unexpected character 'x'".into()), parse("{} x", 128));
+        }
+    }
+
+    #[test]
+    fn test_parse_limits_and_surrogates__360__361() {
+        {
+            let deep = "[".repeat(30000);
+            assert_eq!(Err("The input nests more than 128 levels".into()), parse(&deep, 128));
+            let fits = format!("{}{}", "[".repeat(128), "]".repeat(128));
+            assert!(parse(&fits, 128).is_ok());
+        }
+        {
+            assert_eq!(Ok(Json::string("\u{1F600}!")), parse(r#""\ud83d\ude00!""#, 128));
+            assert_eq!(Err("Unpaired surrogate '\\ud83d'".into()), parse(r#""\ud83d x""#, 128));
+            assert_eq!(Err("Invalid escape '\\ude00'".into()), parse(r#""\ude00""#, 128));
+        }
+    }
+}
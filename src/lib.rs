@@ -0,0 +1,33 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+
+pub mod complex;
+pub mod state;
+pub mod command;
+pub mod actions;
+pub mod history;
+pub mod rpn;
+pub mod repl;
+pub mod log;
+pub mod persist;
+pub mod event;
+pub mod session;
+pub mod error;
+pub mod json;
+pub mod rpc;
+pub mod daemon;
+pub mod limits;
+pub mod input;
+pub mod registry;
+pub mod script;
+pub mod value;
+pub mod format;
+pub mod locale;
+pub mod rewrite;
+
+pub use complex::Complex;
+pub use value::Value;
+pub use event::Event;
+pub use session::Session;
+pub use error::CalcError;
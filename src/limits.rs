@@ -0,0 +1,233 @@
+use std::f64;
+use std::io::{self, BufRead, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use complex::Complex;
+use error::CalcError;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    pub max_input: usize,
+    // How deep brackets and the commands of map may nest, so hostile input cannot exhaust the stack
+    pub max_depth: usize,
+    pub max_steps: usize,
+    pub timeout: Option<Duration>,
+    pub max_magnitude: f64
+}
+
+impl Limits {
+    pub fn default() -> Limits {
+        Limits {
+            max_input: 65536,
+            max_depth: 128,
+            max_steps: 1000000,
+            timeout: None,
+            max_magnitude: f64::MAX
+        }
+    }
+
+    pub fn check_input(&self, line: &str) -> Result<(), CalcError> {
+        if line.len() > self.max_input {
+            Err(CalcError::Limit(format!("The input is longer than {} bytes", self.max_input)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_magnitude(&self, num: &Complex) -> Result<(), CalcError> {
+        if num.real().hypot(num.imaginary()) > self.max_magnitude {
+            Err(CalcError::Overflow(format!("The result exceeds the magnitude limit of {}", self.max_magnitude)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// How deep a parser is in brackets or nested commands, against the limit
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Nesting {
+    depth: usize,
+    max: usize
+}
+
+impl Nesting {
+    pub fn new(max: usize) -> Nesting {
+        Nesting {
+            depth: 0,
+            max: max
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn leave(self) -> Nesting {
+        Nesting {
+            depth: self.depth.saturating_sub(1),
+            max: self.max
+        }
+    }
+
+    pub fn enter(self) -> Result<Nesting, String> {
+        if self.depth == self.max {
+            Err(format!("The input nests more than {} levels", self.max))
+        } else {
+            Ok(Nesting {
+                depth: self.depth + 1,
+                max: self.max
+            })
+        }
+    }
+}
+
+pub struct Budget<'a> {
+    limits: Limits,
+    steps: usize,
+    deadline: Option<Instant>,
+    interrupt: Option<&'a AtomicBool>
+}
+
+impl<'a> Budget<'a> {
+    pub fn new(limits: Limits, interrupt: Option<&'a AtomicBool>) -> Budget<'a> {
+        if let Some(flag) = interrupt {
+            flag.store(false, Ordering::SeqCst);
+        }
+        Budget {
+            limits: limits,
+            steps: 0,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            interrupt: interrupt
+        }
+    }
+
+    pub fn spend(&mut self) -> Result<(), CalcError> {
+        if self.interrupt.map_or(false, |flag| flag.swap(false, Ordering::SeqCst)) {
+            return Err(CalcError::Cancelled("Interrupted".into()));
+        }
+        self.steps += 1;
+        if self.steps > self.limits.max_steps {
+            return Err(CalcError::Limit(format!("The evaluation took more than {} steps", self.limits.max_steps)));
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() > deadline => {
+                let timeout = self.limits.timeout.unwrap();
+                let millis = timeout.as_secs() * 1000 + timeout.subsec_millis() as u64;
+                Err(CalcError::Limit(format!("The evaluation took longer than {} ms", millis)))
+            },
+            _ => Ok(())
+        }
+    }
+}
+
+fn skip_line<R: BufRead>(input: &mut R) -> io::Result<usize> {
+    let mut skipped = 0;
+    loop {
+        let (done, used) = {
+            let available = input.fill_buf()?;
+            match available.iter().position(|&b| b == b'\n') {
+                Some(i) => (true, i + 1),
+                None => (available.is_empty(), available.len())
+            }
+        };
+        input.consume(used);
+        skipped += used;
+        if done {
+            return Ok(skipped);
+        }
+    }
+}
+
+// Reads a line like BufRead::read_line, but never buffers more than max + 1 bytes of it: the
+// rest of a longer line is discarded so that Limits::check_input rejects what was kept.
+pub fn read_line<R: BufRead>(input: &mut R, buf: &mut String, max: usize) -> io::Result<usize> {
+    let mut bytes = Vec::new();
+    let read = input.by_ref().take(max as u64 + 1).read_until(b'\n', &mut bytes)?;
+    if read > max && bytes.last() != Some(&b'\n') {
+        let skipped = skip_line(input)?;
+        buf.push_str(&String::from_utf8_lossy(&bytes));
+        return Ok(read + skipped);
+    }
+    match String::from_utf8(bytes) {
+        Ok(line) => {
+            buf.push_str(&line);
+            Ok(read)
+        },
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_limits__232__233() {
+        let mut limits = Limits::default();
+        limits.max_input = 4;
+        limits.max_magnitude = 10.0;
+        {
+            let expected = Err(CalcError::Limit("The input is longer than 4 bytes".into()));
+
+            assert_eq!(Ok(()), limits.check_input("1 + "));
+            assert_eq!(expected, limits.check_input("1 + 2"));
+        }
+        {
+            let expected = Err(CalcError::Overflow("The result exceeds the magnitude limit of 10".into()));
+
+            assert_eq!(Ok(()), limits.check_magnitude(&Complex::new(6.0, 8.0)));
+            assert_eq!(expected, limits.check_magnitude(&Complex::new(6.0, 9.0)));
+        }
+    }
+
+    #[test]
+    fn test_budget__234__235__236() {
+        let mut limits = Limits::default();
+        limits.max_steps = 2;
+        {
+            let mut budget = Budget::new(limits, None);
+
+            assert_eq!(Ok(()), budget.spend());
+            assert_eq!(Ok(()), budget.spend());
+            assert_eq!(Err(CalcError::Limit("The evaluation took more than 2 steps".into())), budget.spend());
+        }
+        {
+            let flag = AtomicBool::new(true);
+            let mut budget = Budget::new(limits, Some(&flag));
+            flag.store(true, Ordering::SeqCst);
+
+            assert_eq!(Err(CalcError::Cancelled("Interrupted".into())), budget.spend());
+            assert_eq!(Ok(()), budget.spend());
+        }
+        {
+            limits.timeout = Some(Duration::from_millis(0));
+            let mut budget = Budget::new(limits, None);
+            ::std::thread::sleep(Duration::from_millis(2));
+
+            assert_eq!(Err(CalcError::Limit("The evaluation took longer than 0 ms".into())), budget.spend());
+        }
+    }
+
+    #[test]
+    fn test_bounded_read_line__237() {
+        let mut input = "1 + 2 + 3\n4\n".as_bytes();
+        let mut line = String::new();
+
+        assert_eq!(10, read_line(&mut input, &mut line, 4).unwrap());
+        assert_eq!("1 + 2", line);
+        line.clear();
+        assert_eq!(2, read_line(&mut input, &mut line, 4).unwrap());
+        assert_eq!("4\n", line);
+        line.clear();
+        assert_eq!(0, read_line(&mut input, &mut line, 4).unwrap());
+    }
+
+    #[test]
+    fn test_nesting__365() {
+        let nesting = Nesting::new(2).enter().and_then(Nesting::enter).unwrap();
+
+        assert_eq!(2, nesting.max());
+        assert_eq!(Err("The input nests more than 2 levels".into()), nesting.enter());
+        assert!(nesting.leave().enter().is_ok());
+    }
+}
@@ -0,0 +1,355 @@
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use regex::{Captures, Regex};
+use error::{CalcError, Span};
+use rewrite::Rewrite;
+
+pub const LOCALE_VARIABLE: &'static str = "COMPLEX_LOCALE";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Locale {
+    En,
+    Es
+}
+
+impl Locale {
+    // Takes the language of names like es, es_ES.UTF-8 or es-MX
+    pub fn parse(name: &str) -> Option<Locale> {
+        let language = name.split(&['_', '-', '.', '@'][..]).next().unwrap_or("");
+        match language.to_lowercase().as_str() {
+            "en" => Some(Locale::En),
+            "es" => Some(Locale::Es),
+            _ => None
+        }
+    }
+
+    pub fn from_env() -> Option<Locale> {
+        env::var(LOCALE_VARIABLE).ok().or_else(|| env::var("LANG").ok()).and_then(|name| Locale::parse(&name))
+    }
+
+    pub fn decimal(self) -> char {
+        match self {
+            Locale::En => '.',
+            Locale::Es => ',',
+        }
+    }
+
+    pub fn separator(self) -> char {
+        match self {
+            Locale::En => ',',
+            Locale::Es => '.',
+        }
+    }
+
+    // Rewrites numbers like 1,5 or 1.234,5 with a decimal point, so the parser reads them; inside
+    // brackets the comma separates items, so such numbers there are rejected as ambiguous
+    pub fn delocalize(self, text: Rewrite) -> Result<Rewrite, CalcError> {
+        lazy_static! {
+            static ref NUMBER: Regex = Regex::new(r"\d+(?:[.,]\d+)*").unwrap();
+            static ref GROUPED: Regex = Regex::new(r"^\d{1,3}(?:\.\d{3})+(?:,\d+)?$").unwrap();
+            static ref DECIMAL: Regex = Regex::new(r"^\d+,\d+$").unwrap();
+        }
+
+        if self.decimal() == '.' {
+            return Ok(text);
+        }
+        let mut depth = 0;
+        let mut quoted = false;
+        let places: Vec<(usize, bool)> = text.text().bytes().map(|b| {
+            match b {
+                b'"' => quoted = !quoted,
+                b'[' if !quoted => depth += 1,
+                b']' if !quoted && depth > 0 => depth -= 1,
+                _ => ()
+            }
+            (depth, quoted)
+        }).collect();
+        let ambiguous = NUMBER.find_iter(text.text())
+            .find(|m| places[m.start()].0 > 0 && !places[m.start()].1 && m.as_str().contains(','));
+        if let Some(found) = ambiguous {
+            let number = found.as_str();
+            let message = format!("In a list, {} could be one number or two: write {} or {}", number,
+                                  number.replace('.', "").replace(',', "."), number.replace(',', ", "));
+            return Err(text.error(CalcError::parse(message, Span::new(found.start(), found.end()))));
+        }
+        Ok(text.replace_all(&NUMBER, |caps: &Captures| {
+            let number = &caps[0];
+            if places[caps.get(0).unwrap().start()].1 {
+                number.to_owned()
+            } else if GROUPED.is_match(number) || DECIMAL.is_match(number) {
+                number.replace('.', "").replace(',', ".")
+            } else {
+                number.to_owned()
+            }
+        }))
+    }
+}
+
+impl Display for Locale {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Locale::En => write!(f, "en"),
+            Locale::Es => write!(f, "es"),
+        }
+    }
+}
+
+// English messages and their Spanish translations; {} stands for the parts that are copied over
+const SPANISH: &'static [(&'static str, &'static str)] = &[
+    ("You must insert a number first", "Primero debes introducir un número"),
+    ("{} needs a single number, not a {}", "{} necesita un único número, no {}"),
+    ("{} needs a list, not a {}", "{} necesita una lista, no {}"),
+    ("map must turn every element into a single number", "map debe convertir cada elemento en un único número"),
+    ("Cannot reduce an empty list", "No se puede reducir una lista vacía"),
+    ("Division by zero", "División por cero"),
+    ("Session saved to {}", "Sesión guardada en {}"),
+    ("This command is only available in RPN mode", "Este comando solo está disponible en modo RPN"),
+    ("Nothing to undo", "No hay nada que deshacer"),
+    ("Nothing to redo", "No hay nada que rehacer"),
+    ("<empty>", "<vacío>"),
+    ("The result is too large", "El resultado es demasiado grande"),
+    ("The result is undefined", "El resultado no está definido"),
+    ("The stack is empty", "La pila está vacía"),
+    ("You need at least {} numbers on the stack", "Necesitas al menos {} números en la pila"),
+    ("Please enter a command", "Introduce un comando"),
+    ("Unknown command: {} (did you mean {}?)", "Comando desconocido: {} (¿quisiste decir {}?)"),
+    ("Unknown command: {}", "Comando desconocido: {}"),
+    ("{} must be the only command on its line", "{} debe ser el único comando de su línea"),
+    ("Expecting a number", "Se esperaba un número"),
+    ("Expecting a number, found '{}'", "Se esperaba un número, no '{}'"),
+    ("Expecting a mode: rpn or algebraic", "Se esperaba un modo: rpn o algebraic"),
+    ("Expecting an angle unit: deg, rad, grad or turn", "Se esperaba una unidad de ángulo: deg, rad, grad o turn"),
+    ("Expecting a dialect: native, python, matlab, mathematica, c99 or json",
+     "Se esperaba un dialecto: native, python, matlab, mathematica, c99 o json"),
+    ("Expecting a file name", "Se esperaba un nombre de archivo"),
+    ("Expecting commands", "Se esperaban comandos"),
+    ("Expecting a condition such as real > 0", "Se esperaba una condición como real > 0"),
+    ("Expecting addition, subtraction, multiplication or division", "Se esperaba addition, subtraction, multiplication o division"),
+    ("Expecting digits, fixed, sci, eng, exact, rect, polar, exp, i, j, zeros, nozeros, group or nogroup",
+     "Se esperaban dígitos, fixed, sci, eng, exact, rect, polar, exp, i, j, zeros, nozeros, group o nogroup"),
+    ("Expecting at most {} digits", "Se esperaban como mucho {} dígitos"),
+    ("Expecting numbers, not a {}", "Se esperaban números, no {}"),
+    ("Expecting a row like [1, 2], found '{}'", "Se esperaba una fila como [1, 2], no '{}'"),
+    ("Missing closing bracket", "Falta el corchete de cierre"),
+    ("Missing closing quote", "Faltan las comillas de cierre"),
+    ("Every row of a matrix must have the same length", "Todas las filas de una matriz deben tener la misma longitud"),
+    ("The lists have different lengths: {} and {}", "Las listas tienen longitudes distintas: {} y {}"),
+    ("The matrices have different shapes: {} and {}", "Las matrices tienen dimensiones distintas: {} y {}"),
+    ("Cannot combine a {} matrix with a list of {}", "No se puede combinar una matriz {} con una lista de {}"),
+    ("In a list, {} could be one number or two: write {} or {}", "En una lista, {} puede ser uno o dos números: escribe {} o {}"),
+    ("else must follow an if on its own line", "else debe seguir a un if en su propia línea"),
+    ("end must be alone on its line", "end debe estar solo en su línea"),
+    ("end without an open block", "end sin un bloque abierto"),
+    ("Missing end for the {} block", "Falta el end del bloque {}"),
+    ("Expecting a value", "Se esperaba un valor"),
+    ("Unknown value: {}", "Valor desconocido: {}"),
+    ("Expecting <, <=, >, >=, == or !=", "Se esperaba <, <=, >, >=, == o !="),
+    ("Expecting a comparison such as real > 0", "Se esperaba una comparación como real > 0"),
+    ("Expecting a loop variable such as k", "Se esperaba una variable de bucle como k"),
+    ("{} cannot be used as a variable", "{} no se puede usar como variable"),
+    ("Expecting for <variable> in <from>..<to>", "Se esperaba for <variable> in <desde>..<hasta>"),
+    ("Expecting a range such as 0..10", "Se esperaba un rango como 0..10"),
+    ("The condition needs a single number, not a {}", "La condición necesita un único número, no {}"),
+    ("The condition needs a current number", "La condición necesita un número actual"),
+    ("Loop bounds must be integers, not {}", "Los límites del bucle deben ser enteros, no {}"),
+    ("Scripts are nested more than {} deep", "Los scripts se anidan más de {} niveles"),
+    ("Cannot read script {}: {}", "No se puede leer el script {}: {}"),
+    ("Cannot save session to {}: {}", "No se puede guardar la sesión en {}: {}"),
+    ("Cannot load session from {}: {}", "No se puede cargar la sesión de {}: {}"),
+    ("line {}: {}", "línea {}: {}"),
+    ("invalid number '{}'", "número no válido '{}'"),
+    ("invalid numbers '{}'", "números no válidos '{}'"),
+    ("invalid matrix '{}'", "matriz no válida '{}'"),
+    ("invalid boolean '{}'", "booleano no válido '{}'"),
+    ("invalid value '{}'", "valor no válido '{}'"),
+    ("invalid pending operation '{}'", "operación pendiente no válida '{}'"),
+    ("invalid mode '{}'", "modo no válido '{}'"),
+    ("invalid precision '{}'", "precisión no válida '{}'"),
+    ("invalid angle unit '{}'", "unidad de ángulo no válida '{}'"),
+    ("invalid format option '{}'", "opción de formato no válida '{}'"),
+    ("invalid dialect '{}'", "dialecto no válido '{}'"),
+    ("invalid locale '{}'", "idioma no válido '{}'"),
+    ("unknown key '{}'", "clave desconocida '{}'"),
+    ("invalid format version", "versión de formato no válida"),
+    ("not a session file", "no es un archivo de sesión"),
+    ("session format version {} is not supported (expecting 1 to {})",
+     "la versión {} del formato de sesión no está soportada (se esperaba de 1 a {})"),
+    ("duplicated current state", "estado actual duplicado"),
+    ("unknown state section '{}'", "sección de estado desconocida '{}'"),
+    ("expecting a state section", "se esperaba una sección de estado"),
+    ("missing current state", "falta el estado actual"),
+    ("The input is longer than {} bytes", "La entrada ocupa más de {} bytes"),
+    ("The input nests more than {} levels", "La entrada se anida más de {} niveles"),
+    ("The evaluation took more than {} steps", "La evaluación necesitó más de {} pasos"),
+    ("The evaluation took longer than {} ms", "La evaluación tardó más de {} ms"),
+    ("The result exceeds the magnitude limit of {}", "El resultado supera el límite de magnitud de {}"),
+    ("Interrupted", "Interrumpido"),
+    ("Usage: {}", "Uso: {}"),
+    ("Aliases: {}", "Alias: {}"),
+    ("Restarts the calculator", "Reinicia la calculadora"),
+    ("Quits the program", "Sale del programa"),
+    ("Shows all available commands, or the details of one", "Muestra todos los comandos disponibles, o los detalles de uno"),
+    ("Adds the next number to the current one", "Suma el siguiente número al actual"),
+    ("Subtracts the next number from the current one", "Resta el siguiente número del actual"),
+    ("Multiplies the current number by the next one", "Multiplica el número actual por el siguiente"),
+    ("Divides the current number by the next one", "Divide el número actual entre el siguiente"),
+    ("Shows the real part of the current number", "Muestra la parte real del número actual"),
+    ("Shows the imaginary part of the current number", "Muestra la parte imaginaria del número actual"),
+    ("Shows the argument of the current number in the configured angle unit",
+     "Muestra el argumento del número actual en la unidad de ángulo configurada"),
+    ("Shows the modulus and argument of the current number, in the configured angle unit",
+     "Muestra el módulo y el argumento del número actual, en la unidad de ángulo configurada"),
+    ("Takes the sine of the current number, read in the configured angle unit",
+     "Calcula el seno del número actual, leído en la unidad de ángulo configurada"),
+    ("Takes the cosine of the current number, read in the configured angle unit",
+     "Calcula el coseno del número actual, leído en la unidad de ángulo configurada"),
+    ("Takes the tangent of the current number, read in the configured angle unit",
+     "Calcula la tangente del número actual, leído en la unidad de ángulo configurada"),
+    ("Raises the current number to a real power", "Eleva el número actual a una potencia real"),
+    ("Takes the principal n-th root of the current number", "Calcula la raíz n-ésima principal del número actual"),
+    ("Restores the state before the last operation", "Restaura el estado anterior a la última operación"),
+    ("Reapplies the last undone operation", "Vuelve a aplicar la última operación deshecha"),
+    ("Shows the previous states of the calculator", "Muestra los estados anteriores de la calculadora"),
+    ("Exchanges the two topmost numbers of the stack (RPN)", "Intercambia los dos números superiores de la pila (RPN)"),
+    ("Duplicates the topmost number of the stack (RPN)", "Duplica el número superior de la pila (RPN)"),
+    ("Removes the topmost number of the stack (RPN)", "Elimina el número superior de la pila (RPN)"),
+    ("Moves the topmost number to the bottom of the stack (RPN)", "Mueve el número superior al fondo de la pila (RPN)"),
+    ("Shows the contents of the stack (RPN)", "Muestra el contenido de la pila (RPN)"),
+    ("Switches between algebraic and RPN input", "Alterna entre la entrada algebraica y la RPN"),
+    ("Sets the angle unit used for polar numbers, argument and trigonometry",
+     "Fija la unidad de ángulo de los números polares, el argumento y la trigonometría"),
+    ("Sets how results are shown: digits, fixed|sci|eng, rect|polar|exp, i|j, zeros|nozeros and group|nogroup",
+     "Fija cómo se muestran los resultados: dígitos, fixed|sci|eng, rect|polar|exp, i|j, zeros|nozeros y group|nogroup"),
+    ("Shows the result the way another tool writes it, ready to paste",
+     "Muestra el resultado como lo escribe otra herramienta, listo para pegar"),
+    ("Saves the whole session to a file", "Guarda toda la sesión en un archivo"),
+    ("Restores a session saved with save", "Restaura una sesión guardada con save"),
+    ("Runs the commands of a script file", "Ejecuta los comandos de un archivo de script"),
+    ("Runs commands on every element of the current list or matrix",
+     "Ejecuta comandos sobre cada elemento de la lista o matriz actual"),
+    ("Keeps the elements of the current list that satisfy a condition",
+     "Conserva los elementos de la lista actual que cumplen una condición"),
+    ("Combines the elements of the current list with an operation", "Combina los elementos de la lista actual con una operación"),
+];
+
+// The kinds of value that messages name, with the article they take
+const SPANISH_WORDS: &'static [(&'static str, &'static str)] = &[
+    ("number", "un número"),
+    ("list", "una lista"),
+    ("matrix", "una matriz"),
+    ("boolean", "un booleano"),
+    ("string", "una cadena"),
+];
+
+// The parts of the message that fill the template's {} in order, if it fits
+fn fill<'a>(template: &str, message: &'a str) -> Option<Vec<&'a str>> {
+    let pieces: Vec<&str> = template.split("{}").collect();
+    if !message.starts_with(pieces[0]) {
+        return None;
+    }
+    let mut rest = &message[pieces[0].len()..];
+    let mut parts = Vec::new();
+    for (i, piece) in pieces.iter().enumerate().skip(1) {
+        let end = if i + 1 == pieces.len() {
+            if !rest.ends_with(piece) {
+                return None;
+            }
+            rest.len() - piece.len()
+        } else {
+            rest.find(piece)?
+        };
+        parts.push(&rest[..end]);
+        rest = &rest[end + piece.len()..];
+    }
+    if pieces.len() == 1 && !rest.is_empty() {
+        return None;
+    }
+    Some(parts)
+}
+
+// Messages without a translation are shown as they are; the parts copied over are translated too,
+// for messages that wrap others
+pub fn translate(locale: Locale, message: &str) -> String {
+    let (catalog, words) = match locale {
+        Locale::En => return message.to_owned(),
+        Locale::Es => (SPANISH, SPANISH_WORDS),
+    };
+    for &(english, translated) in catalog {
+        if let Some(parts) = fill(english, message) {
+            let mut out = String::new();
+            for (i, piece) in translated.split("{}").enumerate() {
+                if i > 0 {
+                    let part = parts[i - 1];
+                    match words.iter().find(|w| w.0 == part) {
+                        Some(word) => out.push_str(word.1),
+                        None => out.push_str(&translate(locale, part))
+                    }
+                }
+                out.push_str(piece);
+            }
+            return out;
+        }
+    }
+    message.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_locales__326() {
+        assert_eq!(Some(Locale::Es), Locale::parse("es_ES.UTF-8"));
+        assert_eq!(Some(Locale::En), Locale::parse("EN-gb"));
+        assert_eq!(None, Locale::parse("C"));
+    }
+
+    #[test]
+    fn test_translate__327__328() {
+        {
+            assert_eq!("Primero debes introducir un número", translate(Locale::Es, "You must insert a number first"));
+            assert_eq!("real necesita un único número, no una lista", translate(Locale::Es, "real needs a single number, not a list"));
+            assert_eq!("Comando desconocido: fly (¿quisiste decir flip?)", translate(Locale::Es, "Unknown command: fly (did you mean flip?)"));
+            assert_eq!("Las listas tienen longitudes distintas: 2 y 3", translate(Locale::Es, "The lists have different lengths: 2 and 3"));
+        }
+        {
+            assert_eq!("Something new", translate(Locale::Es, "Something new"));
+            assert_eq!("Division by zero", translate(Locale::En, "Division by zero"));
+        }
+    }
+
+    #[test]
+    fn test_delocalize__329() {
+        assert_eq!(Ok("1.5+2.25j".to_owned()), delocalize_text(Locale::Es, "1,5+2,25j"));
+        assert_eq!(Ok("1234.5 [1.5, 2] \"1,5\" for k in 1..3".to_owned()),
+                   delocalize_text(Locale::Es, "1.234,5 [1.5, 2] \"1,5\" for k in 1..3"));
+        assert_eq!(Ok("[1,2]".to_owned()), delocalize_text(Locale::En, "[1,2]"));
+    }
+
+    #[test]
+    fn test_delocalize_spans_and_lists__352__353() {
+        {
+            let text = Locale::Es.delocalize(Rewrite::new("1.234,5 power x")).unwrap();
+            assert_eq!("1234.5 power x", text.text());
+            assert_eq!(Span::new(14, 15), text.span(Span::new(13, 14)));
+        }
+        {
+            let message = "In a list, 1,2 could be one number or two: write 1.2 or 1, 2";
+            assert_eq!(Err(CalcError::parse(message, Span::new(3, 6))), delocalize_text(Locale::Es, "2 [1,2]"));
+            assert_eq!("En una lista, 1,2 puede ser uno o dos números: escribe 1.2 o 1, 2", translate(Locale::Es, message));
+        }
+    }
+
+    #[test]
+    fn test_translate_nested_messages__354() {
+        assert_eq!("No hay nada que deshacer", translate(Locale::Es, "Nothing to undo"));
+        assert_eq!("Falta el end del bloque while", translate(Locale::Es, "Missing end for the while block"));
+        assert_eq!("No se puede cargar la sesión de s.cx: línea 3: número no válido 'x'",
+                   translate(Locale::Es, "Cannot load session from s.cx: line 3: invalid number 'x'"));
+        assert_eq!("<vacío>", translate(Locale::Es, "<empty>"));
+    }
+
+    fn delocalize_text(locale: Locale, text: &str) -> Result<String, CalcError> {
+        locale.delocalize(Rewrite::new(text)).map(Rewrite::into_text)
+    }
+}
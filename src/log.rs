@@ -0,0 +1,380 @@
+use std::io::Write;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::{SystemTime, UNIX_EPOCH};
+use state::{AppState, Mode};
+use command::Command;
+use complex::Complex;
+use value::Value;
+use actions::phasor;
+use format;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogFormat {
+    Plain,
+    Csv,
+    Json
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            LogFormat::Plain => write!(f, "plain"),
+            LogFormat::Csv => write!(f, "csv"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+pub const CSV_HEADER: &'static str = "seq,timestamp,command,operation,operands,result_re,result_im";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Provenance {
+    pub command: String,
+    pub operation: Option<Command>,
+    pub operands: Vec<Value>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    pub seq: u64,
+    pub timestamp: String,
+    pub provenance: Provenance,
+    pub result: Value
+}
+
+fn top(stack: &[Value], n: usize) -> Vec<Value> {
+    if stack.len() < n {
+        Vec::new()
+    } else {
+        stack[stack.len() - n..].to_vec()
+    }
+}
+
+fn literal(state: &AppState, cmd: &Command) -> Value {
+    match *cmd {
+        Command::Number(ref num) => num.clone().into(),
+        Command::Phasor(r, theta, unit) => phasor(r, theta, unit, &state.settings).into(),
+        Command::Value(ref value) => value.clone(),
+        _ => unreachable!()
+    }
+}
+
+// Numbers in the command are written in full, as Display rounds them to the precision
+fn command_text(cmd: &Command) -> String {
+    match *cmd {
+        Command::Number(ref num) => num.lossless(),
+        Command::Value(ref value) => format::lossless(value),
+        Command::Map(ref cmds) => cmds.iter().fold("map".to_owned(), |text, cmd| format!("{} {}", text, command_text(cmd))),
+        Command::Reduce(ref op) => format!("reduce {}", command_text(op)),
+        ref cmd => format!("{}", cmd)
+    }
+}
+
+pub fn provenance(state: &AppState, cmd: &Command) -> Provenance {
+    let (operation, operands) = match (state.mode, cmd) {
+        (Mode::Algebraic, &Command::Number(_)) |
+        (Mode::Algebraic, &Command::Phasor(..)) |
+        (Mode::Algebraic, &Command::Value(_)) => {
+            let num = literal(state, cmd);
+            match (&state.number, &state.pending_op) {
+                (&Some(ref acc), &Some(ref op)) => (Some(op.clone()), vec![acc.clone(), num]),
+                _ => (None, vec![num])
+            }
+        },
+        (Mode::Algebraic, &Command::Power(n)) |
+        (Mode::Algebraic, &Command::Root(n)) => {
+            (Some(cmd.clone()), state.number.iter().cloned().chain(Some(Complex::new(n, 0.0).into())).collect())
+        },
+        (Mode::Algebraic, &Command::Sin) |
+        (Mode::Algebraic, &Command::Cos) |
+        (Mode::Algebraic, &Command::Tan) => (Some(cmd.clone()), state.number.iter().cloned().collect()),
+        (Mode::Rpn, &Command::Number(_)) |
+        (Mode::Rpn, &Command::Phasor(..)) |
+        (Mode::Rpn, &Command::Value(_)) => (None, vec![literal(state, cmd)]),
+        (Mode::Rpn, &Command::Sin) |
+        (Mode::Rpn, &Command::Cos) |
+        (Mode::Rpn, &Command::Tan) => (Some(cmd.clone()), top(&state.stack, 1)),
+        (Mode::Rpn, &Command::Power(n)) |
+        (Mode::Rpn, &Command::Root(n)) => {
+            (Some(cmd.clone()), top(&state.stack, 1).into_iter().chain(Some(Complex::new(n, 0.0).into())).collect())
+        },
+        (_, &Command::Map(_)) |
+        (_, &Command::Filter(_)) |
+        (_, &Command::Reduce(_)) => (Some(cmd.clone()), state.result().into_iter().cloned().collect()),
+        (Mode::Rpn, &Command::Addition) |
+        (Mode::Rpn, &Command::Subtraction) |
+        (Mode::Rpn, &Command::Multiplication) |
+        (Mode::Rpn, &Command::Division) => (Some(cmd.clone()), top(&state.stack, 2)),
+        _ => (None, Vec::new())
+    };
+    Provenance {
+        command: command_text(cmd),
+        operation: operation,
+        operands: operands
+    }
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+pub fn timestamp(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let rem = secs.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, elapsed.subsec_millis())
+}
+
+fn exact(n: f64) -> String {
+    format!("{:?}", n)
+}
+
+fn json_number(n: f64) -> String {
+    if n.is_finite() {
+        exact(n)
+    } else {
+        "null".into()
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_value(value: &Value) -> String {
+    let pair = |c: &Complex| format!("({},{})", exact(c.real()), exact(c.imaginary()));
+    let row = |items: &[Complex]| format!("[{}]", items.iter().map(&pair).collect::<Vec<_>>().join(" "));
+    match *value {
+        Value::Scalar(ref num) => pair(num),
+        Value::List(ref items) => row(items),
+        Value::Matrix(ref rows) => format!("[{}]", rows.iter().map(|r| row(r)).collect::<Vec<_>>().join(" ")),
+        ref value => format!("{}", value)
+    }
+}
+
+// Every complex number, whether result, operand or list item, is a {"re", "im"} object
+fn json_value(value: &Value) -> String {
+    let pair = |c: &Complex| format!("{{\"re\":{},\"im\":{}}}", json_number(c.real()), json_number(c.imaginary()));
+    let row = |items: &[Complex]| format!("[{}]", items.iter().map(&pair).collect::<Vec<_>>().join(","));
+    match *value {
+        Value::Scalar(ref num) => pair(num),
+        Value::List(ref items) => row(items),
+        Value::Matrix(ref rows) => format!("[{}]", rows.iter().map(|r| row(r)).collect::<Vec<_>>().join(",")),
+        Value::Bool(b) => format!("{}", b),
+        Value::Text(ref text) => json_string(text)
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+pub fn format_record(record: &Record, format: LogFormat) -> String {
+    let operation = match record.provenance.operation {
+        Some(ref op) => format!("{}", op),
+        None => String::new()
+    };
+    match format {
+        LogFormat::Plain => format::lossless(&record.result),
+        LogFormat::Csv => {
+            let operands: Vec<String> = record.provenance.operands.iter().map(csv_value).collect();
+            // Results that are not a single number go whole into result_re
+            let (re, im) = match record.result {
+                Value::Scalar(ref num) => (exact(num.real()), exact(num.imaginary())),
+                ref value => (csv_value(value), String::new())
+            };
+            format!("{},{},{},{},{},{},{}",
+                    record.seq,
+                    record.timestamp,
+                    csv_field(&record.provenance.command),
+                    csv_field(&operation),
+                    csv_field(&operands.join(" ")),
+                    csv_field(&re),
+                    im)
+        },
+        LogFormat::Json => {
+            let operands: Vec<String> = record.provenance.operands.iter().map(json_value).collect();
+            let operation = match record.provenance.operation {
+                Some(_) => json_string(&operation),
+                None => "null".into()
+            };
+            format!("{{\"seq\":{},\"timestamp\":{},\"command\":{},\"operation\":{},\"operands\":[{}],\"result\":{}}}",
+                    record.seq,
+                    json_string(&record.timestamp),
+                    json_string(&record.provenance.command),
+                    operation,
+                    operands.join(","),
+                    json_value(&record.result))
+        }
+    }
+}
+
+pub struct Logger {
+    out: Box<dyn Write + Send>,
+    format: LogFormat,
+    seq: u64
+}
+
+impl Logger {
+    pub fn new(mut out: Box<dyn Write + Send>, format: LogFormat, header: bool) -> Logger {
+        if header && format == LogFormat::Csv {
+            let _ = writeln!(out, "{}", CSV_HEADER);
+        }
+        Logger {
+            out: out,
+            format: format,
+            seq: 0
+        }
+    }
+
+    pub fn log(&mut self, provenance: Provenance, mut state: AppState) -> AppState {
+        if state.log {
+            if let Some(num) = state.result() {
+                self.seq += 1;
+                let record = Record {
+                    seq: self.seq,
+                    timestamp: timestamp(SystemTime::now()),
+                    provenance: provenance,
+                    result: num.clone()
+                };
+                let _ = writeln!(self.out, "{}", format_record(&record, self.format));
+            }
+            state.log = false;
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn record() -> Record {
+        Record {
+            seq: 7,
+            timestamp: "2018-03-01T12:30:05.250Z".into(),
+            provenance: Provenance {
+                command: "1+1j".into(),
+                operation: Some(Command::Addition),
+                operands: vec![Complex::new(0.5, 0.0).into(), Complex::new(1.0, 1.0).into()]
+            },
+            result: Complex::new(1.5, 1.0).into()
+        }
+    }
+
+    #[test]
+    fn test_timestamp__172() {
+        let input = UNIX_EPOCH + Duration::from_millis(1519907405250);
+        let expected = "2018-03-01T12:30:05.250Z".to_owned();
+
+        assert_eq!(expected, timestamp(input));
+    }
+
+    #[test]
+    fn test_format_plain__173() {
+        assert_eq!("1.5+1j", format_record(&record(), LogFormat::Plain));
+    }
+
+    #[test]
+    fn test_format_csv__174() {
+        let expected = "7,2018-03-01T12:30:05.250Z,1+1j,addition,\"(0.5,0.0) (1.0,1.0)\",1.5,1.0";
+
+        assert_eq!(expected, format_record(&record(), LogFormat::Csv));
+    }
+
+    #[test]
+    fn test_format_json__175() {
+        let expected = "{\"seq\":7,\"timestamp\":\"2018-03-01T12:30:05.250Z\",\"command\":\"1+1j\",\"operation\":\"addition\",\
+                        \"operands\":[{\"re\":0.5,\"im\":0.0},{\"re\":1.0,\"im\":1.0}],\"result\":{\"re\":1.5,\"im\":1.0}}";
+
+        assert_eq!(expected, format_record(&record(), LogFormat::Json));
+    }
+
+    #[test]
+    fn test_provenance__176__177__178() {
+        {
+            let state = AppState::new(Some(Complex::new(1.0, 0.0).into()), Some(Command::Subtraction), false);
+            let expected = Provenance {
+                command: "2j".into(),
+                operation: Some(Command::Subtraction),
+                operands: vec![Complex::new(1.0, 0.0).into(), Complex::new(0.0, 2.0).into()]
+            };
+
+            assert_eq!(expected, provenance(&state, &Command::Number(Complex::new(0.0, 2.0))));
+        }
+        {
+            let state = AppState::new(Some(Complex::new(1.0, 1.0).into()), None, false);
+            let expected = Provenance {
+                command: "power 3".into(),
+                operation: Some(Command::Power(3.0)),
+                operands: vec![Complex::new(1.0, 1.0).into(), Complex::new(3.0, 0.0).into()]
+            };
+
+            assert_eq!(expected, provenance(&state, &Command::Power(3.0)));
+        }
+        {
+            let state = AppState::rpn(vec![Complex::new(1.0, 0.0).into(), Complex::new(2.0, 0.0).into(), Complex::new(3.0, 0.0).into()], false);
+            let expected = Provenance {
+                command: "multiplication".into(),
+                operation: Some(Command::Multiplication),
+                operands: vec![Complex::new(2.0, 0.0).into(), Complex::new(3.0, 0.0).into()]
+            };
+
+            assert_eq!(expected, provenance(&state, &Command::Multiplication));
+        }
+    }
+
+    #[test]
+    fn test_json_string_escaping__179() {
+        assert_eq!("\"a\\\"b\\\\c\\n\"", json_string("a\"b\\c\n"));
+    }
+
+    #[test]
+    fn test_format_list_result__292() {
+        let mut record = record();
+        record.result = Value::List(vec![Complex::new(1.0, 0.0), Complex::new(2.0, -1.0)]);
+
+        assert!(format_record(&record, LogFormat::Csv).ends_with(",\"[(1.0,0.0) (2.0,-1.0)]\","));
+        assert!(format_record(&record, LogFormat::Json).ends_with("\"result\":[{\"re\":1.0,\"im\":0.0},{\"re\":2.0,\"im\":-1.0}]}"));
+    }
+
+    #[test]
+    fn test_provenance_command_is_lossless__370() {
+        let state = AppState::default();
+        let num = Command::Number(Complex::new(1.23456, 0.0));
+
+        assert_eq!("1.23456", provenance(&state, &num).command);
+        assert_eq!("map 1.23456 power 0.5", provenance(&state, &Command::Map(vec![num, Command::Power(0.5)])).command);
+        assert_eq!("[0.125, 1+1j]", provenance(&state, &Command::Value(Value::List(vec![Complex::new(0.125, 0.0), Complex::new(1.0, 1.0)]))).command);
+    }
+}
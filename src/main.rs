@@ -1,68 +1,571 @@
 #![feature(use_extern_macros)]
 
-#[macro_use]
-extern crate text_io;
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
-
-mod complex;
-mod repl;
-mod state;
-mod command;
-mod actions;
-
-use repl::{eval_cmd};
-use state::AppState;
-use command::{Command, read_command};
+extern crate libc;
+
+extern crate complex;
+
+mod editor;
+mod cli;
+
+use complex::{Event, Session, CalcError};
+use complex::event::render;
+use complex::state::{AppState, Mode, Settings};
+use complex::history::History;
+use complex::command::{Command, command_names, read_command_with};
+use complex::log::{Logger, LogFormat};
+use complex::persist::{self, SESSION_FILE};
+use complex::locale::{Locale, translate};
+use complex::rpc::{Server, parse_error};
+use complex::daemon::{self, Registry};
+use complex::input::{Source, LineSource};
+use complex::script::Runner;
+use editor::{Editor, HISTORY_FILE, is_stdin_tty};
+use cli::{parse_args, Options, Subcommand, USAGE};
 use std::io::prelude::*;
-use std::fs::{File};
-use std::path::Path;
-
-fn log_number(state: AppState, mut file: &File) -> AppState {
-    match state {
-        AppState { number: Some(num), pending_op: op, log: true } => {
-            let _ = file.write_all(format!("{}\n", num).as_bytes());
-            AppState::new(Some(num), op, false)
+use std::io;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::env;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::process;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn trap_interrupt() {
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+struct Prompt<'a> {
+    editor: &'a mut Editor
+}
+
+// Where the interactive loop reads its lines from; the prompt and parser follow the session's settings and limits
+trait Lines {
+    fn read(&mut self, session: &Session) -> Option<Result<Vec<Command>, CalcError>>;
+}
+
+impl<I> Lines for I where I: Iterator<Item = Result<Vec<Command>, CalcError>> {
+    fn read(&mut self, _: &Session) -> Option<Result<Vec<Command>, CalcError>> {
+        self.next()
+    }
+}
+
+impl<'a> Lines for Prompt<'a> {
+    fn read(&mut self, session: &Session) -> Option<Result<Vec<Command>, CalcError>> {
+        let settings = &session.state().settings;
+        self.editor.set_prompt(&prompt(settings));
+        Some(read_command_with(self.editor, settings, session.limits().max_depth))
+    }
+}
+
+fn prompt(settings: &Settings) -> String {
+    format!("{} >>> ", settings.angle)
+}
+
+// A line source that has failed this many times in a row, such as a closed terminal, is given up on
+const MAX_READ_ERRORS: usize = 3;
+
+fn main_loop<L: Lines>(mut session: Session, mut input: L) -> Session {
+    let mut failures = 0;
+    while let Some(line) = input.read(&session) {
+        failures = match line {
+            Err(CalcError::Io(_)) => failures + 1,
+            _ => 0
+        };
+        let events = match line {
+            Ok(cmds) => session.eval_commands(cmds),
+            Err(e) => vec![Event::Error(e)]
+        };
+        let settings = session.state().settings;
+        for event in &events {
+            match *event {
+                Event::Exit => return session,
+                ref event => println!("{}", render(event, &settings))
+            }
+        }
+        if failures == MAX_READ_ERRORS {
+            break;
+        }
+    }
+    session
+}
+
+fn run_batch<S: Source>(session: &mut Session, mut input: S, source: &str, keep_going: bool) -> i32 {
+    let mut runner = Runner::new(source);
+    let mut status = 0;
+    let mut i = 0;
+    let mut done = false;
+    while !done {
+        i += 1;
+        let events = match input.next_line() {
+            Ok(None) => {
+                done = true;
+                runner.finish()
+            },
+            Ok(Some(line)) => runner.feed(session, i, &line),
+            Err(e) => vec![Event::Error(CalcError::at(source, i, e))]
+        };
+        let settings = session.state().settings;
+        for event in &events {
+            match *event {
+                Event::Exit => return status,
+                Event::Error(ref e) => {
+                    let (file, line) = e.location().unwrap_or((source, i));
+                    eprintln!("{}:{}: {}: {}", file, line, e.code(), translate(settings.locale, e.message()));
+                    if !keep_going {
+                        return 1;
+                    }
+                    status = 1;
+                },
+                ref event => println!("{}", render(event, &settings))
+            }
+        }
+    }
+    status
+}
+
+fn run_rpc<S: Source, W: Write>(server: &mut Server, mut input: S, output: &mut W) -> i32 {
+    while !server.is_finished() {
+        let response = match input.next_line() {
+            Ok(None) => break,
+            Ok(Some(ref line)) if line.trim().is_empty() => continue,
+            Ok(Some(line)) => server.handle_line(&line),
+            Err(e) => Some(format!("{}", parse_error(e.message().to_owned())))
+        };
+        if let Some(response) = response {
+            if writeln!(output, "{}", response).and_then(|_| output.flush()).is_err() {
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+fn run_serve(options: &Options) -> i32 {
+    let path = options.socket.as_ref().unwrap();
+    let listener = match daemon::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("complex: cannot listen on {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    eprintln!("complex: listening on {}", path.display());
+    let registry = Registry::new(initial_state(options)).with_limits(options.limits);
+    match daemon::serve(listener, Arc::new(registry)) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("complex: {}", e);
+            1
+        }
+    }
+}
+
+fn run_client(path: &Path) -> i32 {
+    let (mut reader, mut writer) = match UnixStream::connect(path).and_then(|s| s.try_clone().map(|w| (s, w))) {
+        Ok(streams) => streams,
+        Err(e) => {
+            eprintln!("complex: cannot connect to {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let _ = io::copy(&mut stdin.lock(), &mut writer);
+        let _ = writer.shutdown(Shutdown::Write);
+    });
+    let stdout = io::stdout();
+    match io::copy(&mut reader, &mut stdout.lock()) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("complex: {}", e);
+            1
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+fn session_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(SESSION_FILE))
+}
+
+// Only a resumed session is saved on exit, back to where it came from
+fn resume_path(options: &Options) -> Option<PathBuf> {
+    match options.resume {
+        Some(Some(ref path)) => Some(path.clone()),
+        Some(None) => session_path(),
+        None => None
+    }
+}
+
+fn resume(options: &Options) -> Result<(AppState, History), CalcError> {
+    match options.resume {
+        Some(Some(ref path)) => persist::load(path),
+        Some(None) => match session_path() {
+            Some(ref path) if path.exists() => persist::load(path),
+            _ => Ok((initial_state(options), History::default()))
         },
-        state => state
+        None => Ok((initial_state(options), History::default()))
     }
 }
 
-fn main_loop(state: AppState, mut file: &File) {
-    let ns = log_number(state, &mut file);
-    match read_command() {
+fn open_log(options: &Options) -> io::Result<Logger> {
+    match options.log {
+        Some(ref path) => {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(options.append)
+                .truncate(!options.append)
+                .open(path)?;
+            let empty = file.metadata()?.len() == 0;
+            Ok(Logger::new(Box::new(file), options.log_format, empty))
+        },
+        None => Ok(Logger::new(Box::new(io::sink()), LogFormat::Plain, false))
+    }
+}
+
+fn initial_state(options: &Options) -> AppState {
+    let state = match options.mode {
+        Mode::Algebraic => AppState::default(),
+        Mode::Rpn => AppState::rpn(Vec::new(), false),
+    };
+    let mut settings = Settings::new(options.precision, options.angle);
+    settings.dialect = options.dialect;
+    settings.locale = options.locale.or_else(Locale::from_env).unwrap_or(Locale::En);
+    state.with_settings(settings)
+}
+
+fn save_session(path: &Path, state: &AppState, history: &History) -> i32 {
+    match persist::save(path, state, history) {
+        Ok(()) => 0,
+        Err(msg) => {
+            eprintln!("complex: {}", msg);
+            1
+        }
+    }
+}
+
+// A resumed session is saved back however the run ends, and a failed save fails the run
+fn finish(options: &Options, session: &Session, status: i32) -> i32 {
+    match resume_path(options) {
+        Some(ref path) => status.max(save_session(path, session.state(), session.history())),
+        None => status
+    }
+}
+
+fn run(args: Vec<String>) -> i32 {
+    let options = match parse_args(args.into_iter().skip(1)) {
+        Ok(options) => options,
         Err(msg) => {
-            println!("{}", msg);
-            main_loop(ns, &mut file)
-        }
-        Ok(Command::Exit) => return,
-        Ok(cmd) => match eval_cmd(ns, cmd) {
-            Ok(s) => main_loop(s, &mut file),
-            Err((s, msg)) => {
-                println!("{}", msg);
-                main_loop(s, &mut file)
+            eprintln!("complex: {}\n\n{}", msg, USAGE);
+            return 2;
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return 0;
+    }
+    match options.subcommand {
+        Subcommand::Serve => return run_serve(&options),
+        Subcommand::Client => return run_client(options.socket.as_ref().unwrap()),
+        Subcommand::Calculate => ()
+    }
+
+    // A missing script must fail before the log is opened, which would truncate it
+    let script = match options.script {
+        Some(ref path) if path.as_os_str() != "-" => match LineSource::file(path) {
+            Ok(input) => Some((input.with_limits(options.limits), path.display().to_string())),
+            Err(e) => {
+                eprintln!("complex: cannot open {}: {}", path.display(), e);
+                return 1;
             }
+        },
+        _ => None
+    };
+    let log = match open_log(&options) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("complex: cannot open log file {}: {}", options.log.as_ref().unwrap().display(), e);
+            return 1;
+        }
+    };
+    let mut session = match resume(&options) {
+        Ok((state, history)) => Session::new(state).with_history(history).with_logger(log).with_limits(options.limits),
+        Err(msg) => {
+            eprintln!("complex: {}", msg);
+            return 1;
+        }
+    };
+
+    if options.rpc {
+        let stdout = io::stdout();
+        let mut server = Server::new(session);
+        let status = run_rpc(&mut server, LineSource::stdin().with_limits(options.limits), &mut stdout.lock());
+        return finish(&options, server.session(), status);
+    }
+    if !options.expressions.is_empty() {
+        let input = options.expressions.join("\n");
+        let status = run_batch(&mut session, LineSource::string(input).with_limits(options.limits), "-e", options.keep_going);
+        return finish(&options, &session, status);
+    }
+    if let Some((input, name)) = script {
+        let status = run_batch(&mut session, input, &name, options.keep_going);
+        return finish(&options, &session, status);
+    }
+    match options.script {
+        Some(_) => {
+            let status = run_batch(&mut session, LineSource::stdin().with_limits(options.limits), "<stdin>", options.keep_going);
+            finish(&options, &session, status)
+        },
+        None if !is_stdin_tty() => {
+            let status = run_batch(&mut session, LineSource::stdin().with_limits(options.limits), "<stdin>", options.keep_going);
+            finish(&options, &session, status)
+        },
+        None => {
+            // Only command names complete for now: loop variables exist only inside scripts, and the
+            // prompt does not run for blocks yet, so completing them is deferred until it does
+            let mut editor = Editor::new(history_path(), Box::new(|_| command_names()));
+            trap_interrupt();
+            let session = session.with_interrupt(&INTERRUPTED);
+            let session = main_loop(session, Prompt { editor: &mut editor });
+            finish(&options, &session, 0)
         }
     }
 }
 
 fn main() {
-    let state = AppState::default();
-    let path = Path::new("results.txt");
-    let mut results_file = match File::create(&path) {
-        Ok(f) => f,
-        Err(e) => panic!("File error: {}", e)
-    };
-    main_loop(state, &mut results_file);
+    let status = run(env::args().collect());
+    if status != 0 {
+        process::exit(status);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
+
+    fn args(input: &[&str]) -> Vec<String> {
+        input.iter().map(|s| (*s).to_owned()).collect()
+    }
 
     #[test]
     fn test_main__064() {
-        main();
+        assert_eq!(0, run(args(&["complex", "-e", "help"])));
+    }
+
+    #[test]
+    fn test_batch_exit_status__149__150__151() {
+        {
+            let input = "1+1j\naddition\n2\n";
+
+            assert_eq!(0, run_batch(&mut Session::new(AppState::default()), LineSource::new(input.as_bytes()), "test", false));
+        }
+        {
+            let input = "addition\n1\nbad\n";
+
+            assert_eq!(1, run_batch(&mut Session::new(AppState::default()), LineSource::new(input.as_bytes()), "test", false));
+        }
+        {
+            let input = "bad\nexit\nbad\n";
+
+            assert_eq!(1, run_batch(&mut Session::new(AppState::default()), LineSource::new(input.as_bytes()), "test", true));
+        }
+    }
+
+    #[test]
+    fn test_batch_stops_at_exit__152() {
+        let input = "1\nexit\nbad\n";
+
+        assert_eq!(0, run_batch(&mut Session::new(AppState::default()), LineSource::new(input.as_bytes()), "test", false));
+    }
+
+    #[test]
+    fn test_log_append_and_truncate__168__169() {
+        let path = env::temp_dir().join(format!("complex_log_test_{}.txt", process::id()));
+        let mut options = Options::default();
+        options.log = Some(path.clone());
+        {
+            let log = open_log(&options).unwrap();
+            run_batch(&mut Session::new(AppState::default()).with_logger(log), LineSource::new("1\n2\n".as_bytes()), "test", false);
+        }
+        options.append = true;
+        {
+            let log = open_log(&options).unwrap();
+            run_batch(&mut Session::new(AppState::default()).with_logger(log), LineSource::new("3\n".as_bytes()), "test", false);
+        }
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!("1\n2\n3\n", content);
+
+        options.append = false;
+        {
+            let log = open_log(&options).unwrap();
+            run_batch(&mut Session::new(AppState::default()).with_logger(log), LineSource::new("4\n".as_bytes()), "test", false);
+        }
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!("4\n", content);
+    }
+
+    #[test]
+    fn test_csv_header_written_once__182() {
+        let path = env::temp_dir().join(format!("complex_csv_test_{}.csv", process::id()));
+        let mut options = Options::default();
+        options.log = Some(path.clone());
+        options.log_format = LogFormat::Csv;
+        options.append = true;
+        let _ = ::std::fs::remove_file(&path);
+        for _ in 0..2 {
+            let log = open_log(&options).unwrap();
+            run_batch(&mut Session::new(AppState::default()).with_logger(log), LineSource::new("1\n".as_bytes()), "test", false);
+        }
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!(complex::log::CSV_HEADER, lines[0]);
+        assert!(lines[1].starts_with("1,") && lines[1].ends_with(",1,,\"(1.0,0.0)\",1.0,0.0"));
+        assert!(lines[2].starts_with("1,"));
+    }
+
+    #[test]
+    fn test_missing_script_keeps_log__369() {
+        let path = env::temp_dir().join(format!("complex_keep_test_{}.log", process::id()));
+        File::create(&path).unwrap().write_all(b"1\n").unwrap();
+        let script = env::temp_dir().join("missing-dir").join("missing.cx");
+
+        assert_eq!(1, run(args(&["complex", "--log", &path.display().to_string(), &script.display().to_string()])));
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!("1\n", content);
+    }
+
+    #[test]
+    fn test_batch_runs_save_a_resumed_session__377() {
+        let path = env::temp_dir().join(format!("complex_resume_batch_{}.cx", process::id()));
+        persist::save(&path, &AppState::default(), &History::default()).unwrap();
+
+        assert_eq!(0, run(args(&["complex", "--no-log", &format!("--resume={}", path.display()), "-e", "5"])));
+        let loaded = persist::load(&path).map(|(state, _)| state.result().cloned());
+        let _ = ::std::fs::remove_file(&path);
+        assert_eq!(Ok(Some(complex::Complex::new(5.0, 0.0).into())), loaded);
+    }
+
+    #[test]
+    fn test_main_loop_gives_up_on_failing_input__378() {
+        let input = ::std::iter::repeat(()).map(|_| Err(CalcError::Io("Input error: gone".into())));
+
+        let session = main_loop(Session::new(AppState::default()), input);
+
+        assert_eq!(None, session.state().result());
+    }
+
+    #[test]
+    fn test_log_open_failure__170() {
+        let mut options = Options::default();
+        options.log = Some(env::temp_dir().join("missing-dir").join("results.txt"));
+
+        assert!(open_log(&options).is_err());
+    }
+
+    #[test]
+    fn test_initial_state__171() {
+        let mut options = Options::default();
+        options.mode = Mode::Rpn;
+        options.precision = 5;
+
+        assert_eq!(AppState::rpn(vec![], false).with_settings(Settings::new(5, complex::state::Angle::Radians)), initial_state(&options));
+    }
+
+    #[test]
+    fn test_resume_session__198__199() {
+        let path = env::temp_dir().join(format!("complex_resume_test_{}.cx", process::id()));
+        let state = AppState::rpn(vec![complex::Complex::new(1.0, 2.0).into()], false);
+        persist::save(&path, &state, &History::default()).unwrap();
+        let mut options = Options::default();
+        options.resume = Some(Some(path.clone()));
+        {
+            assert_eq!(Ok((state, History::default())), resume(&options));
+            assert_eq!(Some(path.clone()), resume_path(&options));
+            assert_eq!(None, resume_path(&Options::default()));
+        }
+        {
+            File::create(&path).unwrap().write_all(b"complex-session 9\n").unwrap();
+
+            assert_eq!(1, run(args(&["complex", &format!("--resume={}", path.display()), "-e", "1"])));
+        }
+        let _ = ::std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_million_commands_in_small_stack__200() {
+        let driver = ::std::thread::Builder::new().stack_size(256 * 1024).spawn(|| {
+            let num = complex::Complex::new(1.0, 0.0);
+            let input = (0..1000000).map(|i| if i % 2 == 0 {
+                Ok(vec![Command::Number(num.clone())])
+            } else {
+                Ok(vec![Command::Drop])
+            });
+            let session = main_loop(Session::new(AppState::rpn(vec![], false)), input);
+            (session.state().clone(), session.history().past().len())
+        }).unwrap();
+
+        let (state, past) = driver.join().unwrap();
+
+        assert_eq!(AppState::rpn(vec![], false), state);
+        assert_eq!(complex::history::HISTORY_LIMIT, past);
+    }
+
+    #[test]
+    fn test_rpc_loop__220() {
+        let input = "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"eval\",\"params\":[\"2\"]}\n\n\
+                     {\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"eval\",\"params\":[\"exit\"]}\n\
+                     {\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"state\"}\n";
+        let mut output = Vec::new();
+
+        let mut server = Server::new(Session::new(AppState::default()));
+
+        assert_eq!(0, run_rpc(&mut server, LineSource::new(input.as_bytes()), &mut output));
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[1].starts_with("{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":{\"events\":[{\"type\":\"exit\",\"value\":null}]"));
+    }
+
+    #[test]
+    fn test_batch_rejects_long_lines__244() {
+        let mut limits = complex::limits::Limits::default();
+        limits.max_input = 8;
+        let mut session = Session::new(AppState::default()).with_limits(limits);
+        let input = format!("{}\n1\n", "1 + ".repeat(1000));
+
+        assert_eq!(1, run_batch(&mut session, LineSource::new(input.as_bytes()), "test", true));
+        assert_eq!(Some(&complex::Complex::new(1.0, 0.0).into()), session.state().result());
+    }
+
+    #[test]
+    fn test_prompt_shows_angle_unit__300() {
+        assert_eq!("rad >>> ", prompt(&Settings::default()));
+        assert_eq!("grad >>> ", prompt(&Settings::new(3, complex::state::Angle::Gradians)));
     }
 }
@@ -0,0 +1,362 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use state::{AppState, Mode, Angle};
+use history::{History, HISTORY_LIMIT};
+use command::{Command, parse_line};
+use complex::Complex;
+use complex::dialect::Dialect;
+use locale::Locale;
+use value::{self, Value};
+use format::{self, FormatOption};
+use error::CalcError;
+use limits::Limits;
+use json::{self, Json};
+
+pub const SESSION_HEADER: &'static str = "complex-session";
+// Version 2 added lists, matrices, booleans and strings, version 3 writes values as literals and
+// version 4 quotes strings, so newlines and trailing spaces survive
+pub const SESSION_FORMAT: u32 = 4;
+pub const SESSION_FILE: &'static str = ".complex_session";
+
+// Values are written as lossless literals, so they survive a round trip
+fn value_text(value: &Value) -> String {
+    match *value {
+        Value::Text(ref text) => format!("text {}", Json::string(text.as_str())),
+        ref value => format::lossless(value)
+    }
+}
+
+fn write_state(out: &mut String, section: &str, state: &AppState) {
+    out.push_str(&format!("state {}\n", section));
+    out.push_str(&format!("mode {}\n", state.mode));
+    out.push_str(&format!("precision {}\n", state.settings.precision));
+    out.push_str(&format!("angle {}\n", state.settings.angle));
+    out.push_str(&format!("format {}\n", state.settings.format));
+    out.push_str(&format!("dialect {}\n", state.settings.dialect));
+    out.push_str(&format!("locale {}\n", state.settings.locale));
+    if let Some(ref num) = state.number {
+        out.push_str(&format!("number {}\n", value_text(num)));
+    }
+    if let Some(ref op) = state.pending_op {
+        out.push_str(&format!("pending {}\n", op));
+    }
+    for num in &state.stack {
+        out.push_str(&format!("stack {}\n", value_text(num)));
+    }
+}
+
+pub fn to_text(state: &AppState, history: &History) -> String {
+    let mut out = format!("{} {}\n", SESSION_HEADER, SESSION_FORMAT);
+    write_state(&mut out, "current", state);
+    for past in history.past() {
+        write_state(&mut out, "past", past);
+    }
+    for future in history.future() {
+        write_state(&mut out, "future", future);
+    }
+    out
+}
+
+fn parse_complex(value: &str) -> Result<Complex, String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts.len() {
+        2 => match (parts[0].parse::<f64>(), parts[1].parse::<f64>()) {
+            (Ok(re), Ok(im)) => Ok(Complex::new(re, im)),
+            _ => Err(format!("invalid number '{}'", value))
+        },
+        _ => Err(format!("invalid number '{}'", value))
+    }
+}
+
+fn parse_pairs(value: &str) -> Result<Vec<Complex>, String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() % 2 != 0 {
+        return Err(format!("invalid numbers '{}'", value));
+    }
+    parts.chunks(2).map(|pair| parse_complex(&pair.join(" "))).collect()
+}
+
+// Versions 1 and 2 wrote numbers as real and imaginary pairs
+fn parse_pairs_value(value: &str) -> Result<Value, String> {
+    let (kind, rest) = match value.find(' ') {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, "")
+    };
+    match kind {
+        "list" => parse_pairs(rest).map(Value::List),
+        "matrix" => {
+            let (columns, rest) = match rest.find(' ') {
+                Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+                None => (rest, "")
+            };
+            let items = parse_pairs(rest)?;
+            match columns.parse::<usize>() {
+                Ok(0) if items.is_empty() => Ok(Value::Matrix(Vec::new())),
+                Ok(n) if n > 0 && items.len() % n == 0 => Ok(Value::Matrix(items.chunks(n).map(|r| r.to_vec()).collect())),
+                _ => Err(format!("invalid matrix '{}'", value))
+            }
+        },
+        "bool" => match rest {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(format!("invalid boolean '{}'", rest))
+        },
+        "text" => Ok(Value::Text(rest.to_owned())),
+        _ => parse_complex(value).map(Value::Scalar)
+    }
+}
+
+fn parse_value(version: u32, value: &str) -> Result<Value, String> {
+    if version < 3 {
+        return parse_pairs_value(value);
+    }
+    match value.find(' ').map_or(value, |pos| &value[..pos]) {
+        "text" if version < 4 => Ok(Value::Text(value["text".len()..].trim_start().to_owned())),
+        "text" => match json::parse(value["text".len()..].trim_start(), 1) {
+            Ok(Json::String(text)) => Ok(Value::Text(text)),
+            _ => Err(format!("invalid text '{}'", value))
+        },
+        _ => value::parse(value, Limits::default().max_depth).map_err(|_| format!("invalid value '{}'", value))
+    }
+}
+
+fn parse_pending(value: &str) -> Result<Command, String> {
+    match parse_line(value) {
+        Ok(mut cmds) => {
+            if cmds.len() == 1 {
+                Ok(cmds.remove(0))
+            } else {
+                Err(format!("invalid pending operation '{}'", value))
+            }
+        },
+        Err(_) => Err(format!("invalid pending operation '{}'", value))
+    }
+}
+
+fn apply_key(state: &mut AppState, version: u32, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "mode" => state.mode = match value {
+            "rpn" => Mode::Rpn,
+            "algebraic" => Mode::Algebraic,
+            _ => return Err(format!("invalid mode '{}'", value))
+        },
+        "precision" => state.settings.precision = match value.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => return Err(format!("invalid precision '{}'", value))
+        },
+        "angle" => state.settings.angle = match Angle::parse(value) {
+            Some(angle) => angle,
+            None => return Err(format!("invalid angle unit '{}'", value))
+        },
+        "format" => for word in value.split_whitespace() {
+            match FormatOption::parse(word) {
+                Ok(option) => option.apply(&mut state.settings),
+                Err(_) => return Err(format!("invalid format option '{}'", word))
+            }
+        },
+        "dialect" => state.settings.dialect = match Dialect::parse(value) {
+            Some(dialect) => dialect,
+            None => return Err(format!("invalid dialect '{}'", value))
+        },
+        "locale" => state.settings.locale = match Locale::parse(value) {
+            Some(locale) => locale,
+            None => return Err(format!("invalid locale '{}'", value))
+        },
+        "number" => state.number = Some(parse_value(version, value)?),
+        "pending" => state.pending_op = Some(parse_pending(value)?),
+        "stack" => state.stack.push(parse_value(version, value)?),
+        _ => return Err(format!("unknown key '{}'", key))
+    }
+    Ok(())
+}
+
+fn parse_version(line: &str) -> Result<u32, String> {
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next().map(|v| v.parse::<u32>()), parts.next()) {
+        (Some(SESSION_HEADER), Some(Ok(version)), None) => Ok(version),
+        (Some(SESSION_HEADER), _, _) => Err("line 1: invalid format version".into()),
+        _ => Err("not a session file".into())
+    }
+}
+
+pub fn from_text(text: &str) -> Result<(AppState, History), String> {
+    let mut lines = text.lines().enumerate();
+    let version = match lines.next() {
+        Some((_, line)) => parse_version(line.trim())?,
+        None => return Err("not a session file".into())
+    };
+    if version == 0 || version > SESSION_FORMAT {
+        return Err(format!("session format version {} is not supported (expecting 1 to {})", version, SESSION_FORMAT));
+    }
+
+    let mut current = None;
+    let mut past = Vec::new();
+    let mut future = Vec::new();
+    let mut section: Option<(String, AppState)> = None;
+    for (i, line) in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.find(' ') {
+            Some(pos) => (&line[..pos], line[pos + 1..].trim()),
+            None => (line, "")
+        };
+        if key == "state" {
+            if let Some((name, state)) = section.take() {
+                match name.as_str() {
+                    "current" => current = Some(state),
+                    "past" => past.push(state),
+                    _ => future.push(state)
+                }
+            }
+            match value {
+                "current" | "past" | "future" => {
+                    if value == "current" && current.is_some() {
+                        return Err(format!("line {}: duplicated current state", i + 1));
+                    }
+                    section = Some((value.to_owned(), AppState::default()));
+                },
+                _ => return Err(format!("line {}: unknown state section '{}'", i + 1, value))
+            }
+            continue;
+        }
+        match section {
+            Some((_, ref mut state)) => apply_key(state, version, key, value).map_err(|e| format!("line {}: {}", i + 1, e))?,
+            None => return Err(format!("line {}: expecting a state section", i + 1))
+        }
+    }
+    if let Some((name, state)) = section.take() {
+        match name.as_str() {
+            "current" if current.is_some() => return Err("duplicated current state".into()),
+            "current" => current = Some(state),
+            "past" => past.push(state),
+            _ => future.push(state)
+        }
+    }
+    match current {
+        Some(state) => Ok((state, History::from_parts(past, future, HISTORY_LIMIT))),
+        None => Err("missing current state".into())
+    }
+}
+
+pub fn save(path: &Path, state: &AppState, history: &History) -> Result<(), CalcError> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(to_text(state, history).as_bytes()))
+        .map_err(|e| CalcError::Io(format!("Cannot save session to {}: {}", path.display(), e)))
+}
+
+pub fn load(path: &Path) -> Result<(AppState, History), CalcError> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| CalcError::Io(format!("Cannot load session from {}: {}", path.display(), e)))?;
+    from_text(&text).map_err(|e| CalcError::Io(format!("Cannot load session from {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::Settings;
+
+    #[test]
+    fn test_round_trip_algebraic_session__183() {
+        let settings = Settings::new(5, Angle::Degrees);
+        let state = AppState::new(Some(Complex::new(1.5537739740300374, -0.1).into()), Some(Command::Subtraction), false)
+            .with_settings(settings);
+        let history = History::default()
+            .record(AppState::default().with_settings(settings))
+            .record(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false).with_settings(settings));
+
+        let output = from_text(&to_text(&state, &history));
+
+        assert_eq!(Ok((state, history)), output);
+    }
+
+    #[test]
+    fn test_round_trip_rpn_session__184() {
+        let state = AppState::rpn(vec![Complex::new(1e-300, 2.0).into(), Complex::new(-0.0, 1.0 / 3.0).into()], false);
+        let history = History::default().record(AppState::rpn(vec![], false));
+        let (history, state) = history.undo(state).unwrap();
+
+        let output = from_text(&to_text(&state, &history));
+
+        assert_eq!(Ok((state, history)), output);
+    }
+
+    #[test]
+    fn test_missing_keys_take_defaults__185() {
+        let input = "complex-session 1\nstate current\nnumber 2.0 0.0\n";
+        let expected = Ok((AppState::new(Some(Complex::new(2.0, 0.0).into()), None, false), History::default()));
+
+        assert_eq!(expected, from_text(input));
+    }
+
+    #[test]
+    fn test_bad_session_files__186__187__188__189__190() {
+        {
+            let expected = Err("not a session file".into());
+
+            assert_eq!(expected, from_text("1+1j\n2\n"));
+        }
+        {
+            let expected = Err("session format version 5 is not supported (expecting 1 to 4)".into());
+
+            assert_eq!(expected, from_text("complex-session 5\nstate current\n"));
+        }
+        {
+            let expected = Err("line 3: invalid number '1.0 x'".into());
+
+            assert_eq!(expected, from_text("complex-session 1\nstate current\nnumber 1.0 x\n"));
+        }
+        {
+            let expected = Err("line 2: expecting a state section".into());
+
+            assert_eq!(expected, from_text("complex-session 1\nmode rpn\n"));
+        }
+        {
+            let expected = Err("missing current state".into());
+
+            assert_eq!(expected, from_text("complex-session 1\nstate past\n"));
+        }
+    }
+
+    #[test]
+    fn test_load_missing_file__191() {
+        let output = load(Path::new("/nonexistent/session"));
+
+        assert!(output.unwrap_err().message().starts_with("Cannot load session from /nonexistent/session: "));
+    }
+
+    #[test]
+    fn test_round_trip_values__291() {
+        let matrix = Value::Matrix(vec![vec![Complex::new(1.0, 0.5), Complex::new(2.0, 0.0)],
+                                        vec![Complex::new(-0.0, 1.0 / 3.0), Complex::new(1e-300, 0.0)]]);
+        let state = AppState::rpn(vec![matrix, Value::List(vec![Complex::new(1.0, 2.0)]), Value::List(Vec::new()),
+                                       Value::Bool(false), Value::Text("a b".into())], false);
+
+        let output = from_text(&to_text(&state, &History::default()));
+
+        assert_eq!(Ok((state, History::default())), output);
+    }
+
+    #[test]
+    fn test_round_trip_format__311() {
+        let mut settings = Settings::new(4, Angle::Turns);
+        settings.format.notation = ::format::Notation::Engineering;
+        settings.format.unit = 'i';
+        let state = AppState::default().with_settings(settings);
+
+        assert_eq!(Ok((state.clone(), History::default())), from_text(&to_text(&state, &History::default())));
+    }
+
+    #[test]
+    fn test_round_trip_text_with_newline__380() {
+        let state = AppState::rpn(vec![Value::Text("a\n\"b\" ".into())], false);
+
+        assert_eq!(Ok((state.clone(), History::default())), from_text(&to_text(&state, &History::default())));
+        let expected = Ok(AppState::rpn(vec![Value::Text("a  b".into())], false));
+        assert_eq!(expected, from_text("complex-session 3\nstate current\nmode rpn\nstack text a  b\n").map(|(s, _)| s));
+    }
+}
@@ -0,0 +1,474 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::RwLock;
+use state::{AppState, Mode, Angle};
+use command::Command;
+use event::Event;
+use error::{CalcError, CalcResult};
+use script::{self, Condition};
+use format::FormatOption;
+use complex::dialect::Dialect;
+use locale::{Locale, translate};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArgKind {
+    Number,
+    Mode,
+    Angle,
+    Dialect,
+    // Takes the rest of the line, so the command must be alone on it
+    Path,
+    // An optional command name
+    Topic,
+    // The rest of the line, as commands, as a condition or as format options
+    Commands,
+    Condition,
+    Format,
+    // addition, subtraction, multiplication or division
+    Operation
+}
+
+impl Display for ArgKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            ArgKind::Number => write!(f, "<number>"),
+            ArgKind::Mode => write!(f, "<rpn|algebraic>"),
+            ArgKind::Angle => write!(f, "<deg|rad|grad|turn>"),
+            ArgKind::Dialect => write!(f, "<native|python|matlab|mathematica|c99|json>"),
+            ArgKind::Path => write!(f, "<file>"),
+            ArgKind::Topic => write!(f, "[command]"),
+            ArgKind::Commands => write!(f, "<commands>"),
+            ArgKind::Condition => write!(f, "<condition>"),
+            ArgKind::Format => write!(f, "[options]"),
+            ArgKind::Operation => write!(f, "<operation>"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Arg {
+    Number(f64),
+    Mode(Mode),
+    Angle(Angle),
+    Dialect(Dialect),
+    Text(String),
+    Commands(Vec<Command>),
+    Condition(Condition),
+    Format(Vec<FormatOption>),
+    Operation(Command)
+}
+
+impl Display for Arg {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Arg::Number(n) => write!(f, "{}", n),
+            Arg::Mode(mode) => write!(f, "{}", mode),
+            Arg::Angle(angle) => write!(f, "{}", angle),
+            Arg::Dialect(dialect) => write!(f, "{}", dialect),
+            Arg::Text(ref s) => write!(f, "{}", s),
+            Arg::Commands(ref cmds) => {
+                let words: Vec<String> = cmds.iter().map(|c| format!("{}", c)).collect();
+                write!(f, "{}", words.join(" "))
+            },
+            Arg::Condition(ref condition) => write!(f, "{}", condition),
+            Arg::Format(ref options) => {
+                let words: Vec<String> = options.iter().map(|o| format!("{}", o)).collect();
+                write!(f, "{}", words.join(" "))
+            },
+            Arg::Operation(ref op) => write!(f, "{}", op),
+        }
+    }
+}
+
+pub type Action = fn(AppState, &[Arg], &mut Vec<Event>) -> CalcResult;
+
+#[derive(Clone, Copy)]
+pub enum Handler {
+    // Built-in commands are evaluated by the REPL, which also manages history and sessions
+    Builtin(fn(Vec<Arg>) -> Command),
+    Custom(Action)
+}
+
+#[derive(Clone)]
+pub struct Spec {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub args: Vec<ArgKind>,
+    pub help: String,
+    pub handler: Handler
+}
+
+impl Spec {
+    pub fn new(name: &str, help: &str, handler: Handler) -> Spec {
+        Spec {
+            name: name.to_owned(),
+            aliases: Vec::new(),
+            args: Vec::new(),
+            help: help.to_owned(),
+            handler: handler
+        }
+    }
+
+    pub fn with_aliases(mut self, aliases: &[&str]) -> Spec {
+        self.aliases = aliases.iter().map(|a| (*a).to_owned()).collect();
+        self
+    }
+
+    pub fn with_args(mut self, args: &[ArgKind]) -> Spec {
+        self.args = args.to_vec();
+        self
+    }
+
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for arg in &self.args {
+            usage.push_str(&format!(" {}", arg));
+        }
+        usage
+    }
+
+    pub fn build(&self, args: Vec<Arg>) -> Command {
+        match self.handler {
+            Handler::Builtin(build) => build(args),
+            Handler::Custom(_) => Command::Custom(self.name.clone(), args)
+        }
+    }
+
+    fn matches(&self, word: &str) -> bool {
+        self.name.eq_ignore_ascii_case(word) || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(word))
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.name.len() >= prefix.len() && self.name.is_char_boundary(prefix.len()) &&
+            self.name[..prefix.len()].eq_ignore_ascii_case(prefix)
+    }
+}
+
+fn number(args: &[Arg]) -> f64 {
+    match args.first() {
+        Some(&Arg::Number(n)) => n,
+        _ => unreachable!()
+    }
+}
+
+fn text(args: Vec<Arg>) -> String {
+    match args.into_iter().next() {
+        Some(Arg::Text(s)) => s,
+        _ => unreachable!()
+    }
+}
+
+fn arg(args: Vec<Arg>) -> Arg {
+    args.into_iter().next().unwrap()
+}
+
+fn builtins() -> Vec<Spec> {
+    use self::ArgKind::*;
+    use self::Handler::Builtin;
+    vec![
+        Spec::new("help", "Shows all available commands, or the details of one", Builtin(|args| match args.into_iter().next() {
+            Some(Arg::Text(topic)) => Command::HelpOn(topic),
+            _ => Command::Help
+        })).with_args(&[Topic]),
+        Spec::new("clear", "Restarts the calculator", Builtin(|_| Command::Clear)),
+        Spec::new("exit", "Quits the program", Builtin(|_| Command::Exit)),
+        Spec::new("addition", "Adds the next number to the current one", Builtin(|_| Command::Addition))
+            .with_aliases(&["+"]),
+        Spec::new("subtraction", "Subtracts the next number from the current one", Builtin(|_| Command::Subtraction))
+            .with_aliases(&["-"]),
+        Spec::new("multiplication", "Multiplies the current number by the next one", Builtin(|_| Command::Multiplication))
+            .with_aliases(&["*"]),
+        Spec::new("division", "Divides the current number by the next one", Builtin(|_| Command::Division))
+            .with_aliases(&["/"]),
+        Spec::new("real", "Shows the real part of the current number", Builtin(|_| Command::Real)),
+        Spec::new("imaginary", "Shows the imaginary part of the current number", Builtin(|_| Command::Imaginary)),
+        Spec::new("argument", "Shows the argument of the current number in the configured angle unit",
+                  Builtin(|_| Command::Argument)),
+        Spec::new("polar", "Shows the modulus and argument of the current number, in the configured angle unit",
+                  Builtin(|_| Command::Polar)),
+        Spec::new("sin", "Takes the sine of the current number, read in the configured angle unit", Builtin(|_| Command::Sin)),
+        Spec::new("cos", "Takes the cosine of the current number, read in the configured angle unit", Builtin(|_| Command::Cos)),
+        Spec::new("tan", "Takes the tangent of the current number, read in the configured angle unit", Builtin(|_| Command::Tan)),
+        Spec::new("power", "Raises the current number to a real power", Builtin(|args| Command::Power(number(&args))))
+            .with_aliases(&["^"])
+            .with_args(&[Number]),
+        Spec::new("root", "Takes the principal n-th root of the current number", Builtin(|args| Command::Root(number(&args))))
+            .with_args(&[Number]),
+        Spec::new("undo", "Restores the state before the last operation", Builtin(|_| Command::Undo)),
+        Spec::new("redo", "Reapplies the last undone operation", Builtin(|_| Command::Redo)),
+        Spec::new("history", "Shows the previous states of the calculator", Builtin(|_| Command::History)),
+        Spec::new("swap", "Exchanges the two topmost numbers of the stack (RPN)", Builtin(|_| Command::Swap)),
+        Spec::new("dup", "Duplicates the topmost number of the stack (RPN)", Builtin(|_| Command::Dup)),
+        Spec::new("drop", "Removes the topmost number of the stack (RPN)", Builtin(|_| Command::Drop)),
+        Spec::new("roll", "Moves the topmost number to the bottom of the stack (RPN)", Builtin(|_| Command::Roll)),
+        Spec::new("stack", "Shows the contents of the stack (RPN)", Builtin(|_| Command::Stack)),
+        Spec::new("mode", "Switches between algebraic and RPN input", Builtin(|args| match args.first() {
+            Some(&Arg::Mode(mode)) => Command::Mode(mode),
+            _ => unreachable!()
+        })).with_args(&[Mode]),
+        Spec::new("angle", "Sets the angle unit used for polar numbers, argument and trigonometry", Builtin(|args| match args.first() {
+            Some(&Arg::Angle(angle)) => Command::Angle(angle),
+            _ => unreachable!()
+        })).with_args(&[Angle]),
+        Spec::new("format", "Sets how results are shown: digits, fixed|sci|eng, rect|polar|exp, i|j, zeros|nozeros and group|nogroup",
+                  Builtin(|args| match arg(args) {
+                      Arg::Format(options) => Command::Format(options),
+                      _ => unreachable!()
+                  })).with_args(&[Format]),
+        Spec::new("copyas", "Shows the result the way another tool writes it, ready to paste", Builtin(|args| match args.first() {
+            Some(&Arg::Dialect(dialect)) => Command::CopyAs(dialect),
+            _ => unreachable!()
+        })).with_args(&[Dialect]),
+        Spec::new("save", "Saves the whole session to a file", Builtin(|args| Command::Save(text(args))))
+            .with_args(&[Path]),
+        Spec::new("load", "Restores a session saved with save", Builtin(|args| Command::Load(text(args))))
+            .with_args(&[Path]),
+        Spec::new("source", "Runs the commands of a script file", Builtin(|args| Command::Source(text(args))))
+            .with_args(&[Path]),
+        Spec::new("map", "Runs commands on every element of the current list or matrix", Builtin(|args| match arg(args) {
+            Arg::Commands(cmds) => Command::Map(cmds),
+            _ => unreachable!()
+        })).with_args(&[Commands]),
+        Spec::new("filter", "Keeps the elements of the current list that satisfy a condition", Builtin(|args| match arg(args) {
+            Arg::Condition(condition) => Command::Filter(condition),
+            _ => unreachable!()
+        })).with_args(&[Condition]),
+        Spec::new("reduce", "Combines the elements of the current list with an operation", Builtin(|args| match arg(args) {
+            Arg::Operation(op) => Command::Reduce(Box::new(op)),
+            _ => unreachable!()
+        })).with_args(&[Operation]),
+    ]
+}
+
+// Shorter prefixes are too easily typos of something else
+const MIN_PREFIX: usize = 3;
+const MAX_SUGGESTIONS: usize = 3;
+
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            let best = (previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1);
+            current.push(best);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn find_in<'a>(specs: &'a [Spec], word: &str) -> Option<&'a Spec> {
+    if let Some(spec) = specs.iter().find(|s| s.matches(word)) {
+        return Some(spec);
+    }
+    if word.chars().count() < MIN_PREFIX {
+        return None;
+    }
+    let mut candidates = specs.iter().filter(|s| s.starts_with(word));
+    match (candidates.next(), candidates.next()) {
+        (Some(spec), None) => Some(spec),
+        _ => None
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<Vec<Spec>> = RwLock::new(builtins());
+}
+
+pub fn register(spec: Spec) -> Result<(), String> {
+    let mut specs = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    let words = Some(&spec.name).into_iter().chain(spec.aliases.iter());
+    for word in words {
+        if word.is_empty() || word.chars().any(char::is_whitespace) {
+            return Err(format!("Invalid command name: '{}'", word));
+        }
+        if script::is_keyword(word) {
+            return Err(format!("{} is a reserved word", word));
+        }
+        if specs.iter().any(|s| s.matches(word)) {
+            return Err(format!("A command named {} already exists", word));
+        }
+    }
+    specs.push(spec);
+    Ok(())
+}
+
+pub fn find(word: &str) -> Option<Spec> {
+    let specs = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    find_in(&specs, word).cloned()
+}
+
+// Close names by edit distance, also measured against each name cut to the length of the word
+// so that misspelt abbreviations like "multiply" still point to "multiplication".
+pub fn suggest(word: &str) -> Vec<String> {
+    let specs = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    let word: Vec<char> = word.to_lowercase().chars().collect();
+    let threshold = word.len().saturating_sub(1) / 3;
+    let mut scored: Vec<(usize, &str)> = specs.iter().filter_map(|spec| {
+        let name: Vec<char> = spec.name.chars().collect();
+        let cut = &name[..name.len().min(word.len())];
+        let score = distance(&word, &name).min(distance(&word, cut));
+        if score <= threshold {
+            Some((score, spec.name.as_str()))
+        } else {
+            None
+        }
+    }).collect();
+    scored.sort_by_key(|&(score, _)| score);
+    match scored.first().map(|&(best, _)| best) {
+        Some(best) => scored.iter()
+            .take_while(|&&(score, _)| score == best)
+            .take(MAX_SUGGESTIONS)
+            .map(|&(_, name)| name.to_owned())
+            .collect(),
+        None => Vec::new()
+    }
+}
+
+pub fn did_you_mean(word: &str) -> String {
+    let suggestions = suggest(word);
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(" (did you mean {}?)", suggestions.join(" or "))
+    }
+}
+
+pub fn names() -> Vec<String> {
+    let specs = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    specs.iter().map(|s| s.name.clone()).collect()
+}
+
+pub fn help(topic: Option<&str>) -> Result<String, CalcError> {
+    help_in(topic, Locale::En)
+}
+
+pub fn help_in(topic: Option<&str>, locale: Locale) -> Result<String, CalcError> {
+    let specs = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    match topic {
+        None => {
+            let lines: Vec<String> = specs.iter().map(|spec| {
+                let help = translate(locale, &spec.help);
+                if spec.aliases.is_empty() {
+                    format!("{} - {}", spec.usage(), help)
+                } else {
+                    format!("{} ({}) - {}", spec.usage(), spec.aliases.join(" "), help)
+                }
+            }).collect();
+            Ok(lines.join("\n"))
+        },
+        Some(word) => match find_in(&specs, word) {
+            Some(spec) => {
+                let mut lines = vec![translate(locale, &format!("Usage: {}", spec.usage())), translate(locale, &spec.help)];
+                if !spec.aliases.is_empty() {
+                    lines.push(translate(locale, &format!("Aliases: {}", spec.aliases.join(" "))));
+                }
+                Ok(lines.join("\n"))
+            },
+            None => {
+                drop(specs);
+                Err(CalcError::Unavailable(format!("Unknown command: {}{}", word, did_you_mean(word))))
+            }
+        }
+    }
+}
+
+pub fn run_custom(name: &str, state: AppState, args: &[Arg], out: &mut Vec<Event>) -> CalcResult {
+    match find(name).map(|spec| spec.handler) {
+        Some(Handler::Custom(action)) => action(state, args, out),
+        _ => Err((state, CalcError::Unavailable(format!("Unknown command: {}", name))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use complex::Complex;
+    use value::Value;
+
+    fn double(state: AppState, _: &[Arg], out: &mut Vec<Event>) -> CalcResult {
+        match state.number.clone() {
+            Some(Value::Scalar(num)) => {
+                let result: Value = (num.clone() + num).into();
+                out.push(Event::Result(result.clone()));
+                Ok(AppState::new(Some(result), state.pending_op.clone(), true).with_settings(state.settings))
+            },
+            _ => Err((state, CalcError::MissingOperand("You must insert a number first".into())))
+        }
+    }
+
+    #[test]
+    fn test_help_is_generated__248__249__250() {
+        {
+            let help = help(None).unwrap();
+
+            assert!(help.contains("imaginary - Shows the imaginary part of the current number"));
+            assert!(help.contains("power <number> (^) - Raises the current number to a real power"));
+            assert!(help.contains("save <file> - Saves the whole session to a file"));
+        }
+        {
+            let expected = "Usage: addition\nAdds the next number to the current one\nAliases: +";
+
+            assert_eq!(Ok(expected.to_owned()), help(Some("+")));
+        }
+        {
+            assert_eq!(Err(CalcError::Unavailable("Unknown command: fly".into())), help(Some("fly")));
+        }
+    }
+
+    #[test]
+    fn test_register_custom_command__251__252() {
+        {
+            let spec = Spec::new("twice", "Doubles the current number", Handler::Custom(double)).with_aliases(&["x2"]);
+
+            assert_eq!(Ok(()), register(spec));
+            assert_eq!(Command::Custom("twice".into(), vec![]), find("x2").unwrap().build(vec![]));
+
+            let state = AppState::new(Some(Complex::new(1.5, 0.0).into()), None, false);
+            let expected = Ok(AppState::new(Some(Complex::new(3.0, 0.0).into()), None, true));
+            assert_eq!(expected, run_custom("twice", state, &[], &mut Vec::new()));
+        }
+        {
+            let spec = Spec::new("undo", "Shadows a built-in", Handler::Custom(double));
+
+            assert_eq!(Err("A command named undo already exists".into()), register(spec));
+        }
+    }
+
+    #[test]
+    fn test_find_prefixes_and_case__255__256__257() {
+        {
+            assert_eq!("multiplication", find("mult").unwrap().name);
+            assert_eq!("imaginary", find("IMAG").unwrap().name);
+            assert_eq!("help", find("Help").unwrap().name);
+        }
+        {
+            assert!(find("d").is_none());
+            assert!(find("ro").is_none());
+        }
+        {
+            assert!(find("dro").is_some());
+        }
+    }
+
+    #[test]
+    fn test_suggestions__258__259() {
+        {
+            assert_eq!(vec!["multiplication".to_owned()], suggest("multiply"));
+            assert_eq!(vec!["subtraction".to_owned()], suggest("substraction"));
+            assert_eq!(vec!["real".to_owned(), "root".to_owned(), "redo".to_owned()], suggest("r"));
+        }
+        {
+            assert_eq!("", did_you_mean("sqrt"));
+            assert_eq!("", did_you_mean("bad"));
+            assert_eq!(" (did you mean addition?)", did_you_mean("ad"));
+        }
+    }
+
+    #[test]
+    fn test_help_in_spanish__332() {
+        let expected = "Uso: addition\nSuma el siguiente número al actual\nAlias: +";
+
+        assert_eq!(Ok(expected.to_owned()), help_in(Some("+"), Locale::Es));
+    }
+}
@@ -1,20 +1,186 @@
-use state::{AppState};
+use state::{AppState, Mode};
+use complex::Complex;
 use command::{Command};
 use actions::*;
+use rpn;
+use history::History;
+use event::Event;
+use error::{CalcError, CalcResult, check_finite};
+use persist;
+use limits::Limits;
+use registry::run_custom;
+use std::path::Path;
 
-pub fn eval_cmd(state: AppState, cmd: Command) -> Result<AppState, (AppState, String)> {
+pub fn eval_cmd(state: AppState, cmd: Command, out: &mut Vec<Event>) -> CalcResult {
+    match state.mode {
+        Mode::Algebraic => eval_algebraic(state, cmd, out),
+        Mode::Rpn => eval_rpn(state, cmd, out),
+    }
+}
+
+fn eval_algebraic(state: AppState, cmd: Command, out: &mut Vec<Event>) -> CalcResult {
     match cmd {
-        Command::Clear => clear(),
-        Command::Help => print_help(state),
-        Command::Real => print_real(state),
-        Command::Imaginary => print_imaginary(state),
+        Command::Clear => {
+            let settings = state.settings;
+            clear().map(|s| s.with_settings(settings))
+        },
+        Command::Help => print_help(state, out),
+        Command::HelpOn(topic) => print_command_help(&topic, state, out),
+        Command::Custom(name, args) => run_custom(&name, state, &args, out),
+        Command::Map(cmds) => map_values(&cmds, state, out),
+        Command::Filter(condition) => filter_values(&condition, state, out),
+        Command::Reduce(op) => reduce_values(&op, state, out),
+        Command::Real => print_real(state, out),
+        Command::Imaginary => print_imaginary(state, out),
+        Command::Argument => print_argument(state, out),
+        Command::Polar => print_polar(state, out),
+        Command::Sin => do_trig(Complex::sin, state, out),
+        Command::Cos => do_trig(Complex::cos, state, out),
+        Command::Tan => do_trig(Complex::tan, state, out),
         action @ Command::Subtraction |
         action @ Command::Addition |
-        action @ Command::Multiplication => add_action(state, action),
-        Command::Number(num) => add_number(num, state),
-        Command::Power(n) => do_power(n, state),
-        Command::Root(n) => do_power(n, state),
-        Command::Exit => Ok(state)
+        action @ Command::Multiplication |
+        action @ Command::Division => add_action(state, action),
+        Command::Number(num) => add_number(num.into(), state, out),
+        Command::Value(value) => add_number(value, state, out),
+        Command::Phasor(r, theta, unit) => {
+            let num = phasor(r, theta, unit, &state.settings);
+            add_number(num.into(), state, out)
+        },
+        Command::Power(n) => do_power(n, state, out),
+        Command::Root(n) => do_root(n, state, out),
+        Command::Mode(mode) => set_mode(mode, state),
+        Command::Angle(angle) => set_angle(angle, state),
+        Command::Format(options) => set_format(&options, state, out),
+        Command::CopyAs(dialect) => copy_as(dialect, state, out),
+        Command::Swap |
+        Command::Dup |
+        Command::Drop |
+        Command::Roll |
+        Command::Stack => Err((state, CalcError::Unavailable("This command is only available in RPN mode".into()))),
+        Command::Exit |
+        Command::Undo |
+        Command::Redo |
+        Command::History |
+        Command::Save(_) |
+        Command::Load(_) |
+        Command::Source(_) => Ok(state)
+    }
+}
+
+fn eval_rpn(state: AppState, cmd: Command, out: &mut Vec<Event>) -> CalcResult {
+    match cmd {
+        Command::Clear => {
+            let settings = state.settings;
+            rpn::clear().map(|s| s.with_settings(settings))
+        },
+        Command::Help => print_help(state, out),
+        Command::HelpOn(topic) => print_command_help(&topic, state, out),
+        Command::Custom(name, args) => run_custom(&name, state, &args, out),
+        Command::Map(cmds) => map_values(&cmds, state, out),
+        Command::Filter(condition) => filter_values(&condition, state, out),
+        Command::Reduce(op) => reduce_values(&op, state, out),
+        Command::Real => rpn::print_real(state, out),
+        Command::Imaginary => rpn::print_imaginary(state, out),
+        Command::Argument => rpn::print_argument(state, out),
+        Command::Polar => rpn::print_polar(state, out),
+        Command::Sin => rpn::do_trig(Complex::sin, state, out),
+        Command::Cos => rpn::do_trig(Complex::cos, state, out),
+        Command::Tan => rpn::do_trig(Complex::tan, state, out),
+        action @ Command::Subtraction |
+        action @ Command::Addition |
+        action @ Command::Multiplication |
+        action @ Command::Division => rpn::apply_binary(action, state, out),
+        Command::Number(num) => rpn::push(num.into(), state),
+        Command::Value(value) => rpn::push(value, state),
+        Command::Phasor(r, theta, unit) => {
+            let num = phasor(r, theta, unit, &state.settings);
+            rpn::push(num.into(), state)
+        },
+        Command::Power(n) => rpn::do_power(n, state, out),
+        Command::Root(n) => rpn::do_root(n, state, out),
+        Command::Mode(mode) => set_mode(mode, state),
+        Command::Angle(angle) => set_angle(angle, state),
+        Command::Format(options) => set_format(&options, state, out),
+        Command::CopyAs(dialect) => copy_as(dialect, state, out),
+        Command::Swap => rpn::swap(state),
+        Command::Dup => rpn::dup(state),
+        Command::Drop => rpn::drop(state),
+        Command::Roll => rpn::roll(state),
+        Command::Stack => rpn::print_stack(state, out),
+        Command::Exit |
+        Command::Undo |
+        Command::Redo |
+        Command::History |
+        Command::Save(_) |
+        Command::Load(_) |
+        Command::Source(_) => Ok(state)
+    }
+}
+
+// Settings count too, since undo restores them along with the values
+fn changed(before: &AppState, after: &AppState) -> bool {
+    before.number != after.number || before.pending_op != after.pending_op ||
+        before.mode != after.mode || before.stack != after.stack || before.settings != after.settings
+}
+
+fn check_result(before: &AppState, after: &AppState, limits: &Limits) -> Result<(), CalcError> {
+    match after.result() {
+        Some(value) if changed(before, after) => {
+            for num in value.numbers() {
+                check_finite(num.clone()).and_then(|num| limits.check_magnitude(&num))?;
+            }
+            Ok(())
+        },
+        _ => Ok(())
+    }
+}
+
+pub fn eval_with_history(history: History, state: AppState, cmd: Command, out: &mut Vec<Event>) -> (History, CalcResult) {
+    eval_with_limits(history, state, cmd, &Limits::default(), out)
+}
+
+pub fn eval_with_limits(history: History, state: AppState, cmd: Command, limits: &Limits,
+                        out: &mut Vec<Event>) -> (History, CalcResult) {
+    match cmd {
+        Command::Undo => match history.undo(state) {
+            Ok((h, s)) => (h, print_state(s, out)),
+            Err((h, s, msg)) => (h, Err((s, msg)))
+        },
+        Command::Redo => match history.redo(state) {
+            Ok((h, s)) => (h, print_state(s, out)),
+            Err((h, s, msg)) => (h, Err((s, msg)))
+        },
+        Command::History => {
+            let result = print_history(&history, state, out);
+            (history, result)
+        },
+        Command::Save(path) => match persist::save(Path::new(&path), &state, &history) {
+            Ok(()) => {
+                out.push(Event::Message(format!("Session saved to {}", path)));
+                (history, Ok(state))
+            },
+            Err(msg) => (history, Err((state, msg)))
+        },
+        Command::Load(path) => match persist::load(Path::new(&path)) {
+            Ok((s, h)) => (h, print_state(s, out)),
+            Err(msg) => (history, Err((state, msg)))
+        },
+        cmd => {
+            let before = state.clone();
+            let mark = out.len();
+            match eval_cmd(state, cmd, out) {
+                Ok(s) => match check_result(&before, &s, limits) {
+                    Err(e) => {
+                        out.truncate(mark);
+                        (history, Err((before, e)))
+                    },
+                    Ok(()) if changed(&before, &s) => (history.record(before), Ok(s)),
+                    Ok(()) => (history, Ok(s))
+                },
+                err => (history, err)
+            }
+        }
     }
 }
 
@@ -23,82 +189,85 @@ mod tests {
 
     use super::*;
     use complex::Complex;
+    use value::Value;
+    use state::{Settings, Angle};
+    use complex::dialect::Dialect;
 
     #[test]
     fn test_help_command__065() {
         let ini_state = AppState::default();
 
-        assert_eq!(Ok(AppState::default()), eval_cmd(ini_state, Command::Help));
+        assert_eq!(Ok(AppState::default()), eval_cmd(ini_state, Command::Help, &mut Vec::new()));
     }
 
     #[test]
     fn test_clear_command__066() {
-        assert_eq!(Ok(AppState::default()), eval_cmd(AppState::new(None, None, true), Command::Clear));
+        assert_eq!(Ok(AppState::default()), eval_cmd(AppState::new(None, None, true), Command::Clear, &mut Vec::new()));
     }
 
     #[test]
     fn test_print_real__067__068() {
         {
-            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0)), None, true);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), None, false));
+            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true);
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false));
 
-            assert_eq!(expected, eval_cmd(ini_state, Command::Real));
+            assert_eq!(expected, eval_cmd(ini_state, Command::Real, &mut Vec::new()));
         }
         {
             let ini_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
-            assert_eq!(expected, eval_cmd(ini_state, Command::Real));
+            assert_eq!(expected, eval_cmd(ini_state, Command::Real, &mut Vec::new()));
         }
     }
 
     #[test]
     fn test_print_imaginary__069__070() {
         {
-            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0)), None, true);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), None, false));
+            let ini_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true);
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false));
 
-            assert_eq!(expected, eval_cmd(ini_state, Command::Imaginary));
+            assert_eq!(expected, eval_cmd(ini_state, Command::Imaginary, &mut Vec::new()));
         }
         {
             let ini_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
-            assert_eq!(expected, eval_cmd(ini_state, Command::Imaginary));
+            assert_eq!(expected, eval_cmd(ini_state, Command::Imaginary, &mut Vec::new()));
         }
     }
 
     #[test]
     fn test_add_action__071__072__073__074() {
         {
-            let input_state = AppState::new(Some(Complex::new(1.0, 0.0)), None, true);
-            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), Some(Command::Addition), true));
+            let input_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true);
+            let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), Some(Command::Addition), true));
 
-            let output = eval_cmd(input_state, Command::Addition);
+            let output = eval_cmd(input_state, Command::Addition, &mut Vec::new());
 
             assert_eq!(expected, output);
         }
         {
             let input_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
-            let output = eval_cmd(input_state, Command::Addition);
+            let output = eval_cmd(input_state, Command::Addition, &mut Vec::new());
 
             assert_eq!(expected, output);
         }
         {
             let input_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
-            let output = eval_cmd(input_state, Command::Subtraction);
+            let output = eval_cmd(input_state, Command::Subtraction, &mut Vec::new());
 
             assert_eq!(expected, output);
         }
         {
             let input_state = AppState::new(None, None, true);
-            let expected = Err((AppState::new(None, None, true), "You must insert a number first".into()));
+            let expected = Err((AppState::new(None, None, true), CalcError::MissingOperand("You must insert a number first".into())));
 
-            let output = eval_cmd(input_state, Command::Multiplication);
+            let output = eval_cmd(input_state, Command::Multiplication, &mut Vec::new());
 
             assert_eq!(expected, output);
         }
@@ -108,27 +277,27 @@ mod tests {
     fn test_number__075() {
         let input_num = Complex::new(1.0, 0.0);
         let input_state = AppState::new(None, None, false);
-        let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0)), None, true));
+        let expected = Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true));
 
-        assert_eq!(expected, eval_cmd(input_state, Command::Number(input_num)));
+        assert_eq!(expected, eval_cmd(input_state, Command::Number(input_num), &mut Vec::new()));
     }
 
     #[test]
     fn test_power__076() {
         let input_num = 2.0;
-        let input_state = AppState::new(Some(Complex::new(2.0, 2.0)), None, false);
-        let expected = Ok(AppState::new(Some(Complex::new(0.0000000000000004898587196589414, 8.000000000000002)), None, true));
+        let input_state = AppState::new(Some(Complex::new(2.0, 2.0).into()), None, false);
+        let expected = Ok(AppState::new(Some(Complex::new(0.0000000000000004898587196589414, 8.000000000000002).into()), None, true));
         
-        assert_eq!(expected, eval_cmd(input_state, Command::Power(input_num)));
+        assert_eq!(expected, eval_cmd(input_state, Command::Power(input_num), &mut Vec::new()));
     }
 
     #[test]
     fn test_root__077() {
         let input_num = 2.0;
-        let input_state = AppState::new(Some(Complex::new(2.0, 2.0)), None, false);
-        let expected = Ok(AppState::new(Some(Complex::new(1.5537739740300374, 0.6435942529055827)), None, true));
+        let input_state = AppState::new(Some(Complex::new(2.0, 2.0).into()), None, false);
+        let expected = Ok(AppState::new(Some(Complex::new(1.5537739740300374, 0.6435942529055827).into()), None, true));
         
-        assert_eq!(expected, eval_cmd(input_state, Command::Root(input_num)));
+        assert_eq!(expected, eval_cmd(input_state, Command::Root(input_num), &mut Vec::new()));
     }
 
     #[test]
@@ -136,6 +305,210 @@ mod tests {
         let input_state = AppState::default();
         let expected = Ok(AppState::default());
 
-        assert_eq!(expected, eval_cmd(input_state, Command::Exit));
+        assert_eq!(expected, eval_cmd(input_state, Command::Exit, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_undo_mistyped_operation__087() {
+        let state = AppState::new(Some(Complex::new(1.0, 1.0).into()), None, false);
+
+        let (history, state) = eval_with_history(History::default(), state, Command::Multiplication, &mut Vec::new());
+        let (history, state) = eval_with_history(history, state.unwrap(), Command::Undo, &mut Vec::new());
+        let (_, state) = eval_with_history(history, state.unwrap(), Command::Addition, &mut Vec::new());
+
+        assert_eq!(Ok(AppState::new(Some(Complex::new(1.0, 1.0).into()), Some(Command::Addition), false)), state);
+    }
+
+    #[test]
+    fn test_undo_clear__088() {
+        let state = AppState::new(Some(Complex::new(2.0, 0.0).into()), Some(Command::Subtraction), true);
+
+        let (history, state) = eval_with_history(History::default(), state, Command::Clear, &mut Vec::new());
+        assert_eq!(Ok(AppState::default()), state);
+
+        let (_, state) = eval_with_history(history, state.unwrap(), Command::Undo, &mut Vec::new());
+        assert_eq!(Ok(AppState::new(Some(Complex::new(2.0, 0.0).into()), Some(Command::Subtraction), false)), state);
+    }
+
+    #[test]
+    fn test_commands_without_changes_are_not_recorded__089__090() {
+        {
+            let state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, true);
+
+            let (history, _) = eval_with_history(History::default(), state, Command::Real, &mut Vec::new());
+
+            assert_eq!(History::default(), history);
+        }
+        {
+            let state = AppState::default();
+            let expected = Err((AppState::default(), CalcError::MissingOperand("You must insert a number first".into())));
+
+            let (history, output) = eval_with_history(History::default(), state, Command::Addition, &mut Vec::new());
+
+            assert_eq!(History::default(), history);
+            assert_eq!(expected, output);
+        }
+    }
+
+    #[test]
+    fn test_rpn_session__113() {
+        let commands = vec![
+            Command::Mode(Mode::Rpn),
+            Command::Number(Complex::new(3.0, 4.0)),
+            Command::Number(Complex::new(1.0, 0.0)),
+            Command::Swap,
+            Command::Subtraction,
+        ];
+        let mut state = AppState::default();
+        for cmd in commands {
+            state = eval_cmd(state, cmd, &mut Vec::new()).unwrap();
+        }
+
+        assert_eq!(AppState::rpn(vec![Complex::new(-2.0, -4.0).into()], true), state);
+    }
+
+    #[test]
+    fn test_rpn_clear_keeps_mode__114() {
+        let input_state = AppState::rpn(vec![Complex::new(1.0, 0.0).into()], false);
+
+        assert_eq!(Ok(AppState::rpn(vec![], false)), eval_cmd(input_state, Command::Clear, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_clear_keeps_settings__159__160() {
+        {
+            let settings = Settings::new(5, Angle::Degrees);
+            let input_state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false).with_settings(settings);
+
+            assert_eq!(Ok(AppState::default().with_settings(settings)), eval_cmd(input_state, Command::Clear, &mut Vec::new()));
+        }
+        {
+            let settings = Settings::new(5, Angle::Degrees);
+            let input_state = AppState::rpn(vec![Complex::new(1.0, 0.0).into()], false).with_settings(settings);
+
+            assert_eq!(Ok(AppState::rpn(vec![], false).with_settings(settings)), eval_cmd(input_state, Command::Clear, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_stack_commands_in_algebraic_mode__115() {
+        let expected = Err((AppState::default(), CalcError::Unavailable("This command is only available in RPN mode".into())));
+
+        assert_eq!(expected, eval_cmd(AppState::default(), Command::Swap, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_undo_mode_switch__116() {
+        let state = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false);
+
+        let (history, state) = eval_with_history(History::default(), state, Command::Mode(Mode::Rpn), &mut Vec::new());
+        let (_, state) = eval_with_history(history, state.unwrap(), Command::Undo, &mut Vec::new());
+
+        assert_eq!(Ok(AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false)), state);
+    }
+
+    #[test]
+    fn test_settings_changes_are_undone_on_their_own__379() {
+        let one = AppState::new(Some(Complex::new(1.0, 0.0).into()), None, false);
+        let degrees = one.clone().with_settings(Settings::new(3, Angle::Degrees));
+
+        let (history, state) = eval_with_history(History::default(), one.clone(), Command::Angle(Angle::Degrees), &mut Vec::new());
+        let (history, state) = eval_with_history(history, state.unwrap(), Command::Clear, &mut Vec::new());
+        let (history, state) = eval_with_history(history, state.unwrap(), Command::Undo, &mut Vec::new());
+        assert_eq!(Ok(degrees), state);
+        let (_, state) = eval_with_history(history, state.unwrap(), Command::Undo, &mut Vec::new());
+        assert_eq!(Ok(one), state);
+    }
+
+    #[test]
+    fn test_redo_without_undo__091() {
+        let state = AppState::default();
+        let expected = Err((AppState::default(), CalcError::Unavailable("Nothing to redo".into())));
+
+        let (_, output) = eval_with_history(History::default(), state, Command::Redo, &mut Vec::new());
+
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn test_save_and_load_session__195() {
+        let path = ::std::env::temp_dir().join(format!("complex_session_test_{}.cx", ::std::process::id()));
+        let name = path.display().to_string();
+        let state = AppState::new(Some(Complex::new(2.0, 1.0).into()), Some(Command::Addition), false);
+        let history = History::default().record(AppState::default());
+
+        let (history, saved) = eval_with_history(history, state.clone(), Command::Save(name.clone()), &mut Vec::new());
+        let (_, cleared) = eval_with_history(History::default(), saved.unwrap(), Command::Clear, &mut Vec::new());
+        let (loaded_history, loaded) = eval_with_history(History::default(), cleared.unwrap(), Command::Load(name), &mut Vec::new());
+        let _ = ::std::fs::remove_file(&path);
+
+        assert_eq!(Ok(state), loaded);
+        assert_eq!(history, loaded_history);
+    }
+
+    #[test]
+    fn test_overflow_keeps_previous_state__208() {
+        let state = AppState::new(Some(Complex::new(1e200, 0.0).into()), None, false);
+        let expected = Err((state.clone(), CalcError::Overflow("The result is too large".into())));
+        let mut out = Vec::new();
+
+        let (history, output) = eval_with_history(History::default(), state, Command::Power(2.0), &mut out);
+
+        assert_eq!(expected, output);
+        assert_eq!(Vec::<Event>::new(), out);
+        assert_eq!(History::default(), history);
+    }
+
+    #[test]
+    fn test_broadcast_lists__289__290() {
+        let list = |items: &[f64]| Value::List(items.iter().map(|&n| Complex::new(n, 0.0)).collect());
+        {
+            let state = AppState::new(Some(list(&[1.0, 2.0])), Some(Command::Multiplication), false);
+            let expected = AppState::new(Some(list(&[3.0, 6.0])), None, false);
+
+            assert_eq!(Ok(expected), eval_cmd(state, Command::Number(Complex::new(3.0, 0.0)), &mut Vec::new()));
+        }
+        {
+            let state = AppState::rpn(vec![list(&[1.0, 2.0]), list(&[3.0, 4.0, 5.0])], false);
+            let expected = Err((state.clone(), CalcError::Domain("The lists have different lengths: 2 and 3".into())));
+
+            assert_eq!(expected, eval_cmd(state, Command::Addition, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_angle_units__298__299() {
+        {
+            let state = eval_cmd(AppState::default(), Command::Angle(Angle::Turns), &mut Vec::new()).unwrap();
+            let expected = Ok(AppState::new(Some(Complex::new(-2.0, 0.00000000000000024492935982947064).into()), None, true)
+                .with_settings(Settings::new(3, Angle::Turns)));
+
+            assert_eq!(expected, eval_cmd(state, Command::Phasor(2.0, 0.5, None), &mut Vec::new()));
+        }
+        {
+            let settings = Settings::new(3, Angle::Degrees);
+            let state = AppState::rpn(vec![Complex::new(90.0, 0.0).into()], false).with_settings(settings);
+
+            assert_eq!(Ok(AppState::rpn(vec![Complex::new(1.0, 0.0).into()], true).with_settings(settings)),
+                       eval_cmd(state, Command::Sin, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_copy_as__321__322() {
+        {
+            let state = AppState::new(Some(Complex::new(1.0, 2.0).into()), None, true);
+            let mut out = Vec::new();
+            let expected = AppState::new(Some(Complex::new(1.0, 2.0).into()), None, false);
+
+            assert_eq!(Ok(expected), eval_cmd(state, Command::CopyAs(Dialect::C99), &mut out));
+            assert_eq!(vec![Event::Message("1.0+2.0*I".into())], out);
+        }
+        {
+            let state = AppState::rpn(Vec::new(), false);
+            let expected = Err((state.clone(), CalcError::MissingOperand("You must insert a number first".into())));
+
+            assert_eq!(expected, eval_cmd(state, Command::CopyAs(Dialect::Json), &mut Vec::new()));
+        }
     }
 }
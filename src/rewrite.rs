@@ -0,0 +1,98 @@
+use regex::{Captures, Regex};
+use error::{CalcError, Span};
+
+// Text edited by regex replacements that remembers which bytes of the original each byte came
+// from, so errors found in the edited text can point at what was typed
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rewrite {
+    text: String,
+    origins: Vec<(usize, usize)>,
+    len: usize
+}
+
+impl Rewrite {
+    pub fn new(text: &str) -> Rewrite {
+        Rewrite {
+            text: text.to_owned(),
+            origins: (0..text.len()).map(|i| (i, i + 1)).collect(),
+            len: text.len()
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    // Replacements that leave a match as it was keep its bytes mapped one to one
+    pub fn replace_all<F>(self, re: &Regex, replace: F) -> Rewrite where F: Fn(&Captures) -> String {
+        let mut text = String::with_capacity(self.text.len());
+        let mut origins = Vec::with_capacity(self.origins.len());
+        let mut last = 0;
+        for caps in re.captures_iter(&self.text) {
+            let found = caps.get(0).unwrap();
+            let replacement = replace(&caps);
+            if replacement == found.as_str() {
+                continue;
+            }
+            text.push_str(&self.text[last..found.start()]);
+            origins.extend_from_slice(&self.origins[last..found.start()]);
+            let origin = self.origin(found.start(), found.end());
+            text.push_str(&replacement);
+            origins.extend((0..replacement.len()).map(|_| origin));
+            last = found.end();
+        }
+        text.push_str(&self.text[last..]);
+        origins.extend_from_slice(&self.origins[last..]);
+        Rewrite {
+            text: text,
+            origins: origins,
+            len: self.len
+        }
+    }
+
+    fn origin(&self, start: usize, end: usize) -> (usize, usize) {
+        let end = end.min(self.origins.len());
+        if start >= end {
+            let at = self.origins.get(start).map_or(self.len, |origin| origin.0);
+            (at, at)
+        } else {
+            (self.origins[start].0, self.origins[end - 1].1)
+        }
+    }
+
+    // The span of the original text that a span of the edited one came from
+    pub fn span(&self, span: Span) -> Span {
+        let (start, end) = self.origin(span.start, span.end);
+        Span::new(start, end)
+    }
+
+    pub fn error(&self, error: CalcError) -> CalcError {
+        match error {
+            CalcError::Parse { message, span } => CalcError::parse(message, self.span(span)),
+            error => error
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans_map_back_to_the_original__350() {
+        let re = Regex::new(r"<(\w+)>").unwrap();
+        let text = Rewrite::new("a <bb> c <d> e").replace_all(&re, |caps: &Captures| caps[1].to_uppercase());
+        assert_eq!("a BB c D e", text.text());
+        assert_eq!(Span::new(13, 14), text.span(Span::new(9, 10)));
+        assert_eq!(Span::new(2, 6), text.span(Span::new(2, 3)));
+        assert_eq!(Span::new(2, 12), text.span(Span::new(2, 8)));
+        assert_eq!(Span::new(14, 14), text.span(Span::new(10, 10)));
+
+        let same = Rewrite::new("a <b>").replace_all(&re, |caps: &Captures| caps[0].to_owned());
+        assert_eq!(Rewrite::new("a <b>"), same);
+    }
+}
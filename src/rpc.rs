@@ -0,0 +1,363 @@
+use json::{Json, parse};
+use session::Session;
+use state::AppState;
+use event::Event;
+use error::CalcError;
+use command::{Command, parse_line_with, command_names};
+use complex::Complex;
+use value::Value;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const CALC_ERROR: i64 = -32000;
+
+fn number_json(num: &Complex) -> Json {
+    Json::Array(vec![Json::Number(num.real()), Json::Number(num.imaginary())])
+}
+
+// Numbers stay [re, im] pairs, so a list is an array of pairs and a matrix an array of those
+fn value_json(value: &Value) -> Json {
+    match *value {
+        Value::Scalar(ref num) => number_json(num),
+        Value::List(ref items) => Json::Array(items.iter().map(number_json).collect()),
+        Value::Matrix(ref rows) => Json::Array(rows.iter().map(|r| Json::Array(r.iter().map(number_json).collect())).collect()),
+        Value::Bool(b) => Json::Bool(b),
+        Value::Text(ref text) => Json::string(text.as_str())
+    }
+}
+
+pub fn state_json(state: &AppState) -> Json {
+    Json::object(vec![
+        ("mode", Json::string(format!("{}", state.mode))),
+        ("number", state.number.as_ref().map_or(Json::Null, value_json)),
+        ("pending_op", state.pending_op.as_ref().map_or(Json::Null, |op| Json::string(format!("{}", op)))),
+        ("stack", Json::Array(state.stack.iter().map(value_json).collect())),
+        ("settings", Json::object(vec![
+            ("precision", Json::Number(state.settings.precision as f64)),
+            ("angle", Json::string(format!("{}", state.settings.angle))),
+            ("format", Json::string(format!("{}", state.settings.format))),
+            ("dialect", Json::string(format!("{}", state.settings.dialect))),
+            ("locale", Json::string(format!("{}", state.settings.locale)))
+        ]))
+    ])
+}
+
+pub fn error_json(error: &CalcError) -> Json {
+    let mut fields = vec![
+        ("code", Json::string(error.code())),
+        ("message", Json::string(error.message()))
+    ];
+    if let CalcError::Parse { span, .. } = *error {
+        fields.push(("span", Json::Array(vec![Json::Number(span.start as f64), Json::Number(span.end as f64)])));
+    }
+    if let Some((file, line)) = error.location() {
+        fields.push(("file", Json::string(file)));
+        fields.push(("line", Json::Number(line as f64)));
+    }
+    Json::object(fields)
+}
+
+pub fn event_json(event: &Event) -> Json {
+    let (kind, value) = match *event {
+        Event::Result(ref value) => ("result", value_json(value)),
+        Event::Real(n) => ("real", Json::Number(n)),
+        Event::Imaginary(n) => ("imaginary", Json::Number(n)),
+        Event::Argument(n) => ("argument", Json::Number(n)),
+        Event::Polar(r, theta, unit) => ("polar", Json::object(vec![
+            ("modulus", Json::Number(r)),
+            ("argument", Json::Number(theta)),
+            ("unit", Json::string(format!("{}", unit)))
+        ])),
+        Event::Stack(ref stack) => ("stack", Json::Array(stack.iter().map(value_json).collect())),
+        Event::Message(ref msg) => ("message", Json::string(msg.as_str())),
+        Event::Error(ref e) => ("error", error_json(e)),
+        Event::Exit => ("exit", Json::Null),
+    };
+    Json::object(vec![("type", Json::string(kind)), ("value", value)])
+}
+
+pub fn rpc_error(code: i64, message: &str, data: Option<Json>) -> Json {
+    let mut fields = vec![("code", Json::Number(code as f64)), ("message", Json::string(message))];
+    if let Some(data) = data {
+        fields.push(("data", data));
+    }
+    Json::object(fields)
+}
+
+fn param<'a>(params: Option<&'a Json>, name: &str, index: usize) -> Option<&'a Json> {
+    match params {
+        Some(&Json::Array(ref items)) => items.get(index),
+        Some(params) => params.get(name),
+        None => None
+    }
+}
+
+pub fn string_param(params: Option<&Json>, name: &str, index: usize) -> Result<String, Json> {
+    match param(params, name, index) {
+        Some(&Json::String(ref s)) => Ok(s.clone()),
+        _ => Err(rpc_error(INVALID_PARAMS, &format!("Missing string parameter '{}'", name), None))
+    }
+}
+
+pub fn response(id: Option<Json>, result: Result<Json, Json>) -> Json {
+    let mut fields = vec![("jsonrpc", Json::string("2.0")), ("id", id.unwrap_or(Json::Null))];
+    match result {
+        Ok(result) => fields.push(("result", result)),
+        Err(error) => fields.push(("error", error))
+    }
+    Json::object(fields)
+}
+
+pub fn parse_error(message: String) -> Json {
+    response(None, Err(rpc_error(PARSE_ERROR, "Parse error", Some(Json::string(message)))))
+}
+
+pub struct Handler {
+    initial: AppState,
+    finished: bool
+}
+
+impl Handler {
+    pub fn new(initial: AppState) -> Handler {
+        Handler {
+            initial: initial,
+            finished: false
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn outcome(&mut self, session: &Session, events: Vec<Event>) -> Result<Json, Json> {
+        if events.contains(&Event::Exit) {
+            self.finished = true;
+        }
+        let state = state_json(session.state());
+        match events.iter().position(|e| match *e { Event::Error(_) => true, _ => false }) {
+            Some(i) => {
+                let error = match events[i] {
+                    Event::Error(ref e) => e.clone(),
+                    _ => unreachable!()
+                };
+                let data = Json::object(vec![
+                    ("error", error_json(&error)),
+                    ("events", Json::Array(events[..i].iter().map(event_json).collect())),
+                    ("state", state)
+                ]);
+                Err(rpc_error(CALC_ERROR, error.message(), Some(data)))
+            },
+            None => Ok(Json::object(vec![
+                ("events", Json::Array(events.iter().map(event_json).collect())),
+                ("state", state)
+            ]))
+        }
+    }
+
+    fn call(&mut self, session: &mut Session, method: &str, params: Option<&Json>) -> Result<Json, Json> {
+        match method {
+            "eval" => {
+                let expr = string_param(params, "expr", 0)?;
+                let events = session.eval_line(&expr);
+                self.outcome(session, events)
+            },
+            "command" => {
+                let mut text = string_param(params, "name", 0)?;
+                match param(params, "args", 1) {
+                    Some(&Json::Array(ref args)) => for arg in args {
+                        match *arg {
+                            Json::String(ref s) => text.push_str(&format!(" {}", s)),
+                            Json::Number(_) => text.push_str(&format!(" {}", arg)),
+                            _ => return Err(rpc_error(INVALID_PARAMS, "Arguments must be strings or numbers", None))
+                        }
+                    },
+                    None => (),
+                    _ => return Err(rpc_error(INVALID_PARAMS, "Parameter 'args' must be an array", None))
+                }
+                match parse_line_with(&text, &session.state().settings, session.limits().max_depth) {
+                    Ok(ref cmds) if cmds.len() == 1 => {
+                        let events = session.eval(cmds[0].clone());
+                        self.outcome(session, events)
+                    },
+                    Ok(_) => Err(rpc_error(INVALID_PARAMS, "Expecting a single command", None)),
+                    Err(e) => self.outcome(session, vec![Event::Error(e)])
+                }
+            },
+            "state" => Ok(Json::object(vec![("state", state_json(session.state()))])),
+            "reset" => {
+                session.reset(self.initial.clone());
+                Ok(Json::object(vec![("state", state_json(session.state()))]))
+            },
+            "undo" => {
+                let events = session.eval(Command::Undo);
+                self.outcome(session, events)
+            },
+            "complete" => {
+                let prefix = string_param(params, "prefix", 0)?.to_lowercase();
+                let mut names: Vec<String> = command_names().into_iter()
+                    .filter(|name| name.to_lowercase().starts_with(prefix.as_str()))
+                    .collect();
+                names.sort();
+                let completions = names.into_iter().map(Json::String).collect();
+                Ok(Json::object(vec![("completions", Json::Array(completions))]))
+            },
+            _ => Err(rpc_error(METHOD_NOT_FOUND, &format!("Method not found: {}", method), None))
+        }
+    }
+
+    fn handle_request(&mut self, session: &mut Session, request: &Json) -> Option<Json> {
+        let id = request.get("id").cloned();
+        let result = match (request.get("jsonrpc"), request.get("method")) {
+            (Some(&Json::String(ref v)), Some(&Json::String(ref method))) if v == "2.0" => {
+                let method = method.clone();
+                let result = self.call(session, &method, request.get("params"));
+                if id.is_none() {
+                    return None;
+                }
+                result
+            },
+            _ => Err(rpc_error(INVALID_REQUEST, "Invalid request", None))
+        };
+        Some(response(id, result))
+    }
+
+    pub fn handle(&mut self, session: &mut Session, request: &Json) -> Option<Json> {
+        match *request {
+            Json::Array(ref requests) if !requests.is_empty() => {
+                let responses: Vec<Json> = requests.iter().filter_map(|r| self.handle_request(session, r)).collect();
+                if responses.is_empty() {
+                    None
+                } else {
+                    Some(Json::Array(responses))
+                }
+            },
+            ref request => self.handle_request(session, request)
+        }
+    }
+}
+
+pub struct Server {
+    session: Session,
+    handler: Handler
+}
+
+impl Server {
+    pub fn new(session: Session) -> Server {
+        let handler = Handler::new(session.state().clone());
+        Server {
+            session: session,
+            handler: handler
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handler.is_finished()
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn handle_line(&mut self, line: &str) -> Option<String> {
+        let response = match parse(line, self.session.limits().max_depth) {
+            Ok(request) => self.handler.handle(&mut self.session, &request),
+            Err(msg) => Some(parse_error(msg))
+        };
+        response.map(|r| format!("{}", r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::Settings;
+    use locale::Locale;
+
+    fn server() -> Server {
+        Server::new(Session::new(AppState::default()))
+    }
+
+    #[test]
+    fn test_rpc_eval__213() {
+        let mut server = server();
+        let expected = "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"events\":[{\"type\":\"result\",\"value\":[3,1]}],\
+                        \"state\":{\"mode\":\"algebraic\",\"number\":[3,1],\"pending_op\":null,\"stack\":[],\
+                        \"settings\":{\"precision\":3,\"angle\":\"rad\",\"format\":\"fixed rect j nozeros nogroup\",\"dialect\":\"native\",\"locale\":\"en\"}}}}";
+
+        let output = server.handle_line(r#"{"jsonrpc": "2.0", "id": 1, "method": "eval", "params": {"expr": "1+1j + 2"}}"#);
+
+        assert_eq!(Some(expected.to_owned()), output);
+    }
+
+    #[test]
+    fn test_rpc_command_undo_and_reset__214() {
+        let mut server = server();
+        server.handle_line(r#"{"jsonrpc": "2.0", "id": 1, "method": "eval", "params": ["2"]}"#);
+        server.handle_line(r#"{"jsonrpc": "2.0", "id": 2, "method": "command", "params": {"name": "power", "args": [3]}}"#);
+        assert_eq!(Some(&Complex::new(8.0, 0.0).into()), server.session().state().result());
+
+        server.handle_line(r#"{"jsonrpc": "2.0", "id": 3, "method": "undo"}"#);
+        assert_eq!(Some(&Complex::new(2.0, 0.0).into()), server.session().state().result());
+
+        server.handle_line(r#"{"jsonrpc": "2.0", "id": 4, "method": "reset"}"#);
+        assert_eq!(&AppState::default(), server.session().state());
+    }
+
+    #[test]
+    fn test_rpc_errors__215__216__217__218() {
+        let mut server = server();
+        {
+            let output = server.handle_line("{").unwrap();
+
+            assert!(output.starts_with("{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{\"code\":-32700,"));
+        }
+        {
+            let output = server.handle_line(r#"{"jsonrpc": "2.0", "id": "a", "method": "fly"}"#).unwrap();
+
+            assert!(output.contains("\"id\":\"a\",\"error\":{\"code\":-32601,"));
+        }
+        {
+            let output = server.handle_line(r#"{"jsonrpc": "2.0", "id": 5, "method": "eval", "params": {"expr": "1 ^"}}"#).unwrap();
+
+            assert!(output.contains("\"error\":{\"code\":-32000,\"message\":\"Expecting a number\",\"data\":{\"error\":\
+                                     {\"code\":\"E002\",\"message\":\"Expecting a number\",\"span\":[2,3]}"));
+        }
+        {
+            assert_eq!(None, server.handle_line(r#"{"jsonrpc": "2.0", "method": "eval", "params": ["1"]}"#));
+        }
+    }
+
+    #[test]
+    fn test_rpc_complete__219() {
+        let mut server = server();
+        let expected = "{\"jsonrpc\":\"2.0\",\"id\":9,\"result\":{\"completions\":[\"real\",\"redo\",\"reduce\",\"roll\",\"root\"]}}";
+
+        let lower = server.handle_line(r#"{"jsonrpc": "2.0", "id": 9, "method": "complete", "params": {"prefix": "r"}}"#);
+        let upper = server.handle_line(r#"{"jsonrpc": "2.0", "id": 9, "method": "complete", "params": {"prefix": "R"}}"#);
+
+        assert_eq!(Some(expected.to_owned()), lower);
+        assert_eq!(Some(expected.to_owned()), upper);
+    }
+
+    #[test]
+    fn test_rpc_nesting_and_locale__362__363() {
+        {
+            let mut server = server();
+            let deep = format!("{{\"jsonrpc\": \"2.0\", \"id\": 1, \"params\": {}", "[".repeat(30000));
+            let output = server.handle_line(&deep).unwrap();
+
+            assert!(output.starts_with("{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{\"code\":-32700,"));
+        }
+        {
+            let mut settings = Settings::default();
+            settings.locale = Locale::Es;
+            let mut server = Server::new(Session::new(AppState::default().with_settings(settings)));
+            server.handle_line(r#"{"jsonrpc": "2.0", "id": 1, "method": "eval", "params": ["2"]}"#);
+            server.handle_line(r#"{"jsonrpc": "2.0", "id": 2, "method": "command", "params": {"name": "power", "args": ["0,5"]}}"#);
+
+            assert_eq!(Some(&Complex::new(2.0, 0.0).power(0.5).into()), server.session().state().result());
+        }
+    }
+}
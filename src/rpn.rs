@@ -0,0 +1,326 @@
+use state::AppState;
+use command::Command;
+use event::Event;
+use error::{CalcError, CalcResult};
+use actions::{divide, trig};
+use complex::Complex;
+use value::Value;
+
+macro_rules! single {
+    ($state:expr, $name:expr, $kind:expr) => {{
+        let message = format!("{} needs a single number, not a {}", $name, $kind);
+        Err(($state, CalcError::Domain(message)))
+    }}
+}
+
+macro_rules! needs {
+    ($state:expr, 1) => (Err(($state, CalcError::MissingOperand("The stack is empty".into()))));
+    ($state:expr, $n:expr) => (Err(($state, CalcError::MissingOperand(format!("You need at least {} numbers on the stack", $n)))))
+}
+
+pub fn clear() -> CalcResult {
+    Ok(AppState::rpn(Vec::new(), false))
+}
+
+pub fn push(num: Value, mut state: AppState) -> CalcResult {
+    state.stack.push(num);
+    state.log = true;
+    Ok(state)
+}
+
+pub fn apply_binary(action: Command, mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    if state.stack.len() < 2 {
+        return needs!(state, 2);
+    }
+    let result = {
+        let b = &state.stack[state.stack.len() - 1];
+        let a = &state.stack[state.stack.len() - 2];
+        match action {
+            Command::Addition => a.broadcast(b, |x, y| Ok(x.clone() + y.clone())),
+            Command::Subtraction => a.broadcast(b, |x, y| Ok(x.clone() - y.clone())),
+            Command::Multiplication => a.broadcast(b, |x, y| Ok(x.clone() * y.clone())),
+            Command::Division => a.broadcast(b, divide),
+            _ => unreachable!()
+        }
+    };
+    match result {
+        Ok(new_num) => {
+            let len = state.stack.len();
+            state.stack.truncate(len - 2);
+            out.push(Event::Result(new_num.clone()));
+            state.stack.push(new_num);
+            state.log = true;
+            Ok(state)
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn do_power(num: f64, mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let result = match state.stack.last() {
+        Some(value) => value.map(|cplx| Ok(cplx.power(num))),
+        None => return needs!(state, 1)
+    };
+    match result {
+        Ok(new_num) => {
+            out.push(Event::Result(new_num.clone()));
+            state.stack.pop();
+            state.stack.push(new_num);
+            state.log = true;
+            Ok(state)
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn do_root(num: f64, mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let result = match state.stack.last() {
+        Some(value) => value.map(|cplx| Ok(cplx.root(num))),
+        None => return needs!(state, 1)
+    };
+    match result {
+        Ok(new_num) => {
+            out.push(Event::Result(new_num.clone()));
+            state.stack.pop();
+            state.stack.push(new_num);
+            state.log = true;
+            Ok(state)
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn print_real(mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    match state.stack.last().map(|value| value.as_scalar().map(|cplx| cplx.real()).ok_or(value.kind())) {
+        Some(Ok(real)) => {
+            out.push(Event::Real(real));
+            state.log = false;
+            Ok(state)
+        },
+        Some(Err(kind)) => single!(state, "real", kind),
+        None => needs!(state, 1)
+    }
+}
+
+pub fn print_imaginary(mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    match state.stack.last().map(|value| value.as_scalar().map(|cplx| cplx.imaginary()).ok_or(value.kind())) {
+        Some(Ok(imaginary)) => {
+            out.push(Event::Imaginary(imaginary));
+            state.log = false;
+            Ok(state)
+        },
+        Some(Err(kind)) => single!(state, "imaginary", kind),
+        None => needs!(state, 1)
+    }
+}
+
+pub fn print_argument(mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    match state.stack.last().map(|value| value.as_scalar().map(|cplx| cplx.argument()).ok_or(value.kind())) {
+        Some(Ok(argument)) => {
+            out.push(Event::Argument(state.settings.angle.from_radians(argument)));
+            state.log = false;
+            Ok(state)
+        },
+        Some(Err(kind)) => single!(state, "argument", kind),
+        None => needs!(state, 1)
+    }
+}
+
+pub fn print_polar(mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    match state.stack.last().map(|value| value.as_scalar().cloned().ok_or(value.kind())) {
+        Some(Ok(cplx)) => {
+            let angle = state.settings.angle;
+            out.push(Event::Polar(cplx.modulus(), angle.from_radians(cplx.argument()), angle));
+            state.log = false;
+            Ok(state)
+        },
+        Some(Err(kind)) => single!(state, "polar", kind),
+        None => needs!(state, 1)
+    }
+}
+
+pub fn do_trig(f: fn(&Complex) -> Complex, mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    let angle = state.settings.angle;
+    let result = match state.stack.last() {
+        Some(value) => value.map(|cplx| Ok(trig(f, angle, cplx))),
+        None => return needs!(state, 1)
+    };
+    match result {
+        Ok(new_num) => {
+            out.push(Event::Result(new_num.clone()));
+            state.stack.pop();
+            state.stack.push(new_num);
+            state.log = true;
+            Ok(state)
+        },
+        Err(e) => Err((state, e))
+    }
+}
+
+pub fn swap(mut state: AppState) -> CalcResult {
+    let len = state.stack.len();
+    if len < 2 {
+        return needs!(state, 2);
+    }
+    state.stack.swap(len - 1, len - 2);
+    state.log = false;
+    Ok(state)
+}
+
+pub fn dup(mut state: AppState) -> CalcResult {
+    match state.stack.last().cloned() {
+        Some(top) => {
+            state.stack.push(top);
+            state.log = false;
+            Ok(state)
+        },
+        None => needs!(state, 1)
+    }
+}
+
+pub fn drop(mut state: AppState) -> CalcResult {
+    match state.stack.pop() {
+        Some(_) => {
+            state.log = false;
+            Ok(state)
+        },
+        None => needs!(state, 1)
+    }
+}
+
+pub fn roll(mut state: AppState) -> CalcResult {
+    match state.stack.pop() {
+        Some(top) => {
+            state.stack.insert(0, top);
+            state.log = false;
+            Ok(state)
+        },
+        None => needs!(state, 1)
+    }
+}
+
+pub fn print_stack(mut state: AppState, out: &mut Vec<Event>) -> CalcResult {
+    out.push(Event::Stack(state.stack.clone()));
+    state.log = false;
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use complex::Complex;
+
+    fn c(real: f64) -> Value {
+        Complex::new(real, 0.0).into()
+    }
+
+    #[test]
+    fn test_push_number__093() {
+        let input_state = AppState::rpn(vec![c(1.0)], false);
+        let expected = Ok(AppState::rpn(vec![c(1.0), c(2.0)], true));
+
+        assert_eq!(expected, push(c(2.0), input_state));
+    }
+
+    #[test]
+    fn test_binary_operations__094__095__096__097() {
+        {
+            let input_state = AppState::rpn(vec![c(1.0), c(2.0), c(3.0)], false);
+            let expected = Ok(AppState::rpn(vec![c(1.0), c(5.0)], true));
+
+            assert_eq!(expected, apply_binary(Command::Addition, input_state, &mut Vec::new()));
+        }
+        {
+            let input_state = AppState::rpn(vec![c(5.0), c(3.0)], false);
+            let expected = Ok(AppState::rpn(vec![c(2.0)], true));
+
+            assert_eq!(expected, apply_binary(Command::Subtraction, input_state, &mut Vec::new()));
+        }
+        {
+            let input_state = AppState::rpn(vec![c(2.0), c(3.0)], false);
+            let expected = Ok(AppState::rpn(vec![c(6.0)], true));
+
+            assert_eq!(expected, apply_binary(Command::Multiplication, input_state, &mut Vec::new()));
+        }
+        {
+            let input_state = AppState::rpn(vec![c(2.0)], false);
+            let expected = Err((AppState::rpn(vec![c(2.0)], false), CalcError::MissingOperand("You need at least 2 numbers on the stack".into())));
+
+            assert_eq!(expected, apply_binary(Command::Addition, input_state, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_division__129__130() {
+        {
+            let input_state = AppState::rpn(vec![c(6.0), c(3.0)], false);
+            let expected = Ok(AppState::rpn(vec![c(2.0)], true));
+
+            assert_eq!(expected, apply_binary(Command::Division, input_state, &mut Vec::new()));
+        }
+        {
+            let input_state = AppState::rpn(vec![c(6.0), c(0.0)], false);
+            let expected = Err((AppState::rpn(vec![c(6.0), c(0.0)], false), CalcError::Domain("Division by zero".into())));
+
+            assert_eq!(expected, apply_binary(Command::Division, input_state, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_power_and_root__098__099() {
+        {
+            let input_state = AppState::rpn(vec![Complex::new(2.0, 2.0).into()], false);
+            let expected = Ok(AppState::rpn(vec![Complex::new(0.0000000000000004898587196589414, 8.000000000000002).into()], true));
+
+            assert_eq!(expected, do_power(2.0, input_state, &mut Vec::new()));
+        }
+        {
+            let input_state = AppState::rpn(vec![], false);
+            let expected = Err((AppState::rpn(vec![], false), CalcError::MissingOperand("The stack is empty".into())));
+
+            assert_eq!(expected, do_root(2.0, input_state, &mut Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_stack_manipulation__100__101__102__103() {
+        {
+            let input_state = AppState::rpn(vec![c(1.0), c(2.0)], true);
+            let expected = Ok(AppState::rpn(vec![c(2.0), c(1.0)], false));
+
+            assert_eq!(expected, swap(input_state));
+        }
+        {
+            let input_state = AppState::rpn(vec![c(1.0)], false);
+            let expected = Ok(AppState::rpn(vec![c(1.0), c(1.0)], false));
+
+            assert_eq!(expected, dup(input_state));
+        }
+        {
+            let input_state = AppState::rpn(vec![c(1.0), c(2.0)], false);
+            let expected = Ok(AppState::rpn(vec![c(1.0)], false));
+
+            assert_eq!(expected, drop(input_state));
+        }
+        {
+            let input_state = AppState::rpn(vec![c(1.0), c(2.0), c(3.0)], false);
+            let expected = Ok(AppState::rpn(vec![c(3.0), c(1.0), c(2.0)], false));
+
+            assert_eq!(expected, roll(input_state));
+        }
+    }
+
+    #[test]
+    fn test_stack_manipulation_on_empty_stack__104__105() {
+        {
+            let expected = Err((AppState::rpn(vec![c(1.0)], false), CalcError::MissingOperand("You need at least 2 numbers on the stack".into())));
+
+            assert_eq!(expected, swap(AppState::rpn(vec![c(1.0)], false)));
+        }
+        {
+            let expected = Err((AppState::rpn(vec![], false), CalcError::MissingOperand("The stack is empty".into())));
+
+            assert_eq!(expected, dup(AppState::rpn(vec![], false)));
+        }
+    }
+}
@@ -0,0 +1,683 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::mem;
+use command::Token;
+use error::{CalcError, Span};
+use event::Event;
+use input::Source;
+use limits::Budget;
+use registry;
+use session::Session;
+use state::{AppState, Mode};
+use complex::Complex;
+
+const KEYWORDS: [&str; 5] = ["if", "else", "end", "while", "for"];
+
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(word))
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual
+}
+
+impl Comparison {
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Comparison::Less => write!(f, "<"),
+            Comparison::LessOrEqual => write!(f, "<="),
+            Comparison::Greater => write!(f, ">"),
+            Comparison::GreaterOrEqual => write!(f, ">="),
+            Comparison::Equal => write!(f, "=="),
+            Comparison::NotEqual => write!(f, "!="),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    Number(f64),
+    Variable(String),
+    Real,
+    Imaginary,
+    Argument,
+    // How many numbers are available: the stack size in RPN, 0 or 1 otherwise
+    Depth
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Operand::Number(n) => write!(f, "{}", n),
+            Operand::Variable(ref name) => write!(f, "{}", name),
+            Operand::Real => write!(f, "real"),
+            Operand::Imaginary => write!(f, "imaginary"),
+            Operand::Argument => write!(f, "argument"),
+            Operand::Depth => write!(f, "depth"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Condition {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand
+}
+
+impl Condition {
+    pub fn evaluate(&self, state: &AppState) -> Result<bool, CalcError> {
+        test(self, state, &[])
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} {} {}", self.left, self.comparison, self.right)
+    }
+}
+
+// Conditions outside scripts, such as those of filter, cannot refer to loop variables
+pub fn parse_condition(text: &str) -> Result<Condition, CalcError> {
+    Parser::default().parse_condition(Token::new(text, 0))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Line(usize, String),
+    If { line: usize, condition: Condition, then: Vec<Statement>, otherwise: Vec<Statement> },
+    While { line: usize, condition: Condition, body: Vec<Statement> },
+    // Runs with the variable taking every integer in from..to, the end excluded
+    For { line: usize, variable: String, from: Operand, to: Operand, body: Vec<Statement> }
+}
+
+enum Block {
+    If(Condition, Vec<Statement>, Option<Vec<Statement>>),
+    While(Condition, Vec<Statement>),
+    For(String, Operand, Operand, Vec<Statement>)
+}
+
+impl Block {
+    fn name(&self) -> &'static str {
+        match *self {
+            Block::If(..) => "if",
+            Block::While(..) => "while",
+            Block::For(..) => "for",
+        }
+    }
+
+    fn body(&mut self) -> &mut Vec<Statement> {
+        match *self {
+            Block::If(_, _, Some(ref mut body)) |
+            Block::If(_, ref mut body, None) |
+            Block::While(_, ref mut body) |
+            Block::For(_, _, _, ref mut body) => body,
+        }
+    }
+}
+
+// Collects lines until a whole statement is known, so top-level commands still run as they arrive
+pub struct Parser {
+    open: Vec<(usize, Block)>
+}
+
+impl Parser {
+    pub fn default() -> Parser {
+        Parser {
+            open: Vec::new()
+        }
+    }
+
+    pub fn push(&mut self, number: usize, line: &str) -> Result<Option<Statement>, CalcError> {
+        let code = Token::new(line, 0);
+        let code = code.slice(0, comment_start(line)).trim();
+        let first = code.text.split_whitespace().next().unwrap_or("");
+        let keyword = first.to_lowercase();
+        let rest = code.slice(first.len(), code.text.len()).trim();
+        let statement = match keyword.as_str() {
+            "" => return Ok(None),
+            "if" => {
+                let condition = self.parse_condition(rest)?;
+                self.open.push((number, Block::If(condition, Vec::new(), None)));
+                return Ok(None);
+            },
+            "while" => {
+                let condition = self.parse_condition(rest)?;
+                self.open.push((number, Block::While(condition, Vec::new())));
+                return Ok(None);
+            },
+            "for" => {
+                let (variable, from, to) = self.parse_range(rest)?;
+                self.open.push((number, Block::For(variable, from, to, Vec::new())));
+                return Ok(None);
+            },
+            "else" => {
+                match self.open.last_mut() {
+                    Some(&mut (_, Block::If(_, _, ref mut otherwise @ None))) if rest.text.is_empty() => {
+                        *otherwise = Some(Vec::new());
+                        return Ok(None);
+                    },
+                    _ => return Err(CalcError::parse("else must follow an if on its own line", code.span()))
+                }
+            },
+            "end" => {
+                if !rest.text.is_empty() {
+                    return Err(CalcError::parse("end must be alone on its line", rest.span()));
+                }
+                match self.open.pop() {
+                    Some((line, Block::If(condition, then, otherwise))) => Statement::If {
+                        line: line,
+                        condition: condition,
+                        then: then,
+                        otherwise: otherwise.unwrap_or_default()
+                    },
+                    Some((line, Block::While(condition, body))) => Statement::While {
+                        line: line,
+                        condition: condition,
+                        body: body
+                    },
+                    Some((line, Block::For(variable, from, to, body))) => Statement::For {
+                        line: line,
+                        variable: variable,
+                        from: from,
+                        to: to,
+                        body: body
+                    },
+                    None => return Err(CalcError::parse("end without an open block", code.span()))
+                }
+            },
+            _ => Statement::Line(number, code.text.to_owned())
+        };
+        match self.open.last_mut() {
+            Some(&mut (_, ref mut block)) => {
+                block.body().push(statement);
+                Ok(None)
+            },
+            None => Ok(Some(statement))
+        }
+    }
+
+    // Reports the innermost block still open when the input ends, with the line that opened it
+    pub fn finish(&mut self) -> Result<(), (usize, CalcError)> {
+        match self.open.pop() {
+            Some((line, block)) => {
+                self.open.clear();
+                Err((line, CalcError::parse(format!("Missing end for the {} block", block.name()), Span::new(0, 0))))
+            },
+            None => Ok(())
+        }
+    }
+
+    fn is_variable(&self, word: &str) -> bool {
+        self.open.iter().any(|&(_, ref block)| match *block {
+            Block::For(ref variable, ..) => variable == word,
+            _ => false
+        })
+    }
+
+    fn parse_operand(&self, word: Token) -> Result<Operand, CalcError> {
+        let span = word.span();
+        let word = word.text;
+        match word.to_lowercase().as_str() {
+            "real" => Ok(Operand::Real),
+            "imaginary" => Ok(Operand::Imaginary),
+            "argument" => Ok(Operand::Argument),
+            "depth" => Ok(Operand::Depth),
+            _ => match word.parse::<f64>() {
+                Ok(n) => Ok(Operand::Number(n)),
+                Err(_) if self.is_variable(word) => Ok(Operand::Variable(word.to_owned())),
+                Err(_) if word.is_empty() => Err(CalcError::parse("Expecting a value", span)),
+                Err(_) => Err(CalcError::parse(format!("Unknown value: {}", word), span))
+            }
+        }
+    }
+
+    fn parse_condition(&self, text: Token) -> Result<Condition, CalcError> {
+        let (at, comparison, width) = match text.text.find(|c| "<>=!".contains(c)) {
+            Some(i) => match &text.text[i..] {
+                s if s.starts_with("<=") => (i, Comparison::LessOrEqual, 2),
+                s if s.starts_with(">=") => (i, Comparison::GreaterOrEqual, 2),
+                s if s.starts_with("==") => (i, Comparison::Equal, 2),
+                s if s.starts_with("!=") => (i, Comparison::NotEqual, 2),
+                s if s.starts_with('<') => (i, Comparison::Less, 1),
+                s if s.starts_with('>') => (i, Comparison::Greater, 1),
+                _ => return Err(CalcError::parse("Expecting <, <=, >, >=, == or !=", text.slice(i, i + 1).span()))
+            },
+            None => return Err(CalcError::parse("Expecting a comparison such as real > 0", text.span()))
+        };
+        Ok(Condition {
+            left: self.parse_operand(text.slice(0, at).trim())?,
+            comparison: comparison,
+            right: self.parse_operand(text.slice(at + width, text.text.len()).trim())?
+        })
+    }
+
+    fn parse_range(&self, text: Token) -> Result<(String, Operand, Operand), CalcError> {
+        let words = text.words();
+        let variable = match words.first() {
+            Some(word) if word.text.chars().all(|c| c.is_alphanumeric() || c == '_') &&
+                !word.text.starts_with(char::is_numeric) => *word,
+            _ => return Err(CalcError::parse("Expecting a loop variable such as k", text.span()))
+        };
+        let name = variable.text;
+        if is_keyword(name) || registry::find(name).is_some() || name.eq_ignore_ascii_case("depth") {
+            return Err(CalcError::parse(format!("{} cannot be used as a variable", name), variable.span()));
+        }
+        let range = match words.get(1) {
+            Some(word) if word.text == "in" => text.slice(word.end() - text.start, text.text.len()).trim(),
+            _ => return Err(CalcError::parse("Expecting for <variable> in <from>..<to>", text.span()))
+        };
+        match range.text.find("..") {
+            Some(i) => Ok((name.to_owned(), self.parse_operand(range.slice(0, i).trim())?,
+                           self.parse_operand(range.slice(i + 2, range.text.len()).trim())?)),
+            None => Err(CalcError::parse("Expecting a range such as 0..10", range.span()))
+        }
+    }
+}
+
+pub struct Runner {
+    file: String,
+    parser: Parser,
+    variables: Vec<(String, f64)>
+}
+
+impl Runner {
+    pub fn new<S: Into<String>>(file: S) -> Runner {
+        Runner {
+            file: file.into(),
+            parser: Parser::default(),
+            variables: Vec::new()
+        }
+    }
+
+    // Each top-level statement gets a fresh budget, like a line typed at the prompt
+    pub fn feed(&mut self, session: &mut Session, number: usize, line: &str) -> Vec<Event> {
+        let mut out = Vec::new();
+        let mut budget = session.budget();
+        self.feed_with(session, number, line, &mut budget, &mut out);
+        out
+    }
+
+    pub fn finish(&mut self) -> Vec<Event> {
+        match self.parser.finish() {
+            Ok(()) => Vec::new(),
+            Err((line, e)) => vec![Event::Error(CalcError::at(self.file.as_str(), line, e))]
+        }
+    }
+
+    fn feed_with(&mut self, session: &mut Session, number: usize, line: &str, budget: &mut Budget,
+                 out: &mut Vec<Event>) -> bool {
+        match self.parser.push(number, line) {
+            Ok(Some(statement)) => {
+                let mut variables = mem::replace(&mut self.variables, Vec::new());
+                let ok = execute(session, &statement, &self.file, &mut variables, budget, out);
+                self.variables = variables;
+                ok
+            },
+            Ok(None) => true,
+            Err(e) => {
+                out.push(Event::Error(CalcError::at(self.file.as_str(), number, e)));
+                false
+            }
+        }
+    }
+}
+
+// Runs a whole script inside the evaluation that sourced it, sharing its budget
+pub fn run<S: Source>(session: &mut Session, mut input: S, file: &str, budget: &mut Budget, out: &mut Vec<Event>) -> bool {
+    let mut runner = Runner::new(file);
+    let mut number = 0;
+    loop {
+        number += 1;
+        match input.next_line() {
+            Ok(None) => break,
+            Ok(Some(line)) => if !runner.feed_with(session, number, &line, budget, out) {
+                return false;
+            },
+            Err(e) => {
+                out.push(Event::Error(CalcError::at(file, number, e)));
+                return false;
+            }
+        }
+    }
+    let errors = runner.finish();
+    let ok = errors.is_empty();
+    out.extend(errors);
+    ok
+}
+
+fn current(state: &AppState) -> Result<&Complex, CalcError> {
+    match state.result() {
+        Some(value) => value.as_scalar().ok_or_else(|| {
+            CalcError::Domain(format!("The condition needs a single number, not a {}", value.kind()))
+        }),
+        None => Err(CalcError::MissingOperand("The condition needs a current number".into()))
+    }
+}
+
+fn value(operand: &Operand, state: &AppState, variables: &[(String, f64)]) -> Result<f64, CalcError> {
+    match *operand {
+        Operand::Number(n) => Ok(n),
+        Operand::Variable(ref name) => Ok(variables.iter().rev().find(|v| &v.0 == name).map_or(0.0, |v| v.1)),
+        Operand::Real => current(state).map(|n| n.real()),
+        Operand::Imaginary => current(state).map(|n| n.imaginary()),
+        Operand::Argument => current(state).map(|n| state.settings.angle.from_radians(n.argument())),
+        Operand::Depth => Ok(match state.mode {
+            Mode::Rpn => state.stack.len(),
+            Mode::Algebraic => state.number.iter().count(),
+        } as f64),
+    }
+}
+
+fn test(condition: &Condition, state: &AppState, variables: &[(String, f64)]) -> Result<bool, CalcError> {
+    let left = value(&condition.left, state, variables)?;
+    let right = value(&condition.right, state, variables)?;
+    Ok(condition.comparison.holds(left, right))
+}
+
+fn bound(operand: &Operand, state: &AppState, variables: &[(String, f64)]) -> Result<f64, CalcError> {
+    let n = value(operand, state, variables)?;
+    if n.fract() == 0.0 {
+        Ok(n)
+    } else {
+        Err(CalcError::Domain(format!("Loop bounds must be integers, not {}", n)))
+    }
+}
+
+// Where a # comment starts; a # inside a string literal is part of the string
+fn comment_start(line: &str) -> usize {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return i,
+            _ => ()
+        }
+    }
+    line.len()
+}
+
+// Replaces whole words naming a loop variable, the innermost binding first; a negative value before a
+// superscript goes in parentheses, so k² is the square of k rather than -(k²)
+fn substitute(text: &str, variables: &[(String, f64)]) -> String {
+    let lookup = |word: &str, next: Option<char>| variables.iter().rev().find(|v| v.0 == word).map(|v| {
+        match next {
+            Some(c) if v.1 < 0.0 && "⁻⁺⁰¹²³⁴⁵⁶⁷⁸⁹".contains(c) => format!("({})", v.1),
+            _ => format!("{}", v.1)
+        }
+    });
+    let mut output = String::with_capacity(text.len());
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        // z² is z followed by a power
+        if (c.is_alphanumeric() && !"⁰¹²³⁴⁵⁶⁷⁸⁹".contains(c)) || c == '_' {
+            start = start.or(Some(i));
+        } else {
+            if let Some(s) = start.take() {
+                output.push_str(&lookup(&text[s..i], Some(c)).unwrap_or_else(|| text[s..i].to_owned()));
+            }
+            output.push(c);
+        }
+    }
+    if let Some(s) = start {
+        output.push_str(&lookup(&text[s..], None).unwrap_or_else(|| text[s..].to_owned()));
+    }
+    output
+}
+
+fn fail(file: &str, line: usize, error: CalcError, out: &mut Vec<Event>) -> bool {
+    out.push(Event::Error(CalcError::at(file, line, error)));
+    false
+}
+
+fn execute_all(session: &mut Session, statements: &[Statement], file: &str, variables: &mut Vec<(String, f64)>,
+               budget: &mut Budget, out: &mut Vec<Event>) -> bool {
+    statements.iter().all(|statement| execute(session, statement, file, variables, budget, out))
+}
+
+fn execute(session: &mut Session, statement: &Statement, file: &str, variables: &mut Vec<(String, f64)>,
+           budget: &mut Budget, out: &mut Vec<Event>) -> bool {
+    match *statement {
+        Statement::Line(line, ref text) => {
+            let start = out.len();
+            let ok = session.eval_line_with(&substitute(text, variables), budget, out);
+            for event in &mut out[start..] {
+                if let Event::Error(ref mut e) = *event {
+                    *e = CalcError::at(file, line, mem::replace(e, CalcError::Io(String::new())));
+                }
+            }
+            ok
+        },
+        Statement::If { line, ref condition, ref then, ref otherwise } => {
+            match budget.spend().and_then(|_| test(condition, session.state(), variables)) {
+                Ok(true) => execute_all(session, then, file, variables, budget, out),
+                Ok(false) => execute_all(session, otherwise, file, variables, budget, out),
+                Err(e) => fail(file, line, e, out)
+            }
+        },
+        Statement::While { line, ref condition, ref body } => loop {
+            match budget.spend().and_then(|_| test(condition, session.state(), variables)) {
+                Ok(true) => if !execute_all(session, body, file, variables, budget, out) {
+                    return false;
+                },
+                Ok(false) => return true,
+                Err(e) => return fail(file, line, e, out)
+            }
+        },
+        Statement::For { line, ref variable, ref from, ref to, ref body } => {
+            let range = bound(from, session.state(), variables)
+                .and_then(|from| bound(to, session.state(), variables).map(|to| (from, to)));
+            let (mut k, to) = match range {
+                Ok(range) => range,
+                Err(e) => return fail(file, line, e, out)
+            };
+            while k < to {
+                if let Err(e) = budget.spend() {
+                    return fail(file, line, e, out);
+                }
+                variables.push((variable.clone(), k));
+                let ok = execute_all(session, body, file, variables, budget, out);
+                variables.pop();
+                if !ok {
+                    return false;
+                }
+                k += 1.0;
+            }
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::process;
+    use command::Command;
+    use value::Value;
+
+    fn parse(input: &str) -> Result<Vec<Statement>, (usize, CalcError)> {
+        let mut parser = Parser::default();
+        let mut statements = Vec::new();
+        for (i, line) in input.lines().enumerate() {
+            match parser.push(i + 1, line) {
+                Ok(Some(statement)) => statements.push(statement),
+                Ok(None) => (),
+                Err(e) => return Err((i + 1, e))
+            }
+        }
+        parser.finish().map(|_| statements)
+    }
+
+    fn run_all(session: &mut Session, input: &str) -> Vec<Event> {
+        let mut runner = Runner::new("test.cx");
+        let mut out = Vec::new();
+        for (i, line) in input.lines().enumerate() {
+            out.extend(runner.feed(session, i + 1, line));
+        }
+        out.extend(runner.finish());
+        out
+    }
+
+    #[test]
+    fn test_parse_blocks__264() {
+        let input = "# setup\n1\nfor k in 0..depth\n  if real >= k # nested\n    real\n  else\n    2\n  end\nend";
+        let expected = Ok(vec![
+            Statement::Line(2, "1".into()),
+            Statement::For {
+                line: 3,
+                variable: "k".into(),
+                from: Operand::Number(0.0),
+                to: Operand::Depth,
+                body: vec![Statement::If {
+                    line: 4,
+                    condition: Condition { left: Operand::Real, comparison: Comparison::GreaterOrEqual,
+                                           right: Operand::Variable("k".into()) },
+                    then: vec![Statement::Line(5, "real".into())],
+                    otherwise: vec![Statement::Line(7, "2".into())]
+                }]
+            }
+        ]);
+
+        assert_eq!(expected, parse(input));
+    }
+
+    #[test]
+    fn test_parse_errors__265__266__267__268() {
+        {
+            let expected = Err((2, CalcError::parse("end without an open block", Span::new(0, 3))));
+
+            assert_eq!(expected, parse("1\nend"));
+        }
+        {
+            let expected = Err((2, CalcError::parse("Unknown value: n", Span::new(9, 10))));
+
+            assert_eq!(expected, parse("for k in 0..10\n  if k < n\n  end\nend"));
+        }
+        {
+            let expected = Err((1, CalcError::parse("real cannot be used as a variable", Span::new(4, 8))));
+
+            assert_eq!(expected, parse("for real in 0..2\nend"));
+        }
+        {
+            let expected = Err((1, CalcError::parse("Missing end for the if block", Span::new(0, 0))));
+
+            assert_eq!(expected, parse("if depth == 0\n  while real != 3\n  end"));
+        }
+    }
+
+    #[test]
+    fn test_run_control_flow__269__270() {
+        {
+            let mut session = Session::new(AppState::default());
+            let input = "0\nfor k in 1..4\n  addition k\nend\nif real == 6\n  imaginary\nelse\n  real\nend";
+            let expected = vec![Event::Result(Complex::new(1.0, 0.0).into()),
+                                Event::Result(Complex::new(3.0, 0.0).into()), Event::Result(Complex::new(6.0, 0.0).into()),
+                                Event::Imaginary(0.0)];
+
+            assert_eq!(expected, run_all(&mut session, input));
+        }
+        {
+            let mut session = Session::new(AppState::rpn(vec![], false));
+            let input = "while depth < 3\n  7\nend\nstack";
+            let seven: Value = Complex::new(7.0, 0.0).into();
+            let expected = vec![Event::Stack(vec![seven.clone(), seven.clone(), seven])];
+
+            assert_eq!(expected, run_all(&mut session, input));
+        }
+    }
+
+    #[test]
+    fn test_run_errors_have_locations__271__272() {
+        {
+            let mut session = Session::new(AppState::default());
+            let expected = vec![Event::Error(CalcError::at("test.cx", 2, CalcError::MissingOperand(
+                "The condition needs a current number".into())))];
+
+            assert_eq!(expected, run_all(&mut session, "# nothing yet\nwhile real < 1\n  1\nend"));
+        }
+        {
+            let mut limits = ::limits::Limits::default();
+            limits.max_steps = 20;
+            let mut session = Session::new(AppState::default()).with_limits(limits);
+            let events = run_all(&mut session, "1\nwhile real > 0\n  1\nend");
+            let expected = Event::Error(CalcError::at("test.cx", 2, CalcError::Limit(
+                "The evaluation took more than 20 steps".into())));
+
+            assert_eq!(Some(&expected), events.last());
+        }
+    }
+
+    #[test]
+    fn test_source_files__273__274() {
+        let path = env::temp_dir().join(format!("complex_source_test_{}.cx", process::id()));
+        let name = path.display().to_string();
+        {
+            File::create(&path).unwrap().write_all(b"2\nfor k in 0..2\n  power k\nend\nclear\nreal\n").unwrap();
+            let mut session = Session::new(AppState::default());
+            let events = session.eval(Command::Source(name.clone()));
+            let expected = Event::Error(CalcError::at(name.as_str(), 6, CalcError::MissingOperand(
+                "You must insert a number first".into())));
+
+            assert_eq!(Some(&expected), events.last());
+        }
+        {
+            File::create(&path).unwrap().write_all(format!("source {}\n", name).as_bytes()).unwrap();
+            let mut session = Session::new(AppState::default());
+            let events = session.eval_line(&format!("source {}", name));
+            let expected = vec![Event::Error(CalcError::at(name.as_str(), 1, CalcError::Limit(
+                "Scripts are nested more than 32 deep".into())))];
+
+            assert_eq!(expected, events);
+        }
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_substitute__275() {
+        let variables = vec![("k".to_owned(), 1.0), ("i".to_owned(), 2.0), ("k".to_owned(), -3.0)];
+
+        assert_eq!("power -3 * 2+2j # k2", substitute("power k * i+2j # k2", &variables));
+        assert_eq!("(-3)²", substitute("k²", &variables));
+    }
+
+    #[test]
+    fn test_negative_variable_squared__374() {
+        let mut session = Session::new(AppState::default());
+
+        let events = run_all(&mut session, "for k in -3..-2\n  k² + 0\nend");
+
+        assert!(events.iter().all(|e| match *e { Event::Error(_) => false, _ => true }), "{:?}", events);
+        let result = session.state().result().and_then(Value::as_scalar).cloned().unwrap();
+        assert!((result.real() - 9.0).abs() < 1e-9 && result.imaginary().abs() < 1e-9, "{:?}", result);
+    }
+
+    #[test]
+    fn test_hash_inside_strings__375() {
+        let mut session = Session::new(AppState::default());
+
+        assert_eq!(Ok(vec![Statement::Line(1, "\"a#b\"".into())]), parse("\"a#b\" # note"));
+        assert_eq!(Vec::<Event>::new(), run_all(&mut session, "\"a#b\""));
+        assert_eq!(Some(&Value::Text("a#b".into())), session.state().result());
+    }
+}
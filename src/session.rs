@@ -0,0 +1,294 @@
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use state::AppState;
+use history::History;
+use command::{Command, parse_line_with};
+use event::Event;
+use error::CalcError;
+use log::{Logger, LogFormat, provenance};
+use repl::eval_with_limits;
+use limits::{Limits, Budget};
+use input::LineSource;
+use script;
+
+// Deep enough for any sensible include tree, shallow enough for the 2 MB stacks of daemon threads
+const MAX_SOURCE_DEPTH: usize = 32;
+
+pub struct Session {
+    state: AppState,
+    history: History,
+    logger: Logger,
+    limits: Limits,
+    interrupt: Option<&'static AtomicBool>,
+    sourcing: usize
+}
+
+impl Session {
+    pub fn new(state: AppState) -> Session {
+        Session {
+            state: state,
+            history: History::default(),
+            logger: Logger::new(Box::new(io::sink()), LogFormat::Plain, false),
+            limits: Limits::default(),
+            interrupt: None,
+            sourcing: 0
+        }
+    }
+
+    pub fn with_history(mut self, history: History) -> Session {
+        self.history = history;
+        self
+    }
+
+    pub fn with_logger(mut self, logger: Logger) -> Session {
+        self.logger = logger;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Session {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_interrupt(mut self, interrupt: &'static AtomicBool) -> Session {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    pub fn reset(&mut self, state: AppState) {
+        self.state = state;
+        self.history = History::default();
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn budget(&self) -> Budget<'static> {
+        Budget::new(self.limits, self.interrupt)
+    }
+
+    fn source(&mut self, path: &str, budget: &mut Budget, out: &mut Vec<Event>) -> bool {
+        if self.sourcing >= MAX_SOURCE_DEPTH {
+            out.push(Event::Error(CalcError::Limit(format!("Scripts are nested more than {} deep", MAX_SOURCE_DEPTH))));
+            return false;
+        }
+        let path = Path::new(path);
+        let input = match LineSource::file(path) {
+            Ok(input) => input.with_limits(self.limits),
+            Err(e) => {
+                out.push(Event::Error(CalcError::Io(format!("Cannot read script {}: {}", path.display(), e))));
+                return false;
+            }
+        };
+        self.sourcing += 1;
+        let ok = script::run(self, input, &path.display().to_string(), budget, out);
+        self.sourcing -= 1;
+        ok
+    }
+
+    fn step(&mut self, cmd: Command, budget: &mut Budget, out: &mut Vec<Event>) -> bool {
+        if let Err(e) = budget.spend() {
+            out.push(Event::Error(e));
+            return false;
+        }
+        if cmd == Command::Exit {
+            out.push(Event::Exit);
+            return false;
+        }
+        if let Command::Source(path) = cmd {
+            return self.source(&path, budget, out);
+        }
+        let origin = provenance(&self.state, &cmd);
+        let state = mem::replace(&mut self.state, AppState::default());
+        let history = mem::replace(&mut self.history, History::default());
+        let (history, result) = eval_with_limits(history, state, cmd, &self.limits, out);
+        self.history = history;
+        match result {
+            Ok(s) => {
+                self.state = self.logger.log(origin, s);
+                true
+            },
+            Err((s, e)) => {
+                self.state = s;
+                out.push(Event::Error(e));
+                false
+            }
+        }
+    }
+
+    pub fn eval(&mut self, cmd: Command) -> Vec<Event> {
+        let mut out = Vec::new();
+        let mut budget = Budget::new(self.limits, self.interrupt);
+        self.step(cmd, &mut budget, &mut out);
+        out
+    }
+
+    fn run(&mut self, cmds: Vec<Command>, budget: &mut Budget, out: &mut Vec<Event>) -> bool {
+        cmds.into_iter().all(|cmd| self.step(cmd, budget, out))
+    }
+
+    pub fn eval_commands(&mut self, cmds: Vec<Command>) -> Vec<Event> {
+        let mut out = Vec::new();
+        let mut budget = Budget::new(self.limits, self.interrupt);
+        self.run(cmds, &mut budget, &mut out);
+        out
+    }
+
+    // Evaluates a line as part of a larger evaluation, such as a script; false means it stopped early
+    pub fn eval_line_with(&mut self, line: &str, budget: &mut Budget, out: &mut Vec<Event>) -> bool {
+        let cmds = self.limits.check_input(line).and_then(|_| parse_line_with(line, &self.state.settings, self.limits.max_depth));
+        match cmds {
+            Ok(cmds) => self.run(cmds, budget, out),
+            Err(e) => {
+                out.push(Event::Error(e));
+                false
+            }
+        }
+    }
+
+    pub fn eval_line(&mut self, line: &str) -> Vec<Event> {
+        let mut out = Vec::new();
+        let mut budget = Budget::new(self.limits, self.interrupt);
+        self.eval_line_with(line, &mut budget, &mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use complex::Complex;
+    use complex::dialect::Dialect;
+    use state::Settings;
+    use locale::Locale;
+    use event::render;
+    use value::Value;
+
+    #[test]
+    fn test_session_results__201() {
+        let mut session = Session::new(AppState::default());
+        let expected = vec![Event::Result(Complex::new(3.0, 1.0).into())];
+
+        assert_eq!(expected, session.eval_line("1+1j + 2"));
+        assert_eq!(Some(&Complex::new(3.0, 1.0).into()), session.state().result());
+        assert_eq!(3, session.history().past().len());
+    }
+
+    #[test]
+    fn test_session_stops_at_errors__202__203() {
+        {
+            let mut session = Session::new(AppState::default());
+            let expected = vec![Event::Error(CalcError::MissingOperand("You must insert a number first".into()))];
+
+            assert_eq!(expected, session.eval_line("+ 1"));
+            assert_eq!(&AppState::default(), session.state());
+        }
+        {
+            let mut session = Session::new(AppState::default());
+            let expected = vec![Event::Real(2.0), Event::Exit];
+
+            assert_eq!(expected, session.eval_commands(vec![Command::Number(Complex::new(2.0, 0.0)), Command::Real,
+                                                            Command::Exit, Command::Clear]));
+            assert_eq!(Some(&Complex::new(2.0, 0.0).into()), session.state().result());
+        }
+    }
+
+    #[test]
+    fn test_session_messages__204() {
+        let mut session = Session::new(AppState::rpn(vec![], false));
+        let expected = vec![Event::Stack(vec![Complex::new(1.0, 0.0).into(), Complex::new(2.0, 0.0).into()]),
+                            Event::Message("[1]".into())];
+
+        session.eval_line("1 2");
+
+        assert_eq!(expected, session.eval_line("stack undo"));
+    }
+
+    #[test]
+    fn test_session_limits__238__239__240() {
+        let mut limits = Limits::default();
+        limits.max_input = 12;
+        limits.max_depth = 2;
+        limits.max_magnitude = 100.0;
+        let mut session = Session::new(AppState::default()).with_limits(limits);
+        {
+            let expected = vec![Event::Error(CalcError::Limit("The input is longer than 12 bytes".into()))];
+
+            assert_eq!(expected, session.eval_line("1 + 2 + 3 + 4"));
+        }
+        {
+            let expected = vec![Event::Error(CalcError::Limit("The input nests more than 2 levels".into()))];
+
+            assert_eq!(expected, session.eval_line("map map map"));
+        }
+        {
+            let expected = vec![Event::Error(CalcError::Overflow("The result exceeds the magnitude limit of 100".into()))];
+
+            session.eval_line("50");
+            assert_eq!(expected, session.eval_line("power 2"));
+            assert_eq!(Some(&Complex::new(50.0, 0.0).into()), session.state().result());
+        }
+    }
+
+    #[test]
+    fn test_session_interrupt__241() {
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+        let mut session = Session::new(AppState::default()).with_interrupt(&INTERRUPT);
+        session.eval_line("2");
+        let mut budget = Budget::new(Limits::default(), Some(&INTERRUPT));
+        let mut out = Vec::new();
+        INTERRUPT.store(true, ::std::sync::atomic::Ordering::SeqCst);
+
+        assert!(!session.step(Command::Number(Complex::new(3.0, 0.0)), &mut budget, &mut out));
+
+        assert_eq!(vec![Event::Error(CalcError::Cancelled("Interrupted".into()))], out);
+        assert_eq!(Some(&Complex::new(2.0, 0.0).into()), session.state().result());
+    }
+
+    #[test]
+    fn test_session_reads_dialect__323() {
+        let mut settings = Settings::default();
+        settings.dialect = Dialect::Matlab;
+        let mut session = Session::new(AppState::default().with_settings(settings));
+
+        assert_eq!(vec![Event::Result(Complex::new(4.0, 1.0).into())], session.eval_line("3+2i + 1-1i"));
+    }
+
+    #[test]
+    fn test_session_in_spanish__331() {
+        let mut settings = Settings::default();
+        settings.locale = Locale::Es;
+        let mut session = Session::new(AppState::default().with_settings(settings));
+        let events = session.eval_line("1,5+2,25j + 1");
+
+        assert_eq!(vec![Event::Result(Complex::new(2.5, 2.25).into())], events);
+        assert_eq!("2,500+2,250j", render(&events[0], &settings));
+        assert_eq!("Primero debes introducir un número", render(&session.eval_line("clear real")[0], &settings));
+    }
+
+    #[test]
+    fn test_roots_of_negative_numbers__356() {
+        let mut session = Session::new(AppState::default());
+        for line in &["√(−4) + 0", "-4 root 2"] {
+            match session.eval_line(line).as_slice() {
+                [Event::Result(Value::Scalar(num))] => {
+                    assert!(num.real().abs() < 1e-12, "{}: {:?}", line, num);
+                    assert!((num.imaginary() - 2.0).abs() < 1e-12, "{}: {:?}", line, num);
+                },
+                other => panic!("{}: unexpected {:?}", line, other)
+            }
+            session.eval_line("clear");
+        }
+    }
+}
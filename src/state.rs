@@ -1,24 +1,155 @@
-use complex::Complex;
+use value::Value;
+use format::Format;
+use complex::dialect::Dialect;
+use locale::Locale;
 use command::Command;
+use std::fmt::{Display, Formatter, Result};
+use std::f64::consts::PI;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    Algebraic,
+    Rpn
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            Mode::Algebraic => write!(f, "algebraic"),
+            Mode::Rpn => write!(f, "rpn"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Angle {
+    Radians,
+    Degrees,
+    Gradians,
+    Turns
+}
+
+impl Angle {
+    pub fn parse(unit: &str) -> Option<Angle> {
+        match unit.to_lowercase().as_str() {
+            "rad" => Some(Angle::Radians),
+            "deg" => Some(Angle::Degrees),
+            "grad" => Some(Angle::Gradians),
+            "turn" => Some(Angle::Turns),
+            _ => None
+        }
+    }
+
+    fn per_turn(self) -> f64 {
+        match self {
+            Angle::Radians => 2.0 * PI,
+            Angle::Degrees => 360.0,
+            Angle::Gradians => 400.0,
+            Angle::Turns => 1.0,
+        }
+    }
+
+    pub fn from_radians(self, radians: f64) -> f64 {
+        match self {
+            Angle::Radians => radians,
+            Angle::Degrees => radians.to_degrees(),
+            unit => radians * unit.per_turn() / (2.0 * PI),
+        }
+    }
+
+    pub fn to_radians(self, angle: f64) -> f64 {
+        match self {
+            Angle::Radians => angle,
+            Angle::Degrees => angle.to_radians(),
+            unit => angle * (2.0 * PI) / unit.per_turn(),
+        }
+    }
+}
+
+impl Display for Angle {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            Angle::Radians => write!(f, "rad"),
+            Angle::Degrees => write!(f, "deg"),
+            Angle::Gradians => write!(f, "grad"),
+            Angle::Turns => write!(f, "turn"),
+        }
+    }
+}
+
+pub const DEFAULT_PRECISION: usize = 3;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Settings {
+    pub precision: usize,
+    pub angle: Angle,
+    pub format: Format,
+    pub dialect: Dialect,
+    pub locale: Locale
+}
+
+impl Settings {
+    pub fn new(precision: usize, angle: Angle) -> Settings {
+        Settings {
+            precision: precision,
+            angle: angle,
+            format: Format::default(),
+            dialect: Dialect::Native,
+            locale: Locale::En
+        }
+    }
+
+    pub fn default() -> Settings {
+        Settings::new(DEFAULT_PRECISION, Angle::Radians)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct AppState {
-    pub number: Option<Complex>,
+    pub number: Option<Value>,
     pub pending_op: Option<Command>,
-    pub log: bool
+    pub log: bool,
+    pub mode: Mode,
+    pub stack: Vec<Value>,
+    pub settings: Settings
 }
 
 impl AppState {
-    pub fn new(complex: Option<Complex>, pending_op: Option<Command>, log: bool) -> AppState {
+    pub fn new(number: Option<Value>, pending_op: Option<Command>, log: bool) -> AppState {
         AppState {
-            number: complex,
+            number: number,
             pending_op: pending_op,
-            log: log
+            log: log,
+            mode: Mode::Algebraic,
+            stack: Vec::new(),
+            settings: Settings::default()
+        }
+    }
+
+    pub fn rpn(stack: Vec<Value>, log: bool) -> AppState {
+        AppState {
+            number: None,
+            pending_op: None,
+            log: log,
+            mode: Mode::Rpn,
+            stack: stack,
+            settings: Settings::default()
         }
     }
 
     pub fn default() -> AppState {
         AppState::new(None, None, false)
     }
-}
 
+    pub fn with_settings(mut self, settings: Settings) -> AppState {
+        self.settings = settings;
+        self
+    }
+
+    pub fn result(&self) -> Option<&Value> {
+        match self.mode {
+            Mode::Algebraic => self.number.as_ref(),
+            Mode::Rpn => self.stack.last(),
+        }
+    }
+}
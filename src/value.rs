@@ -0,0 +1,279 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use complex::Complex;
+use complex::parser::parse_from_string;
+use error::CalcError;
+use limits::Nesting;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Scalar(Complex),
+    List(Vec<Complex>),
+    // Rows of the same length
+    Matrix(Vec<Vec<Complex>>),
+    Bool(bool),
+    Text(String)
+}
+
+impl From<Complex> for Value {
+    fn from(num: Complex) -> Value {
+        Value::Scalar(num)
+    }
+}
+
+fn shape(rows: &[Vec<Complex>]) -> String {
+    format!("{}x{}", rows.len(), rows.first().map_or(0, |r| r.len()))
+}
+
+fn zip<F>(a: &[Complex], b: &[Complex], f: &F) -> Result<Vec<Complex>, CalcError>
+    where F: Fn(&Complex, &Complex) -> Result<Complex, CalcError> {
+    a.iter().zip(b).map(|(x, y)| f(x, y)).collect()
+}
+
+impl Value {
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Value::Scalar(_) => "number",
+            Value::List(_) => "list",
+            Value::Matrix(_) => "matrix",
+            Value::Bool(_) => "boolean",
+            Value::Text(_) => "string",
+        }
+    }
+
+    pub fn as_scalar(&self) -> Option<&Complex> {
+        match *self {
+            Value::Scalar(ref num) => Some(num),
+            _ => None
+        }
+    }
+
+    pub fn numbers(&self) -> Vec<&Complex> {
+        match *self {
+            Value::Scalar(ref num) => vec![num],
+            Value::List(ref items) => items.iter().collect(),
+            Value::Matrix(ref rows) => rows.iter().flat_map(|r| r.iter()).collect(),
+            Value::Bool(_) | Value::Text(_) => Vec::new(),
+        }
+    }
+
+    fn not_numeric(&self) -> CalcError {
+        CalcError::Domain(format!("Expecting numbers, not a {}", self.kind()))
+    }
+
+    pub fn map<F>(&self, f: F) -> Result<Value, CalcError>
+        where F: Fn(&Complex) -> Result<Complex, CalcError> {
+        match *self {
+            Value::Scalar(ref num) => f(num).map(Value::Scalar),
+            Value::List(ref items) => items.iter().map(&f).collect::<Result<_, _>>().map(Value::List),
+            Value::Matrix(ref rows) => rows.iter()
+                .map(|row| row.iter().map(&f).collect::<Result<_, _>>())
+                .collect::<Result<_, _>>()
+                .map(Value::Matrix),
+            Value::Bool(_) | Value::Text(_) => Err(self.not_numeric()),
+        }
+    }
+
+    // Applies f elementwise: numbers combine with every element, lists of a matrix's width with every row
+    pub fn broadcast<F>(&self, other: &Value, f: F) -> Result<Value, CalcError>
+        where F: Fn(&Complex, &Complex) -> Result<Complex, CalcError> {
+        match (self, other) {
+            (&Value::Scalar(ref a), _) => other.map(|b| f(a, b)),
+            (_, &Value::Scalar(ref b)) => self.map(|a| f(a, b)),
+            (&Value::List(ref a), &Value::List(ref b)) if a.len() == b.len() => zip(a, b, &f).map(Value::List),
+            (&Value::List(ref a), &Value::List(ref b)) => {
+                Err(CalcError::Domain(format!("The lists have different lengths: {} and {}", a.len(), b.len())))
+            },
+            (&Value::Matrix(ref a), &Value::Matrix(ref b)) if shape(a) == shape(b) => {
+                a.iter().zip(b).map(|(x, y)| zip(x, y, &f)).collect::<Result<_, _>>().map(Value::Matrix)
+            },
+            (&Value::Matrix(ref a), &Value::Matrix(ref b)) => {
+                Err(CalcError::Domain(format!("The matrices have different shapes: {} and {}", shape(a), shape(b))))
+            },
+            (&Value::Matrix(ref rows), &Value::List(ref row)) if rows.iter().all(|r| r.len() == row.len()) => {
+                rows.iter().map(|r| zip(r, row, &f)).collect::<Result<_, _>>().map(Value::Matrix)
+            },
+            (&Value::List(ref row), &Value::Matrix(ref rows)) if rows.iter().all(|r| r.len() == row.len()) => {
+                rows.iter().map(|r| zip(row, r, &f)).collect::<Result<_, _>>().map(Value::Matrix)
+            },
+            (&Value::Matrix(ref rows), &Value::List(ref row)) |
+            (&Value::List(ref row), &Value::Matrix(ref rows)) => {
+                Err(CalcError::Domain(format!("Cannot combine a {} matrix with a list of {}", shape(rows), row.len())))
+            },
+            (&Value::Bool(_), _) | (&Value::Text(_), _) => Err(self.not_numeric()),
+            (_, &Value::Bool(_)) | (_, &Value::Text(_)) => Err(other.not_numeric()),
+        }
+    }
+}
+
+fn number(item: &str) -> Result<Complex, String> {
+    parse_from_string(item.to_owned()).map_err(|_| format!("Expecting a number, found '{}'", item.trim()))
+}
+
+// Splits on the commas that are not nested inside brackets
+fn items(inner: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&inner[start..i]);
+                start = i + 1;
+            },
+            _ => ()
+        }
+    }
+    if !inner[start..].trim().is_empty() || !items.is_empty() {
+        items.push(&inner[start..]);
+    }
+    items
+}
+
+fn row(item: &str) -> Result<Vec<Complex>, String> {
+    let item = item.trim();
+    if item.starts_with('[') && item.ends_with(']') {
+        items(&item[1..item.len() - 1]).into_iter().map(number).collect()
+    } else {
+        Err(format!("Expecting a row like [1, 2], found '{}'", item))
+    }
+}
+
+// Literals: [1, 2+1j] is a list, [[1, 2], [3, 4]] a matrix, "..." a string and true or false a boolean;
+// brackets nest at most max_depth deep
+pub fn parse(text: &str, max_depth: usize) -> Result<Value, String> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("true") || text.eq_ignore_ascii_case("false") {
+        return Ok(Value::Bool(text.eq_ignore_ascii_case("true")));
+    }
+    if text.starts_with('"') {
+        return match text[1..].find('"') {
+            Some(end) if end + 2 == text.len() => Ok(Value::Text(text[1..end + 1].to_owned())),
+            _ => Err("Missing closing quote".into())
+        };
+    }
+    if !text.starts_with('[') {
+        return number(text).map(Value::Scalar);
+    }
+    let mut nesting = Nesting::new(max_depth);
+    for c in text.chars() {
+        match c {
+            '[' => nesting = nesting.enter()?,
+            ']' => nesting = nesting.leave(),
+            _ => ()
+        }
+    }
+    if !text.ends_with(']') {
+        return Err("Missing closing bracket".into());
+    }
+    let items = items(&text[1..text.len() - 1]);
+    if items.first().map_or(false, |i| i.trim().starts_with('[')) {
+        let rows = items.into_iter().map(row).collect::<Result<Vec<_>, _>>()?;
+        if rows.iter().any(|r| r.len() != rows[0].len()) {
+            return Err("Every row of a matrix must have the same length".into());
+        }
+        Ok(Value::Matrix(rows))
+    } else {
+        items.into_iter().map(number).collect::<Result<_, _>>().map(Value::List)
+    }
+}
+
+fn write_items<'a, I>(f: &mut Formatter, items: I) -> FmtResult where I: Iterator<Item = &'a Complex> {
+    write!(f, "[")?;
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        match f.precision() {
+            Some(p) => write!(f, "{:.*}", p, item)?,
+            None => write!(f, "{}", item)?
+        }
+    }
+    write!(f, "]")
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            Value::Scalar(ref num) => match f.precision() {
+                Some(p) => write!(f, "{:.*}", p, num),
+                None => write!(f, "{}", num)
+            },
+            Value::List(ref items) => write_items(f, items.iter()),
+            Value::Matrix(ref rows) => {
+                write!(f, "[")?;
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_items(f, row.iter())?;
+                }
+                write!(f, "]")
+            },
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Text(ref text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: &[f64]) -> Vec<Complex> {
+        items.iter().map(|&n| Complex::new(n, 0.0)).collect()
+    }
+
+    #[test]
+    fn test_parse_values__276__277() {
+        {
+            assert_eq!(Ok(Value::List(vec![Complex::new(1.0, 0.0), Complex::new(2.0, 1.0)])), parse("[1, 2+1j]", 128));
+            assert_eq!(Ok(Value::Matrix(vec![list(&[1.0, 2.0]), list(&[3.0, 4.0])])), parse("[[1, 2], [3,4]]", 128));
+            assert_eq!(Ok(Value::List(Vec::new())), parse("[]", 128));
+            assert_eq!(Ok(Value::Bool(true)), parse("True", 128));
+            assert_eq!(Ok(Value::Text("a b".into())), parse("\"a b\"", 128));
+        }
+        {
+            assert_eq!(Err("Expecting a number, found 'x'".into()), parse("[1, x]", 128));
+            assert_eq!(Err("Every row of a matrix must have the same length".into()), parse("[[1, 2], [3]]", 128));
+        }
+    }
+
+    #[test]
+    fn test_broadcast__278__279__280() {
+        let add = |a: &Complex, b: &Complex| Ok(a.clone() + b.clone());
+        {
+            let expected = Ok(Value::List(list(&[11.0, 12.0])));
+
+            assert_eq!(expected, Value::List(list(&[1.0, 2.0])).broadcast(&Complex::new(10.0, 0.0).into(), add));
+        }
+        {
+            let matrix = Value::Matrix(vec![list(&[1.0, 2.0]), list(&[3.0, 4.0])]);
+            let expected = Ok(Value::Matrix(vec![list(&[2.0, 4.0]), list(&[4.0, 6.0])]));
+
+            assert_eq!(expected, matrix.broadcast(&Value::List(list(&[1.0, 2.0])), add));
+        }
+        {
+            let expected = Err(CalcError::Domain("The lists have different lengths: 2 and 3".into()));
+
+            assert_eq!(expected, Value::List(list(&[1.0, 2.0])).broadcast(&Value::List(list(&[1.0, 2.0, 3.0])), add));
+            assert_eq!(Err(CalcError::Domain("Expecting numbers, not a boolean".into())),
+                       Value::Bool(true).broadcast(&Complex::new(1.0, 0.0).into(), add));
+        }
+    }
+
+    #[test]
+    fn test_display_values__281() {
+        let matrix = Value::Matrix(vec![vec![Complex::new(0.5, 0.0), Complex::new(1.0, 1.0)]]);
+
+        assert_eq!("[[0.50, 1.00+1.00j]]", format!("{:.2}", matrix));
+        assert_eq!("[1, 2]", format!("{}", Value::List(list(&[1.0, 2.0]))));
+    }
+
+    #[test]
+    fn test_parse_nesting_limit__366() {
+        assert_eq!(Err("The input nests more than 1 levels".into()), parse("[[1]]", 1));
+        assert_eq!(Ok(Value::Text("[[[".into())), parse("\"[[[\"", 1));
+    }
+}